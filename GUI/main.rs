@@ -7,7 +7,7 @@ use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Write;
 
-use chrono::Local;
+use chrono::{Local, TimeZone};
 use gtk::glib::{Receiver, Sender};
 use gtk::{glib, prelude::*, ApplicationWindow, Builder};
 
@@ -15,8 +15,11 @@ use cli::ui_notification_structs::peer_state::PeerState;
 use cli::ui_notification_structs::torrent_state::TorrentState;
 use cli::ui_notification_structs::ui_notification::UiNotification;
 use cli::{
-    client::client_side::ClientSide, config::Config, logging::logger::Logger,
-    server::server_side::ServerSide, utils,
+    client::client_side::{ClientCommand, ClientSide},
+    config::Config,
+    logging::{log_handle::LogHandle, logger::Logger},
+    server::server_side::ServerSide,
+    utils,
 };
 use std::{sync::mpsc, thread};
 
@@ -30,6 +33,7 @@ pub struct UiGrid {
     info_window: gtk::Window,
     general_info: gtk::Grid,
     statics_info: gtk::Grid,
+    command_sender: mpsc::Sender<ClientCommand>,
 }
 
 thread_local!(
@@ -309,14 +313,14 @@ fn build_accept_url_button(
     builder: Builder,
     text: gtk::Entry,
     dialog: gtk::Dialog,
-    sender: mpsc::Sender<String>,
+    sender: mpsc::Sender<ClientCommand>,
 ) -> Result<(), String> {
     let add_button: gtk::Button = builder
         .object("file_chooser_dialog_accept_button")
         .ok_or_else(|| "error".to_string())?;
     add_button.connect_clicked(move |_| {
         dialog.close();
-        let _ = sender.send(text.buffer().text());
+        let _ = sender.send(ClientCommand::Load(text.buffer().text()));
     });
     Ok(())
 }
@@ -336,7 +340,7 @@ fn build_torrent_grid(builder: Builder) -> Result<gtk::Grid, String> {
     Ok(grid)
 }
 
-pub fn update_torrent_grid_row(grid: gtk::Grid, name: String, text: f64) -> Result<(), String> {
+pub fn update_torrent_grid_row(grid: gtk::Grid, name: String, text: String) -> Result<(), String> {
     let children = grid.children();
     let mut found_row: bool = false;
     for child in children {
@@ -345,7 +349,7 @@ pub fn update_torrent_grid_row(grid: gtk::Grid, name: String, text: f64) -> Resu
                 Ok(casted_widget) => {
                     let label_name = casted_widget.widget_name().to_string();
                     if label_name == format!("{}_progress", name) {
-                        casted_widget.set_text(&format!("{text:.2}%"));
+                        casted_widget.set_text(&text);
                         found_row = true;
                         continue;
                     } else if found_row {
@@ -407,6 +411,26 @@ fn update_general_information_shown(
                 label.set_text(&torrent_state.get_metadata_downloaded().to_string());
             } else if label_name == "active_connections" {
                 label.set_text(active_peers.to_string().borrow());
+            } else if label_name == "comment" {
+                label.set_text(&torrent_state.get_metadata_comment());
+            } else if label_name == "created_by" {
+                label.set_text(&torrent_state.get_metadata_created_by());
+            } else if label_name == "creation_date" {
+                let creation_date = torrent_state
+                    .get_metadata_creation_date()
+                    .and_then(|date| Local.timestamp_opt(date, 0).single());
+                match creation_date {
+                    Some(date) => label.set_text(&date.format("%Y-%m-%d %H:%M:%S").to_string()),
+                    None => label.set_text(""),
+                }
+            } else if label_name == "source" {
+                label.set_text(&torrent_state.get_metadata_source());
+            } else if label_name == "private" {
+                if torrent_state.get_metadata_is_private() {
+                    label.set_text("Yes");
+                } else {
+                    label.set_text("No");
+                }
             }
         }
     }
@@ -439,6 +463,12 @@ fn update_statistics_shown(statistics_grid: gtk::Grid, peers: Vec<PeerState>, pi
         } else {
             msg.push_str("Not Interested");
         }
+        if peer.get_is_optimistic() {
+            msg.push_str(" (Optimistic Unchoke)");
+        }
+        if peer.get_is_snubbed() {
+            msg.push_str(" (Snubbed)");
+        }
         statistics_grid.attach(make_row_label(&msg).borrow(), 1, pos, 1, 1);
         pos += 1;
         msg = "Client State: ".to_string();
@@ -495,6 +525,64 @@ fn make_row_button(torrent: &str) -> gtk::Button {
     button
 }
 
+/// Makes a button that sends the command built by `command` for the torrent shown in the row.
+fn make_command_button(
+    label: &str,
+    torrent: &str,
+    command: fn([u8; 20]) -> ClientCommand,
+) -> gtk::Button {
+    let button = gtk::Button::with_label(label);
+    let name = torrent.to_string();
+
+    button.connect_clicked(move |_| {
+        let info_hash = get_torrent_from_hash_by_name(&name).get_metadata_info_hash();
+        let _ = get_command_sender().send(command(info_hash));
+    });
+    button.show();
+    button
+}
+
+/// Groups the info button with the buttons that pause, resume, remove and move in the queue the torrent
+/// of a row.
+fn make_row_buttons(torrent: &str) -> gtk::Box {
+    let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    buttons.add(&make_row_button(torrent));
+    buttons.add(&make_command_button("Pause", torrent, ClientCommand::Pause));
+    buttons.add(&make_command_button(
+        "Resume",
+        torrent,
+        ClientCommand::Resume,
+    ));
+    buttons.add(&make_command_button(
+        "Remove",
+        torrent,
+        ClientCommand::Remove,
+    ));
+    buttons.add(&make_command_button(
+        "Remove with data",
+        torrent,
+        ClientCommand::RemoveWithData,
+    ));
+    buttons.add(&make_command_button(
+        "Top",
+        torrent,
+        ClientCommand::QueueTop,
+    ));
+    buttons.add(&make_command_button("Up", torrent, ClientCommand::QueueUp));
+    buttons.add(&make_command_button(
+        "Down",
+        torrent,
+        ClientCommand::QueueDown,
+    ));
+    buttons.add(&make_command_button(
+        "Bottom",
+        torrent,
+        ClientCommand::QueueBottom,
+    ));
+    buttons.show();
+    buttons
+}
+
 fn add_row_to_grid(grid: gtk::Grid, mut name: &str) {
     match name.split('/').last() {
         Some(new_name) => name = new_name,
@@ -503,7 +591,7 @@ fn add_row_to_grid(grid: gtk::Grid, mut name: &str) {
     let file_name = make_row_label(name);
     file_name.set_wrap(true);
     let status = make_row_label(&format!("{}_progress", name));
-    let info_button = make_row_button(<&str>::clone(&name));
+    let info_button = make_row_buttons(<&str>::clone(&name));
     match grid.children().len().try_into() {
         Ok(mut top) => {
             top -= 2;
@@ -526,11 +614,12 @@ pub fn add_torrents_to_hash(states: Vec<TorrentState>) {
                     add_row_to_grid(aux_grid.clone(), &state.get_metadata_name());
                 }
                 let _ = aux_hash.insert(state.get_metadata_name(), state.clone());
-                let _ = update_torrent_grid_row(
-                    aux_grid,
-                    state.get_metadata_name(),
-                    state.get_completion_precentage(),
-                );
+                let progress = if state.is_checking() {
+                    format!("Checking {:.2}%", state.get_checking_percentage())
+                } else {
+                    format!("{:.2}%", state.get_completion_precentage())
+                };
+                let _ = update_torrent_grid_row(aux_grid, state.get_metadata_name(), progress);
             }
         }
         *torrents.borrow_mut() = Some(aux_hash);
@@ -589,6 +678,16 @@ pub fn get_info_window_handler() -> gtk::Window {
     aux
 }
 
+pub fn get_command_sender() -> mpsc::Sender<ClientCommand> {
+    let (mut aux, _) = mpsc::channel();
+    GLOBAL.with(|global| {
+        if let Some(ui) = &*global.borrow() {
+            aux = ui.command_sender.clone();
+        }
+    });
+    aux
+}
+
 pub fn get_statistics_grid() -> gtk::Grid {
     let mut aux: gtk::Grid = gtk::Grid::new();
     GLOBAL.with(|global| {
@@ -599,7 +698,7 @@ pub fn get_statistics_grid() -> gtk::Grid {
     aux
 }
 
-pub fn add_event_callbacks(ui: &UiGrid, sender: mpsc::Sender<String>) {
+pub fn add_event_callbacks(ui: &UiGrid, sender: mpsc::Sender<ClientCommand>) {
     let _ = build_add_torrent_button(ui.builder.clone(), ui.add_torrent_dialog.clone());
     let _ = build_cancel_button(
         ui.builder.clone(),
@@ -622,7 +721,7 @@ pub fn add_event_callbacks(ui: &UiGrid, sender: mpsc::Sender<String>) {
 
 fn build_ui(
     application: &gtk::Application,
-    sender: mpsc::Sender<String>,
+    sender: mpsc::Sender<ClientCommand>,
     config: Config,
 ) -> Result<UiGrid, String> {
     let glade1_src = include_str!("view1.glade");
@@ -645,17 +744,25 @@ fn build_ui(
         info_window,
         general_info,
         statics_info,
+        command_sender: sender.clone(),
     };
     add_event_callbacks(ui.borrow(), sender);
     ui.main_window.show();
     Ok(ui)
 }
 
-fn start_client_worker(receiver: mpsc::Receiver<String>, mut client: ClientSide) {
+/// Runs the commands of the UI on the client. Commands that fail are written to the log, as the UI only
+/// shows the state of the torrents.
+fn start_client_worker(
+    receiver: mpsc::Receiver<ClientCommand>,
+    mut client: ClientSide,
+    log_handle: LogHandle,
+) {
     let _ = thread::spawn(move || loop {
-        if let Ok(user_input) = receiver.recv() {
-            let torrent_path = vec![user_input];
-            let _ = client.load_torrents(torrent_path);
+        if let Ok(command) = receiver.recv() {
+            if let Err(e) = client.handle_command(command.clone()) {
+                let _ = log_handle.log(&format!("Command {command:?} failed: {e}"));
+            }
         };
     });
 }
@@ -666,7 +773,7 @@ fn main() -> Result<(), String> {
     let config_aux = Config::new()?;
     let config = Config::new()?;
     let logger = Logger::new(config.log_path())?;
-    let (path_tx, path_rx) = mpsc::channel::<String>();
+    let (path_tx, path_rx) = mpsc::channel::<ClientCommand>();
 
     thread::spawn(move || {
         let application = gtk::Application::new(
@@ -704,6 +811,14 @@ fn main() -> Result<(), String> {
     let mut server = ServerSide::new(client.get_id(), &config, logger.handle());
 
     server.set_peer_id(client.get_id());
+    server.set_disk_cache(client.disk_cache());
+    let (loaded_torrents, downloaded_torrents) = client.client_torrents();
+    server.set_client_torrents(loaded_torrents, downloaded_torrents);
+    server.set_rate_limits(client.rate_limits());
+    server.set_ban_list(client.ban_list());
+    server.set_ip_filter(client.ip_filter());
+    server.set_connected_peers(client.connected_peers());
+    server.set_connection_limit(client.connection_limit());
     server.set_ui_sender(Some(updater_tx));
     server.init(notif_tx.clone(), notif_rx)?;
 
@@ -713,7 +828,7 @@ fn main() -> Result<(), String> {
     );
 
     let mut download_pool = client.init(notif_tx)?;
-    start_client_worker(path_rx, client);
+    start_client_worker(path_rx, client, logger.handle());
     download_pool.wait_for_workers();
     Ok(())
 }
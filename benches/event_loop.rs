@@ -0,0 +1,155 @@
+//! Measures how fast a single event loop serves blocks to hundreds of loopback peers, which all run on a
+//! second event loop. Run it with `cargo bench --bench event_loop`.
+
+use cli::{
+    client::download::peer_protocol::{BLOCK_SIZE, REQUEST_SLOTS},
+    messages::{
+        message_types::{
+            block::Block,
+            handshake::{Handshake, HANDSHAKE_PSTR},
+            request::Request,
+        },
+        peer_message::PeerMessage,
+    },
+    network::{connection::Connection, event_loop::EventLoop, event_loop::PeerHandler},
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const PEERS: usize = 400;
+const BLOCKS_PER_PEER: u32 = 64;
+
+/// Unchokes every peer and answers its requests with blocks of zeros.
+struct Seeder;
+
+impl PeerHandler for Seeder {
+    fn on_handshake(
+        &mut self,
+        connection: &mut Connection,
+        handshake: Handshake,
+    ) -> Result<(), String> {
+        handshake.send(connection).map_err(|e| e.to_string())?;
+        PeerMessage::Unchoke
+            .send(connection)
+            .map_err(|e| e.to_string())
+    }
+
+    fn on_message(
+        &mut self,
+        connection: &mut Connection,
+        message: PeerMessage,
+    ) -> Result<(), String> {
+        if let PeerMessage::Request(request) = message {
+            let block = Block::new(
+                request.index(),
+                request.begin(),
+                vec![0; BLOCK_SIZE as usize],
+            );
+            block.send(connection).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Requests `BLOCKS_PER_PEER` blocks through every connection, keeping `REQUEST_SLOTS` of them in flight.
+#[derive(Default)]
+struct Leecher {
+    requested: HashMap<usize, u32>,
+    received: usize,
+}
+
+impl Leecher {
+    fn request_next(&mut self, connection: &mut Connection) -> Result<(), String> {
+        let requested = self.requested.entry(connection.id()).or_default();
+        if *requested < BLOCKS_PER_PEER {
+            let request = Request::new(0, *requested * BLOCK_SIZE, BLOCK_SIZE);
+            PeerMessage::Request(request)
+                .send(connection)
+                .map_err(|e| e.to_string())?;
+            *requested += 1;
+        }
+        Ok(())
+    }
+}
+
+impl PeerHandler for Leecher {
+    fn on_handshake(&mut self, _connection: &mut Connection, _: Handshake) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        connection: &mut Connection,
+        message: PeerMessage,
+    ) -> Result<(), String> {
+        match message {
+            PeerMessage::Unchoke => {
+                for _ in 0..REQUEST_SLOTS {
+                    self.request_next(connection)?;
+                }
+            }
+            PeerMessage::Block(_) => {
+                self.received += 1;
+                self.request_next(connection)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), String> {
+    let mut seeder = EventLoop::new(Seeder)?;
+    let address = seeder.listen("127.0.0.1:0")?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let seeder_stop = stop.clone();
+    let seeder_thread = thread::spawn(move || -> Result<(), String> {
+        while !seeder_stop.load(Ordering::Relaxed) {
+            seeder.turn(Duration::from_millis(10))?;
+        }
+        Ok(())
+    });
+
+    let mut leecher = EventLoop::new(Leecher::default())?;
+    let started = Instant::now();
+    for peer in 0..PEERS {
+        let mut peer_id = [0; 20];
+        peer_id[..8].copy_from_slice(&(peer as u64).to_be_bytes());
+        leecher.connect(
+            address,
+            Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], peer_id),
+        )?;
+    }
+
+    let expected = PEERS * BLOCKS_PER_PEER as usize;
+    while leecher.handler().received < expected {
+        if started.elapsed() > Duration::from_secs(120) {
+            return Err(format!(
+                "Only {} of {expected} blocks arrived",
+                leecher.handler().received
+            ));
+        }
+        leecher.turn(Duration::from_millis(10))?;
+    }
+    let elapsed = started.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    seeder_thread
+        .join()
+        .map_err(|_| "Error joining the seeder loop")??;
+
+    let megabytes = (expected * BLOCK_SIZE as usize) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{PEERS} peers downloaded {expected} blocks ({megabytes:.0} MiB) in {elapsed:.2?}: {:.0} blocks/s, {:.1} MiB/s",
+        expected as f64 / elapsed.as_secs_f64(),
+        megabytes / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use cli::create::TorrentCreator;
+use std::{env, path::PathBuf};
+
+const USAGE: &str = "Usage: make-torrent <path> -a <announce> [-a <backup announce>]... \
+[-o <output>] [-c <comment>] [-l <piece length in KiB>] [-p]";
+
+/// Options read from the command line.
+struct Options {
+    path: PathBuf,
+    announces: Vec<String>,
+    output: Option<PathBuf>,
+    comment: Option<String>,
+    piece_length: Option<usize>,
+    private: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut options = Options {
+        path: PathBuf::new(),
+        announces: Vec::new(),
+        output: None,
+        comment: None,
+        piece_length: None,
+        private: false,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+        match arg.as_str() {
+            "-a" | "--announce" => options.announces.push(value()?),
+            "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+            "-c" | "--comment" => options.comment = Some(value()?),
+            "-l" | "--piece-length" => {
+                let kib = value()?
+                    .parse::<usize>()
+                    .map_err(|e| format!("invalid piece length: {e}"))?;
+                options.piece_length = Some(kib * 1024);
+            }
+            "-p" | "--private" => options.private = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+
+    options.path = path.ok_or("missing path to share")?;
+    if options.announces.is_empty() {
+        return Err("missing announce URL".to_string());
+    }
+    Ok(options)
+}
+
+fn main() -> Result<(), String> {
+    let options = parse_args(env::args().skip(1)).map_err(|e| format!("{e}\n{USAGE}"))?;
+
+    let mut creator = TorrentCreator::new(&options.path, options.announces[0].clone());
+    for announce in &options.announces[1..] {
+        creator.add_tier(vec![announce.clone()]);
+    }
+    if let Some(comment) = options.comment {
+        creator.set_comment(comment);
+    }
+    if let Some(piece_length) = options.piece_length {
+        creator.set_piece_length(piece_length)?;
+    }
+    creator.set_private(options.private);
+
+    let output = options.output.unwrap_or_else(|| {
+        let mut output = options.path.clone().into_os_string();
+        output.push(".torrent");
+        PathBuf::from(output)
+    });
+    creator.write_to(&output)?;
+    println!("Created {}", output.display());
+    Ok(())
+}
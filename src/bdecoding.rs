@@ -1,5 +1,5 @@
 use crate::utils;
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 const INT_START: u8 = 0x69;
 const INT_END: u8 = 0x65;
@@ -21,7 +21,31 @@ pub enum BType {
     Integer(i64),
     String(Vec<u8>),
     List(Vec<BType>),
-    Dictionary(HashMap<String, BType>),
+    Dictionary(BDictionary),
+}
+
+/// Bencoded dictionary. Its keys are kept as raw bytes, since some of them (such as the pieces roots
+/// of the "piece layers" dictionary) are not valid UTF-8.
+#[derive(Default)]
+pub struct BDictionary(HashMap<Vec<u8>, BType>);
+
+impl BDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&BType> {
+        self.0.get(key.as_bytes())
+    }
+
+    pub fn insert(&mut self, key: impl Into<Vec<u8>>, value: BType) {
+        self.0.insert(key.into(), value);
+    }
+
+    /// Iterates over every key-value pair, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &BType)> {
+        self.0.iter().map(|(key, value)| (key.as_slice(), value))
+    }
 }
 
 impl BDecoder {
@@ -41,6 +65,29 @@ impl BDecoder {
         Ok(btype_structure)
     }
 
+    /// Returns the position of the raw bytes of the value associated to `key` in a bencoded dictionary (if present).
+    /// It is needed to hash the info dictionary exactly as it was encoded.
+    pub fn value_span(bytes: Vec<u8>, key: &str) -> Result<Option<Range<usize>>, String> {
+        let mut bdecoder = Self::new(bytes);
+        if bdecoder.bytes.first() != Some(&DICT_START) {
+            return Err("decoded file is not a bencoded dictionary".to_string());
+        }
+        bdecoder.pos += 1;
+
+        while !bdecoder.eof() && bdecoder.bytes[bdecoder.pos] != DICT_END {
+            let found = match bdecoder.decode_string()? {
+                BType::String(bytes) => bytes == key.as_bytes(),
+                _ => return Err("dictionary key is not a string".to_string()),
+            };
+            let value_start = bdecoder.pos;
+            bdecoder.decode_next()?;
+            if found {
+                return Ok(Some(value_start..bdecoder.pos));
+            }
+        }
+        Ok(None)
+    }
+
     /// Reads the current byte and builts a whole `BType`'d structure depending on what value was read.
     fn decode_next(&mut self) -> Result<BType, String> {
         match self.bytes[self.pos] {
@@ -119,13 +166,13 @@ impl BDecoder {
     /// Every existing key must be converted into a valid BType::String.
     /// Associated values can be of any BType variant.
     fn decode_dictionary(&mut self) -> Result<BType, String> {
-        let mut dict: HashMap<String, BType> = HashMap::new();
+        let mut dict = BDictionary::new();
         let mut key;
         self.pos += 1;
 
         while self.bytes[self.pos] != DICT_END {
             match self.decode_string()? {
-                BType::String(bytes) => key = utils::bytes_to_string(&bytes)?,
+                BType::String(bytes) => key = bytes,
                 _ => return Err("dictionary key is not a string".to_string()),
             }
 
@@ -218,7 +265,7 @@ mod tests {
         let input_bytes = b"de".to_vec();
 
         match BDecoder::bdecode(input_bytes)? {
-            BType::Dictionary(dict) => assert!(dict.is_empty()),
+            BType::Dictionary(dict) => assert!(dict.iter().next().is_none()),
             _ => return Err("expected to decode a BType::Dictionary".to_string()),
         }
         Ok(())
@@ -245,4 +292,29 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_dictionary_with_binary_keys() -> Result<(), String> {
+        let input_bytes = [b"d2:".to_vec(), vec![0xff, 0xfe], b"i1ee".to_vec()].concat();
+
+        match BDecoder::bdecode(input_bytes)? {
+            BType::Dictionary(dict) => {
+                let keys: Vec<&[u8]> = dict.iter().map(|(key, _)| key).collect();
+                assert_eq!(vec![&[0xff, 0xfe][..]], keys);
+            }
+            _ => return Err("expected to decode a BType::Dictionary".to_string()),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_span() -> Result<(), String> {
+        let input_bytes = b"d4:infod1:ai1ee5:otheri2ee".to_vec();
+        assert_eq!(
+            Some(7..15),
+            BDecoder::value_span(input_bytes.clone(), "info")?
+        );
+        assert_eq!(None, BDecoder::value_span(input_bytes, "missing")?);
+        Ok(())
+    }
 }
@@ -0,0 +1,73 @@
+use crate::bdecoding::BType;
+
+/// Builds the bencoded representation of a `BType`'d structure.
+/// Dictionary keys are written in sorted order, as the specification requires.
+pub fn bencode(value: &BType) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_into(value, &mut bytes);
+    bytes
+}
+
+fn encode_into(value: &BType, bytes: &mut Vec<u8>) {
+    match value {
+        BType::Integer(integer) => bytes.extend_from_slice(format!("i{integer}e").as_bytes()),
+        BType::String(string) => encode_string(string, bytes),
+        BType::List(list) => {
+            bytes.push(b'l');
+            list.iter().for_each(|value| encode_into(value, bytes));
+            bytes.push(b'e');
+        }
+        BType::Dictionary(dict) => {
+            let mut entries: Vec<(&[u8], &BType)> = dict.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+
+            bytes.push(b'd');
+            for (key, value) in entries {
+                encode_string(key, bytes);
+                encode_into(value, bytes);
+            }
+            bytes.push(b'e');
+        }
+    }
+}
+
+fn encode_string(string: &[u8], bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(format!("{}:", string.len()).as_bytes());
+    bytes.extend_from_slice(string);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bdecoding::{BDecoder, BDictionary};
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(b"i-42e".to_vec(), bencode(&BType::Integer(-42)));
+        assert_eq!(
+            b"4:spam".to_vec(),
+            bencode(&BType::String(b"spam".to_vec()))
+        );
+        assert_eq!(b"0:".to_vec(), bencode(&BType::String(Vec::new())));
+    }
+
+    #[test]
+    fn test_dictionary_keys_are_sorted() {
+        let mut dict = BDictionary::new();
+        dict.insert("zeta", BType::Integer(1));
+        dict.insert("alpha", BType::List(vec![BType::Integer(2)]));
+
+        assert_eq!(
+            b"d5:alphali2ee4:zetai1ee".to_vec(),
+            bencode(&BType::Dictionary(dict))
+        );
+    }
+
+    #[test]
+    fn test_encoded_bytes_decode_back() -> Result<(), String> {
+        let input_bytes = b"d4:listl3:abci7ee4:name3:bla6:nestedd1:ai0eee".to_vec();
+        let decoded = BDecoder::bdecode(input_bytes.clone())?;
+        assert_eq!(input_bytes, bencode(&decoded));
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    client::download::peer_manager::ConnectionLimit, logging::log_handle::LogHandle,
+    network::rate_limit::RateLimits,
+};
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use std::{
+    str::FromStr,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How often the scheduler checks whether another rule applies.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Limits that the client runs with while a rule of the schedule applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthProfile {
+    /// Upload and download limits of the whole client in bytes per second, where zero means unlimited.
+    pub upload_limit: usize,
+    pub download_limit: usize,
+    /// Amount of peer connections that can be open at once.
+    pub max_connections: usize,
+}
+
+/// A range of time in some days of the week, and the limits that apply during it. Ranges that end before
+/// they start go on past midnight, into the next day.
+///
+/// Rules are written as `<days> <start>-<end> <upload KiB/s> <download KiB/s> <max connections>`, where
+/// days are a comma separated list of days and ranges of days, such as `mon-fri 09:00-18:00 100 500 20`
+/// or `sat,sun 00:00-00:00 0 0 200`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleRule {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+    profile: BandwidthProfile,
+}
+
+impl ScheduleRule {
+    /// Returns true if the rule applies at the given local time.
+    pub fn applies_at(&self, when: NaiveDateTime) -> bool {
+        let day = when.weekday();
+        let time = when.time();
+        match self.start < self.end {
+            true => self.days.contains(&day) && self.start <= time && time < self.end,
+            false => {
+                (self.days.contains(&day) && time >= self.start)
+                    || (self.days.contains(&day.pred()) && time < self.end)
+            }
+        }
+    }
+
+    pub fn profile(&self) -> BandwidthProfile {
+        self.profile
+    }
+
+    fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+        let parse = |day: &str| {
+            Weekday::from_str(day).map_err(|_| format!("Invalid day in bandwidth schedule: {day}"))
+        };
+        let mut result = Vec::new();
+        for range in days.split(',') {
+            let (first, last) = match range.split_once('-') {
+                Some((first, last)) => (parse(first)?, parse(last)?),
+                None => (parse(range)?, parse(range)?),
+            };
+            let mut day = first;
+            result.push(day);
+            while day != last {
+                day = day.succ();
+                result.push(day);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl FromStr for ScheduleRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = rule.split_whitespace().collect();
+        let [days, times, upload, download, connections] = fields[..] else {
+            return Err(format!("Invalid bandwidth schedule rule: {rule}"));
+        };
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid time range in bandwidth schedule: {times}"))?;
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M").map_err(|e| format!("{e}: {time}"))
+        };
+        let parse_number = |number: &str| usize::from_str(number).map_err(|e| e.to_string());
+
+        Ok(Self {
+            days: Self::parse_days(days)?,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+            profile: BandwidthProfile {
+                upload_limit: parse_number(upload)? * 1024,
+                download_limit: parse_number(download)? * 1024,
+                max_connections: parse_number(connections)?,
+            },
+        })
+    }
+}
+
+/// Rules that change the limits of the client depending on the time of the week. The first rule that
+/// applies wins, and the default profile applies when none does.
+#[derive(Debug, Clone)]
+pub struct BandwidthSchedule {
+    rules: Vec<ScheduleRule>,
+    default_profile: BandwidthProfile,
+}
+
+impl BandwidthSchedule {
+    pub fn new(rules: Vec<ScheduleRule>, default_profile: BandwidthProfile) -> Self {
+        Self {
+            rules,
+            default_profile,
+        }
+    }
+
+    /// Returns the index of the rule that applies at the given local time, if any.
+    pub fn active_rule(&self, when: NaiveDateTime) -> Option<usize> {
+        self.rules.iter().position(|rule| rule.applies_at(when))
+    }
+
+    /// Returns the limits of a rule, or the default ones if there is no rule.
+    pub fn profile(&self, rule: Option<usize>) -> BandwidthProfile {
+        rule.and_then(|index| self.rules.get(index))
+            .map_or(self.default_profile, ScheduleRule::profile)
+    }
+
+    /// Starts a thread that applies the limits of the schedule to the running client whenever another rule
+    /// starts to apply. Limits that are changed by hand stay until the next switch.
+    pub fn spawn(
+        self,
+        rate_limits: RateLimits,
+        connection_limit: ConnectionLimit,
+        log_handle: LogHandle,
+    ) -> JoinHandle<Result<(), String>> {
+        thread::spawn(move || {
+            let mut current = None;
+            loop {
+                let rule = self.active_rule(Local::now().naive_local());
+                if current != Some(rule) {
+                    let profile = self.profile(rule);
+                    rate_limits.set_global_limits(profile.upload_limit, profile.download_limit);
+                    connection_limit.set_max(profile.max_connections);
+                    let name = match rule {
+                        Some(index) => format!("bandwidth schedule rule {}", index + 1),
+                        None => "default bandwidth limits".to_string(),
+                    };
+                    log_handle.log(&format!(
+                        "Switched to {name}: upload {} B/s, download {} B/s, {} connections",
+                        profile.upload_limit, profile.download_limit, profile.max_connections
+                    ))?;
+                    current = Some(rule);
+                }
+                thread::sleep(SCHEDULE_CHECK_INTERVAL);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, time: &str) -> Result<NaiveDateTime, String> {
+        // The 2nd of January of 2023 was a Monday.
+        let date = NaiveDate::from_ymd_opt(2023, 1, day).ok_or("Invalid date")?;
+        let time = NaiveTime::parse_from_str(time, "%H:%M").map_err(|e| e.to_string())?;
+        Ok(date.and_time(time))
+    }
+
+    #[test]
+    fn rules_are_parsed_from_days_times_and_limits() -> Result<(), String> {
+        let rule = ScheduleRule::from_str("fri-mon,wed 22:00-06:00 100 500 20")?;
+        assert_eq!(
+            vec![
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+                Weekday::Mon,
+                Weekday::Wed
+            ],
+            rule.days
+        );
+        assert_eq!(
+            BandwidthProfile {
+                upload_limit: 100 * 1024,
+                download_limit: 500 * 1024,
+                max_connections: 20,
+            },
+            rule.profile()
+        );
+        assert!(ScheduleRule::from_str("mon 09:00-18:00 100 500").is_err());
+        assert!(ScheduleRule::from_str("someday 09:00-18:00 100 500 20").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn first_rule_that_applies_wins_and_ranges_go_past_midnight() -> Result<(), String> {
+        let schedule = BandwidthSchedule::new(
+            vec![
+                ScheduleRule::from_str("mon-fri 09:00-18:00 100 500 20")?,
+                ScheduleRule::from_str("fri 22:00-02:00 0 0 200")?,
+                ScheduleRule::from_str("mon 00:00-00:00 1 1 1")?,
+            ],
+            BandwidthProfile {
+                upload_limit: 0,
+                download_limit: 0,
+                max_connections: 100,
+            },
+        );
+
+        assert_eq!(Some(0), schedule.active_rule(at(2, "09:00")?));
+        assert_eq!(Some(2), schedule.active_rule(at(2, "18:00")?));
+        assert_eq!(None, schedule.active_rule(at(3, "08:59")?));
+        assert_eq!(Some(1), schedule.active_rule(at(6, "23:00")?));
+        assert_eq!(Some(1), schedule.active_rule(at(7, "01:59")?));
+        assert_eq!(None, schedule.active_rule(at(7, "02:00")?));
+        assert_eq!(100, schedule.profile(None).max_connections);
+        Ok(())
+    }
+}
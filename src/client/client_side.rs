@@ -1,25 +1,72 @@
 use crate::{
-    client::torrent::Torrent, config::Config, logging::log_handle::LogHandle,
+    client::{torrent::Torrent, torrent_handle::TorrentStatus},
+    config::Config,
+    logging::log_handle::LogHandle,
+    network::{ban_list::BanList, ip_filter::IpFilter, rate_limit::RateLimits},
     server::server_side::Notification,
+    storage::disk_cache::DiskCache,
 };
 use rand::Rng;
 use std::{
+    collections::HashMap,
     env,
     io::Error,
     sync::{
         mpsc::{self, Receiver, Sender},
         {Arc, Mutex},
     },
+    thread,
+    time::{Duration, Instant},
     {fs, ops::Deref, path::Path},
 };
 
-use super::download::download_pool::DownloadPool;
+use super::bandwidth_schedule::BandwidthSchedule;
+use super::download::{
+    download_pool::DownloadPool,
+    peer_manager::{ConnectedPeers, ConnectionLimit, PeerManager},
+};
+use super::seeding_goals::{SeedingAction, SeedingGoals};
+use super::torrent_queue::TorrentQueue;
 
 const TORRENT_EXTENSION: &str = "torrent";
+const ANNOUNCE_PORT: u32 = 6881;
+/// How often completed torrents are checked against their seeding goals.
+const SEEDING_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the queue looks for torrents that stalled or finished, to let the next ones run.
+const QUEUE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 pub type TorrentSender = Sender<Torrent>;
 pub type TorrentReceiver = Arc<Mutex<Receiver<Torrent>>>;
 pub type DownloadedTorrents = Arc<Mutex<Vec<Torrent>>>;
+pub type LoadedTorrents = Arc<Mutex<HashMap<[u8; 20], Torrent>>>;
+
+/// Requests that the GUI, or any other control interface, can make to the client while it runs.
+/// Torrents are identified by their info hash.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientCommand {
+    /// Loads the .torrent file or directory of .torrent files at the given path.
+    Load(String),
+    Pause([u8; 20]),
+    Resume([u8; 20]),
+    Remove([u8; 20]),
+    /// Removes the torrent and deletes every file it downloaded.
+    RemoveWithData([u8; 20]),
+    /// Sets the upload and download limits of the whole client, in bytes per second. Zero means unlimited.
+    SetGlobalRateLimits(usize, usize),
+    /// Sets the upload and download limits of a torrent, in bytes per second.
+    SetTorrentRateLimits([u8; 20], usize, usize),
+    /// Sets the upload and download limits of every peer connection, in bytes per second.
+    SetPeerRateLimits(usize, usize),
+    /// Sets the seeding goals of every torrent that has none of its own.
+    SetGlobalSeedingGoals(SeedingGoals),
+    /// Sets the seeding goals of a torrent, which replace the global ones.
+    SetTorrentSeedingGoals([u8; 20], SeedingGoals),
+    /// Moves a torrent one position up in the queue, so it runs before the one that was above it.
+    QueueUp([u8; 20]),
+    QueueDown([u8; 20]),
+    QueueTop([u8; 20]),
+    QueueBottom([u8; 20]),
+}
 
 #[derive(Clone)]
 pub struct ClientSide {
@@ -28,7 +75,16 @@ pub struct ClientSide {
     torrent_tx: TorrentSender,
     torrent_rx: TorrentReceiver,
     downloaded_torrents: DownloadedTorrents,
+    loaded_torrents: LoadedTorrents,
     log_handle: LogHandle,
+    notif_tx: Option<Sender<Notification>>,
+    disk_cache: DiskCache,
+    peer_manager: PeerManager,
+    rate_limits: RateLimits,
+    seeding_goals: Arc<Mutex<SeedingGoals>>,
+    queue: Arc<Mutex<TorrentQueue>>,
+    ban_list: BanList,
+    ip_filter: IpFilter,
 }
 
 impl ClientSide {
@@ -41,7 +97,28 @@ impl ClientSide {
             torrent_tx,
             torrent_rx: Arc::new(Mutex::new(torrent_rx)),
             downloaded_torrents: Arc::new(Mutex::new(Vec::new())),
+            loaded_torrents: Arc::new(Mutex::new(HashMap::new())),
             log_handle,
+            notif_tx: None,
+            disk_cache: DiskCache::new(config.disk_cache_size()),
+            peer_manager: PeerManager::new(
+                config.connections_per_torrent(),
+                ConnectionLimit::new(config.max_peer_connections()),
+            ),
+            rate_limits: RateLimits::new(
+                config.global_rate_limits(),
+                config.torrent_rate_limits(),
+                config.peer_rate_limits(),
+            ),
+            seeding_goals: Arc::new(Mutex::new(config.seeding_goals())),
+            queue: Arc::new(Mutex::new(TorrentQueue::new(
+                config.max_active_downloads(),
+                config.max_active_seeds(),
+                config.stall_time(),
+                config.session_state_path(),
+            ))),
+            ban_list: BanList::default(),
+            ip_filter: IpFilter::new(config.ip_filter_path()),
         }
     }
 
@@ -59,6 +136,47 @@ impl ClientSide {
         self.id
     }
 
+    /// Returns the torrents that were loaded, along with the ones that finished downloading, so the server
+    /// side can download them from the peers that connect to the client.
+    pub fn client_torrents(&self) -> (LoadedTorrents, DownloadedTorrents) {
+        (
+            self.loaded_torrents.clone(),
+            self.downloaded_torrents.clone(),
+        )
+    }
+
+    /// Returns the rate limiters that every transfer of the client goes through.
+    pub fn rate_limits(&self) -> RateLimits {
+        self.rate_limits.clone()
+    }
+
+    /// Returns the peers that were banned for misbehaving, which the server side does not accept either.
+    pub fn ban_list(&self) -> BanList {
+        self.ban_list.clone()
+    }
+
+    /// Returns the peers that the client is connected to, which the server side does not connect to twice.
+    pub fn connected_peers(&self) -> ConnectedPeers {
+        self.peer_manager.connected_peers().clone()
+    }
+
+    /// Returns the limit of open peer connections, which connections that the server side accepts count
+    /// against too.
+    pub fn connection_limit(&self) -> ConnectionLimit {
+        self.peer_manager.limit().clone()
+    }
+
+    /// Returns the blocklist of the config, which the server side applies to incoming peers too.
+    pub fn ip_filter(&self) -> IpFilter {
+        self.ip_filter.clone()
+    }
+
+    pub fn disk_cache(&self) -> DiskCache {
+        self.disk_cache.clone()
+    }
+
+    /// Loads torrents into the queue, which starts as many of them as its limits allow. Torrents that fail to
+    /// load are logged and skipped, so they do not keep the rest from loading.
     pub fn load_torrents<A>(&mut self, paths: A) -> Result<(), String>
     where
         A: IntoIterator<Item = String>,
@@ -68,17 +186,26 @@ impl ClientSide {
             if path.is_dir() {
                 self.load_from_dir(path)?
             } else if path.is_file() {
-                self.load_from_file(path)?
+                self.load_or_skip(path)?
             }
         }
-        Ok(())
+        self.update_queue(Instant::now())
     }
 
     fn load_from_dir(&mut self, dir: &Path) -> Result<(), String> {
         let err = |e: Error| e.to_string();
         for entry in fs::read_dir(dir).map_err(err)? {
             let path = entry.map_err(err)?.path();
-            self.load_from_file(path)?;
+            self.load_or_skip(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Loads a torrent file, logging the error instead of returning it.
+    fn load_or_skip(&mut self, file: &Path) -> Result<(), String> {
+        if let Err(e) = self.load_from_file(file) {
+            self.log_handle
+                .log(&format!("Skipped torrent {}: {e}", file.display()))?;
         }
         Ok(())
     }
@@ -91,14 +218,320 @@ impl ClientSide {
             if extension == TORRENT_EXTENSION {
                 let mut torrent = Torrent::from(file)?;
                 torrent.save_in(self.config.download_path());
-                self.torrent_tx.send(torrent).map_err(|e| e.to_string())?
+                torrent.set_disk_cache(self.disk_cache.clone());
+                torrent.set_peer_manager(self.peer_manager.clone());
+                torrent.set_rate_limits(self.rate_limits.clone());
+                torrent.set_ban_list(self.ban_list.clone());
+                torrent.set_ip_filter(self.ip_filter.clone());
+                if let (true, Some(notif_tx)) = (torrent.has_data_on_disk(), &self.notif_tx) {
+                    torrent.recheck(notif_tx.clone(), &self.log_handle)?;
+                }
+                torrent.allocate_files(self.config.allocation_mode())?;
+                torrent.handle().set_status(TorrentStatus::Queued)?;
+                self.loaded_torrents
+                    .lock()
+                    .map_err(|e| e.to_string())?
+                    .insert(torrent.info_hash(), torrent.clone());
+                self.queue
+                    .lock()
+                    .map_err(|e| e.to_string())?
+                    .add(torrent.info_hash())?;
             }
         }
         Ok(())
     }
 
+    pub fn handle_command(&mut self, command: ClientCommand) -> Result<(), String> {
+        match command {
+            ClientCommand::Load(path) => self.load_torrents(vec![path]),
+            ClientCommand::Pause(info_hash) => self.pause(info_hash),
+            ClientCommand::Resume(info_hash) => self.resume(info_hash),
+            ClientCommand::Remove(info_hash) => self.remove(info_hash, false),
+            ClientCommand::RemoveWithData(info_hash) => self.remove(info_hash, true),
+            ClientCommand::SetGlobalRateLimits(upload, download) => {
+                self.rate_limits.set_global_limits(upload, download);
+                Ok(())
+            }
+            ClientCommand::SetTorrentRateLimits(info_hash, upload, download) => self
+                .rate_limits
+                .set_torrent_limits(info_hash, upload, download),
+            ClientCommand::SetPeerRateLimits(upload, download) => {
+                self.rate_limits.set_peer_limits(upload, download);
+                Ok(())
+            }
+            ClientCommand::SetGlobalSeedingGoals(goals) => {
+                *self.seeding_goals.lock().map_err(|e| e.to_string())? = goals;
+                Ok(())
+            }
+            ClientCommand::SetTorrentSeedingGoals(info_hash, goals) => self
+                .loaded_torrent(info_hash)?
+                .handle()
+                .set_seeding_goals(goals),
+            ClientCommand::QueueUp(info_hash) => {
+                self.move_in_queue(info_hash, TorrentQueue::move_up)
+            }
+            ClientCommand::QueueDown(info_hash) => {
+                self.move_in_queue(info_hash, TorrentQueue::move_down)
+            }
+            ClientCommand::QueueTop(info_hash) => {
+                self.move_in_queue(info_hash, TorrentQueue::move_to_top)
+            }
+            ClientCommand::QueueBottom(info_hash) => {
+                self.move_in_queue(info_hash, TorrentQueue::move_to_bottom)
+            }
+        }
+    }
+
+    fn loaded_torrent(&self, info_hash: [u8; 20]) -> Result<Torrent, String> {
+        self.loaded_torrents
+            .lock()
+            .map_err(|e| e.to_string())?
+            .get(&info_hash)
+            .cloned()
+            .ok_or_else(|| format!("Torrent {info_hash:?} is not loaded"))
+    }
+
+    /// Stops downloading and serving a torrent, keeping its data so it can be resumed later. Paused
+    /// torrents keep their position in the queue, but do not run until they are resumed.
+    pub fn pause(&self, info_hash: [u8; 20]) -> Result<(), String> {
+        let torrent = self.loaded_torrent(info_hash)?;
+        let status = torrent.handle().status()?;
+        if status != TorrentStatus::Active && status != TorrentStatus::Queued {
+            return Ok(());
+        }
+        torrent.handle().set_status(TorrentStatus::Paused)?;
+        if status == TorrentStatus::Active {
+            self.stop(&torrent)?;
+        }
+        self.log_handle
+            .log(&format!("Paused torrent {info_hash:?}"))?;
+        self.update_queue(Instant::now())
+    }
+
+    /// Puts a paused torrent back in the queue, which runs it again once there is room for it.
+    pub fn resume(&self, info_hash: [u8; 20]) -> Result<(), String> {
+        let torrent = self.loaded_torrent(info_hash)?;
+        if torrent.handle().status()? != TorrentStatus::Paused {
+            return Ok(());
+        }
+        torrent.handle().set_status(TorrentStatus::Queued)?;
+        self.log_handle
+            .log(&format!("Resumed torrent {info_hash:?}"))?;
+        self.update_queue(Instant::now())
+    }
+
+    /// Downloads and serves a torrent that was waiting in the queue. A torrent that was stopped so recently
+    /// that the workers still have it is not sent again, as they pick its new status up.
+    fn start(&self, torrent: Torrent) -> Result<(), String> {
+        torrent.handle().set_status(TorrentStatus::Active)?;
+        if let Some(notif_tx) = &self.notif_tx {
+            torrent.notify_downloaded(notif_tx)?;
+        }
+        let info_hash = torrent.info_hash();
+        if torrent.handle().schedule()? {
+            self.torrent_tx.send(torrent).map_err(|e| e.to_string())?;
+        }
+        self.log_handle
+            .log(&format!("Started torrent {info_hash:?}"))
+    }
+
+    /// Sends a running torrent back to the queue, to make room for the ones before it.
+    fn enqueue(&self, torrent: Torrent) -> Result<(), String> {
+        torrent.handle().set_status(TorrentStatus::Queued)?;
+        self.stop(&torrent)?;
+        self.log_handle
+            .log(&format!("Queued torrent {:?}", torrent.info_hash()))
+    }
+
+    fn move_in_queue(
+        &self,
+        info_hash: [u8; 20],
+        move_torrent: fn(&mut TorrentQueue, [u8; 20]) -> Result<(), String>,
+    ) -> Result<(), String> {
+        move_torrent(
+            &mut *self.queue.lock().map_err(|e| e.to_string())?,
+            info_hash,
+        )?;
+        self.update_queue(Instant::now())
+    }
+
+    /// Starts the queued torrents that there is room for, and queues the running ones that went over the
+    /// limits, such as when a torrent is moved before them or when a download becomes a seed.
+    pub fn update_queue(&self, now: Instant) -> Result<(), String> {
+        let loaded_torrents = self
+            .loaded_torrents
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clone();
+        let mut queue = self.queue.lock().map_err(|e| e.to_string())?;
+        let mut entries = Vec::new();
+        for info_hash in queue.order() {
+            let Some(torrent) = loaded_torrents.get(info_hash) else {
+                continue;
+            };
+            if let TorrentStatus::Active | TorrentStatus::Queued = torrent.handle().status()? {
+                entries.push(torrent.queue_entry()?);
+            }
+        }
+        let changes = queue.update(&entries, now);
+        drop(queue);
+
+        for info_hash in changes.to_queue {
+            if let Some(torrent) = loaded_torrents.get(&info_hash) {
+                self.enqueue(torrent.clone())?;
+            }
+        }
+        for info_hash in changes.to_start {
+            if let Some(torrent) = loaded_torrents.get(&info_hash) {
+                self.start(torrent.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts updating the queue as torrents stall or finish.
+    fn init_queue(&self) {
+        let client = self.clone();
+        let _queue_thread = thread::spawn(move || -> Result<(), String> {
+            loop {
+                thread::sleep(QUEUE_CHECK_INTERVAL);
+                client.update_queue(Instant::now())?;
+            }
+        });
+    }
+
+    /// Stops a torrent for good and forgets about it. Its files are deleted too if `delete_data` is set.
+    pub fn remove(&self, info_hash: [u8; 20], delete_data: bool) -> Result<(), String> {
+        let torrent = self
+            .loaded_torrents
+            .lock()
+            .map_err(|e| e.to_string())?
+            .remove(&info_hash)
+            .ok_or_else(|| format!("Torrent {info_hash:?} is not loaded"))?;
+        self.queue
+            .lock()
+            .map_err(|e| e.to_string())?
+            .remove(info_hash)?;
+        let was_active = torrent.handle().status()? == TorrentStatus::Active;
+        torrent.handle().set_status(TorrentStatus::Removed)?;
+        torrent.kill_workers(self.config.get_max_download_connections())?;
+        if was_active {
+            self.stop(&torrent)?;
+        }
+
+        self.downloaded_torrents
+            .lock()
+            .map_err(|e| e.to_string())?
+            .retain(|downloaded| *downloaded != torrent);
+        if delete_data {
+            torrent.delete_data()?;
+        }
+        self.log_handle
+            .log(&format!("Removed torrent {info_hash:?}"))
+    }
+
+    /// Writes the buffered pieces of a torrent that is no longer active, stops serving it and tells its tracker.
+    fn stop(&self, torrent: &Torrent) -> Result<(), String> {
+        torrent.flush_pieces()?;
+        if let Some(notif_tx) = &self.notif_tx {
+            notif_tx
+                .send(Notification::RemoveTorrent(torrent.info_hash()))
+                .map_err(|e| e.to_string())?;
+        }
+        if let Err(e) = torrent.announce_stopped(self.id, ANNOUNCE_PORT) {
+            self.log_handle
+                .log(&format!("Could not announce that the torrent stopped: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Takes the action of the seeding goals of every active torrent that reached one of them. Torrents
+    /// that are resumed after reaching a goal seed until they are stopped by hand.
+    fn check_seeding_goals(&self, now: Instant) -> Result<(), String> {
+        let torrents: Vec<Torrent> = self
+            .loaded_torrents
+            .lock()
+            .map_err(|e| e.to_string())?
+            .values()
+            .cloned()
+            .collect();
+        let global_goals = *self.seeding_goals.lock().map_err(|e| e.to_string())?;
+
+        for torrent in torrents {
+            let handle = torrent.handle();
+            if !handle.is_active() || handle.goal_reached()? {
+                continue;
+            }
+            let Some(stats) = torrent.seeding_stats(now)? else {
+                continue;
+            };
+            let goals = handle.seeding_goals()?.unwrap_or(global_goals);
+            if !goals.is_met(&stats) {
+                continue;
+            }
+
+            handle.set_goal_reached()?;
+            let info_hash = torrent.info_hash();
+            self.log_handle.log(&format!(
+                "Torrent {info_hash:?} reached its seeding goal with a ratio of {:.2} after {} minutes",
+                stats.ratio(),
+                stats.seed_time.as_secs() / 60
+            ))?;
+            match goals.action {
+                SeedingAction::Pause => self.pause(info_hash)?,
+                SeedingAction::Remove => self.remove(info_hash, false)?,
+                SeedingAction::RemoveWithData => self.remove(info_hash, true)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts checking the seeding goals of completed torrents.
+    fn init_seeding_goals(&self) {
+        let client = self.clone();
+        let _seeding_thread = thread::spawn(move || -> Result<(), String> {
+            loop {
+                thread::sleep(SEEDING_CHECK_INTERVAL);
+                client.check_seeding_goals(Instant::now())?;
+            }
+        });
+    }
+
+    /// Starts applying the bandwidth schedule of the config, if it has any rules.
+    fn init_bandwidth_schedule(&self) {
+        let rules = self.config.bandwidth_schedule();
+        if rules.is_empty() {
+            return;
+        }
+        let schedule = BandwidthSchedule::new(rules, self.config.default_bandwidth_profile());
+        let _schedule_thread = schedule.spawn(
+            self.rate_limits.clone(),
+            self.peer_manager.limit().clone(),
+            self.log_handle.clone(),
+        );
+    }
+
+    /// Loads the blocklist of the config, if it has one, and reloads it whenever it changes. The client
+    /// does not start if the blocklist can't be loaded, rather than connect to peers it should not.
+    fn init_ip_filter(&self) -> Result<(), String> {
+        let Some(path) = self.ip_filter.path() else {
+            return Ok(());
+        };
+        let ranges = self.ip_filter.load()?;
+        self.log_handle
+            .log(&format!("Loaded IP filter {path}: {ranges} ranges"))?;
+        let _filter_thread = self.ip_filter.clone().spawn(self.log_handle.clone());
+        Ok(())
+    }
+
     pub fn init(&mut self, notif_tx: Sender<Notification>) -> Result<DownloadPool, String> {
+        self.notif_tx = Some(notif_tx.clone());
+        self.init_ip_filter()?;
+        self.queue.lock().map_err(|e| e.to_string())?.load()?;
         self.load_torrents(env::args())?;
+        self.init_bandwidth_schedule();
+        self.init_seeding_goals();
+        self.init_queue();
 
         Ok(DownloadPool::new(
             self.id,
@@ -114,7 +547,11 @@ impl ClientSide {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::logging::logger::Logger;
+    use crate::{
+        client::{piece::Piece, single_file::SingleFile},
+        logging::logger::Logger,
+        utils,
+    };
 
     #[test]
     fn generate_correctly_sized_id() {
@@ -130,4 +567,32 @@ mod tests {
         assert_eq!(20, client.id.len());
         Ok(())
     }
+
+    #[test]
+    fn restarted_torrents_are_not_sent_to_the_workers_twice() -> Result<(), String> {
+        let config = Config::new()?;
+        let logger = Logger::new(config.log_path())?;
+        let client = ClientSide::new(&config, logger.handle());
+        let torrent = Torrent::new(
+            "restarted".to_string(),
+            "http://localhost/announce".to_string(),
+            4,
+            vec![Piece::new(0, 4, utils::sha1(b"abcd")?)],
+            vec![SingleFile::new(4, "restarted".to_string())],
+            [0; 20],
+        )?;
+        let torrent_rx = client.torrent_rx.lock().map_err(|e| e.to_string())?;
+
+        client.start(torrent.clone())?;
+        torrent.handle().set_status(TorrentStatus::Paused)?;
+        client.start(torrent.clone())?;
+        assert_eq!(1, torrent_rx.try_iter().count());
+
+        // Once a worker drops the stopped torrent, starting it sends it again.
+        torrent.handle().set_status(TorrentStatus::Paused)?;
+        assert!(!torrent.handle().keep_scheduled()?);
+        client.start(torrent)?;
+        assert_eq!(1, torrent_rx.try_iter().count());
+        Ok(())
+    }
 }
@@ -27,12 +27,18 @@ impl DownloadWorker {
             let mut torrent = torrent_rx.recv().map_err(|e| e.to_string())?;
             drop(torrent_rx);
 
+            // Paused torrents are sent again once they are resumed, and removed ones are dropped for good.
+            if !torrent.handle().keep_scheduled()? {
+                continue;
+            }
+
             let downloaded_torrents = downloaded_torrents_mutex
                 .lock()
                 .map_err(|e| e.to_string())?;
 
             if downloaded_torrents.iter().any(|torr| *torr == torrent) {
                 drop(downloaded_torrents);
+                torrent.handle().unschedule()?;
                 continue;
             }
 
@@ -45,7 +51,6 @@ impl DownloadWorker {
 
             torrent.download(
                 pieces_to_download,
-                client_id,
                 downloaded_torrents_mutex.clone(),
                 notif_tx.clone(),
                 &log_handle,
@@ -1,6 +1,9 @@
 use std::time::Instant;
 
-use crate::ui_notification_structs::{peer_state::PeerState, torrent_state::TorrentState};
+use crate::{
+    client::torrent_meta::TorrentMeta,
+    ui_notification_structs::{peer_state::PeerState, torrent_state::TorrentState},
+};
 
 use super::peer::Peer;
 
@@ -24,6 +27,8 @@ impl DownloadWorkerState {
             am_choked: true,
             is_interested: true,
             is_choked: true,
+            is_optimistic: false,
+            is_snubbed: false,
             torrent_name,
             n_pieces,
             downloaded_pieces: 0,
@@ -31,6 +36,7 @@ impl DownloadWorkerState {
             total_size: 0,
             last_download_instant,
             total_peers,
+            meta: TorrentMeta::default(),
         }
     }
 
@@ -50,6 +56,13 @@ impl DownloadWorkerState {
         self.is_choked = choked;
     }
 
+    /// Records the choker's view of the peer: whether it holds the optimistic unchoke, and whether it
+    /// snubs the client.
+    pub fn set_choker_flags(&mut self, optimistic: bool, snubbed: bool) {
+        self.is_optimistic = optimistic;
+        self.is_snubbed = snubbed;
+    }
+
     pub fn is_choked(&self) -> bool {
         self.is_choked
     }
@@ -63,6 +76,9 @@ impl DownloadWorkerState {
     pub fn set_downloaded_pieces(&mut self, downloaded_pieces: usize) {
         self.downloaded_pieces = downloaded_pieces;
     }
+    pub fn set_meta(&mut self, meta: TorrentMeta) {
+        self.meta = meta;
+    }
     pub fn generate_torrent_state(
         &self,
         connections_qty: usize,
@@ -76,6 +92,7 @@ impl DownloadWorkerState {
         state.set_metadata_n_pieces(self.n_pieces as u32);
         state.set_metadata_name(self.torrent_name.clone());
         state.set_metadata_total_size(self.total_size as u32);
+        self.meta.fill_torrent_state(&mut state);
         peer_states
             .iter()
             .for_each(|peer_state| state.add_peer_state(peer_state.clone()));
@@ -91,6 +108,8 @@ impl DownloadWorkerState {
 
         peer_state.set_p_is_chocked(self.is_choked);
         peer_state.set_p_is_interested(self.is_interested);
+        peer_state.set_is_optimistic(self.is_optimistic);
+        peer_state.set_is_snubbed(self.is_snubbed);
         peer_state
     }
 }
@@ -106,6 +125,8 @@ pub struct DownloadWorkerState {
     pub am_choked: bool,
     pub is_interested: bool,
     pub is_choked: bool,
+    pub is_optimistic: bool,
+    pub is_snubbed: bool,
     pub torrent_name: String,
     pub n_pieces: usize,
     pub downloaded_pieces: usize,
@@ -113,4 +134,5 @@ pub struct DownloadWorkerState {
     pub total_size: usize,
     pub last_download_instant: Option<Instant>,
     pub total_peers: usize,
+    pub meta: TorrentMeta,
 }
@@ -2,4 +2,6 @@ pub mod download_pool;
 mod download_worker;
 pub mod download_worker_state;
 pub mod peer;
+pub mod peer_manager;
 pub mod peer_protocol;
+pub mod web_seed;
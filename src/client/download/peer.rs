@@ -1,19 +1,30 @@
 use crate::{
     client::{piece::Piece, torrent::Torrent},
-    logging::log_handle::LogHandle,
     messages::{
         message_types::{bitfield::Bitfield, interested::Interested, request::Request},
         peer_message::PeerMessage,
     },
+    network::{ban_list::Offense, event_loop::Timers, rate_limit::Bandwidth},
+    server::upload::upload_info::UploadInfo,
+    storage::disk_cache::DiskCache,
 };
 
 use super::{
     download_pool::DownloadedPieces,
-    peer_protocol::{self, ProtocolError},
+    peer_manager::{ConnectionPermit, PeerRegistration},
+    peer_protocol::{self, ProtocolError, MAX_REQUEST_LEN},
+    web_seed::WebSeed,
+};
+use std::{
+    io::Write,
+    net::{SocketAddr, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
 };
-use std::net::TcpStream;
 
-/// Stores information about each peer in the peer list that is provided by the tracker.
+/// Stores information about each peer in the peer list that is provided by the tracker, along with the state
+/// of the session with it. A session both downloads from the peer and uploads to it, whichever side opened
+/// the connection.
 #[derive(Debug)]
 pub struct Peer {
     id: Option<[u8; 20]>,
@@ -24,8 +35,22 @@ pub struct Peer {
     am_choked: bool,
     is_interested: bool,
     is_choked: bool,
-    connection: Option<TcpStream>,
-    last_request: Request,
+    /// Block requests that were sent and not answered yet, in the order they were sent.
+    requests: Vec<Request>,
+    /// When the last block arrived, or when the client started waiting for one.
+    last_block: Instant,
+    /// Bytes that were downloaded from the peer and uploaded to it through the current connection.
+    downloaded_bytes: usize,
+    uploaded_bytes: usize,
+    /// Counts the connection to the peer against the global connection limit while it is open.
+    permit: Option<ConnectionPermit>,
+    /// Keeps other connections to the same peer out while the connection is open.
+    registration: Option<PeerRegistration>,
+    /// Rate limiters of the peer itself, on top of the ones of its torrent and of the whole client.
+    bandwidth: Bandwidth,
+    timers: Timers,
+    swarm_hash: Option<[u8; 20]>,
+    web_seed: Option<WebSeed>,
 }
 
 impl PartialEq for Peer {
@@ -54,8 +79,16 @@ impl Clone for Peer {
             am_choked: self.am_choked,
             is_interested: self.is_interested,
             is_choked: self.is_choked,
-            connection: None,
-            last_request: Request::default(),
+            requests: Vec::new(),
+            last_block: Instant::now(),
+            downloaded_bytes: 0,
+            uploaded_bytes: 0,
+            permit: None,
+            registration: None,
+            bandwidth: self.bandwidth.clone(),
+            timers: self.timers,
+            swarm_hash: self.swarm_hash,
+            web_seed: self.web_seed.clone(),
         }
     }
 }
@@ -71,8 +104,16 @@ impl Default for Peer {
             am_choked: true,
             is_interested: false,
             is_choked: true,
-            connection: None,
-            last_request: Request::default(),
+            requests: Vec::new(),
+            last_block: Instant::now(),
+            downloaded_bytes: 0,
+            uploaded_bytes: 0,
+            permit: None,
+            registration: None,
+            bandwidth: Bandwidth::default(),
+            timers: Timers::default(),
+            swarm_hash: None,
+            web_seed: None,
         }
     }
 }
@@ -88,11 +129,92 @@ impl Peer {
             am_choked: true,
             is_interested: false,
             is_choked: true,
-            connection: None,
-            last_request: Request::default(),
+            requests: Vec::new(),
+            last_block: Instant::now(),
+            downloaded_bytes: 0,
+            uploaded_bytes: 0,
+            permit: None,
+            registration: None,
+            bandwidth: Bandwidth::default(),
+            timers: Timers::default(),
+            swarm_hash: None,
+            web_seed: None,
+        }
+    }
+
+    /// Creates the session of a peer that connected to the client, once its handshake arrived.
+    pub fn from_accepted(id: [u8; 20], address: SocketAddr, total_pieces: usize) -> Self {
+        let mut peer = Self::new(Some(id), address.ip().to_string(), address.port());
+        peer.bitfield.set_size(total_pieces);
+        peer
+    }
+
+    /// Creates a peer that downloads whole pieces from a web seed, instead of talking the peer protocol.
+    pub fn from_web_seed(web_seed: WebSeed) -> Self {
+        Self {
+            ip: web_seed.host(),
+            port: web_seed.port(),
+            web_seed: Some(web_seed),
+            ..Self::default()
         }
     }
 
+    /// Gives back what the connection to the peer held once it closed, so the peer can be connected to again.
+    pub fn disconnect(&mut self) {
+        self.permit = None;
+        self.registration = None;
+        self.is_choked = true;
+        self.downloaded_bytes = 0;
+        self.uploaded_bytes = 0;
+    }
+
+    /// Returns true if the peer is a web seed, which is downloaded from over HTTP by the download workers
+    /// instead of by the event loop.
+    pub fn is_web_seed(&self) -> bool {
+        self.web_seed.is_some()
+    }
+
+    /// Records the id that the handshake of a peer that the client connected to carried, and sizes its
+    /// bitfield for the torrent.
+    pub fn set_handshake(&mut self, id: [u8; 20], total_pieces: usize) {
+        self.id = Some(id);
+        self.bitfield.set_size(total_pieces);
+    }
+
+    pub fn set_permit(&mut self, permit: ConnectionPermit) {
+        self.permit = Some(permit);
+    }
+
+    /// Keeps the registration of the connection to the peer while the session lasts.
+    pub fn set_registration(&mut self, registration: PeerRegistration) {
+        self.registration = Some(registration);
+    }
+
+    pub fn set_bandwidth(&mut self, bandwidth: Bandwidth) {
+        self.bandwidth = bandwidth;
+    }
+
+    pub fn bandwidth(&self) -> &Bandwidth {
+        &self.bandwidth
+    }
+
+    pub fn set_timers(&mut self, timers: Timers) {
+        self.timers = timers;
+    }
+
+    /// Returns how long a web seed waits after failed requests before it is used again. Peers that talk the
+    /// peer protocol never wait.
+    pub fn backoff(&self) -> Duration {
+        self.web_seed
+            .as_ref()
+            .map_or(Duration::ZERO, WebSeed::backoff)
+    }
+
+    /// Returns true if the peer told the client that it has the piece.
+    pub fn has_piece(&self, piece_index: usize) -> bool {
+        piece_index < self.bitfield.total_pieces() && self.bitfield.contains(piece_index)
+    }
+
     pub fn id(&self) -> Option<[u8; 20]> {
         self.id
     }
@@ -109,96 +231,121 @@ impl Peer {
         format!("{}:{}", self.ip, self.port)
     }
 
-    fn open_connection(
-        &mut self,
-        client_id: [u8; 20],
-        torrent: &Torrent,
-        log_handle: LogHandle,
-    ) -> Result<TcpStream, ProtocolError> {
-        let mut stream =
-            TcpStream::connect(self.address()).map_err(|e| ProtocolError::Peer(e.to_string()))?;
-        peer_protocol::handle_handshakes(&mut stream, client_id, torrent.info_hash())?;
-        log_handle
-            .log(&format!("Handshaked with: {}", self.address()))
-            .map_err(ProtocolError::Peer)?;
-        self.bitfield.set_size(torrent.total_pieces());
-        Ok(stream)
+    /// Returns the address to connect to the peer at, or `None` if its address is not valid.
+    pub fn socket_address(&self) -> Option<SocketAddr> {
+        self.address().to_socket_addrs().ok()?.next()
     }
 
-    fn reuse_connection(
-        &mut self,
-        mut stream: TcpStream,
-        piece_index: usize,
-    ) -> Result<TcpStream, ProtocolError> {
-        if self.bitfield.contains(piece_index) {
-            if !self.am_interested {
-                let interested = Interested::new();
-                interested.send(&mut stream)?;
-                self.am_interested = true;
-            }
-            if !self.am_choked && self.am_interested {
-                self.last_request.send(&mut stream)?;
-            }
-        }
-        Ok(stream)
+    /// Makes the handshake use `swarm_hash` instead of the info hash of the torrent,
+    /// for peers that were found in the v2 swarm of a hybrid torrent.
+    pub fn set_swarm_hash(&mut self, swarm_hash: [u8; 20]) {
+        self.swarm_hash = Some(swarm_hash);
+    }
+
+    pub fn swarm_hash(&self) -> Option<[u8; 20]> {
+        self.swarm_hash
     }
 
-    pub fn download(
+    /// Starts downloading a piece through an open connection, telling the peer that the client is
+    /// interested if it did not already.
+    pub fn start_piece<W: Write>(
         &mut self,
-        piece: &mut Piece,
-        torrent: &Torrent,
-        client_id: [u8; 20],
-        log_handle: &LogHandle,
+        stream: &mut W,
+        piece: &Piece,
     ) -> Result<(), ProtocolError> {
-        self.last_request = piece.request_next_block();
+        // Blocks of the previous piece that are still on their way do not match any request, so they are ignored.
+        self.requests.clear();
+        self.last_block = Instant::now();
 
-        let mut stream = match self.connection.take() {
-            None => self.open_connection(client_id, torrent, log_handle.clone())?,
-            Some(stream) => self.reuse_connection(stream, piece.index())?,
-        };
-
-        match self.handle_messages(&mut stream, piece, torrent.downloaded()) {
-            Ok(()) => {
-                self.connection = Some(stream);
-                Ok(())
-            }
-            Err(ProtocolError::Piece(e)) => {
-                self.connection = Some(stream);
-                Err(ProtocolError::Piece(e))
-            }
-            Err(ProtocolError::Peer(e)) => Err(ProtocolError::Peer(e)),
+        if !self.am_interested {
+            let interested = Interested::new();
+            interested.send(stream)?;
+            self.am_interested = true;
+        }
+        if !self.am_choked {
+            peer_protocol::fill_request_slots(stream, piece, &mut self.requests)?;
         }
+        Ok(())
+    }
+
+    /// Downloads a whole piece from the web seed of the peer, within the rate limits of the seed, its torrent
+    /// and the whole client. Peers that talk the peer protocol are downloaded from by the event loop.
+    pub fn download(&mut self, piece: &mut Piece, torrent: &Torrent) -> Result<(), ProtocolError> {
+        let Some(web_seed) = &mut self.web_seed else {
+            return Err(ProtocolError::Peer(format!(
+                "Peer {} is not a web seed",
+                self.address()
+            )));
+        };
+        web_seed.download(piece, torrent)?;
+        let throttle = torrent
+            .throttle(&self.bandwidth)
+            .map_err(ProtocolError::Peer)?;
+        let wait = throttle
+            .transfer(0, piece.len(), Instant::now())
+            .map_err(ProtocolError::Peer)?;
+        thread::sleep(wait);
+        Ok(())
     }
 
-    fn handle_messages(
+    /// Handles a message of the peer in both roles of the session: requests of the peer are answered with
+    /// the pieces of `upload`, and the blocks of `piece` are requested from the peer if the client is
+    /// downloading one. Choking and interest are tracked on each side independently.
+    pub fn handle_message<W: Write>(
         &mut self,
-        stream: &mut TcpStream,
-        piece: &mut Piece,
-        downloaded_mutex: DownloadedPieces,
+        stream: &mut W,
+        message: PeerMessage,
+        piece: Option<&mut Piece>,
+        upload: Option<&UploadInfo>,
+        disk_cache: &DiskCache,
+        downloaded: DownloadedPieces,
     ) -> Result<(), ProtocolError> {
-        loop {
-            let message_bytes = peer_protocol::read_message_bytes(stream)?;
-            let message = PeerMessage::from(message_bytes)?;
-
-            match message {
-                PeerMessage::Choke => {
-                    peer_protocol::handle_choke(&mut self.last_request, &mut self.am_choked)
+        let was_interested = self.am_interested;
+        match message {
+            PeerMessage::Choke => {
+                if !self.am_choked {
+                    self.last_block = Instant::now();
                 }
-                PeerMessage::Unchoke => peer_protocol::handle_unchoke(
-                    stream,
-                    &mut self.last_request,
-                    &mut self.am_choked,
-                    self.am_interested,
-                )?,
-                PeerMessage::Have(have) => peer_protocol::handle_have(
+                peer_protocol::handle_choke(&mut self.requests, &mut self.am_choked)
+            }
+            PeerMessage::Unchoke => {
+                self.last_block = Instant::now();
+                match piece {
+                    Some(piece) => peer_protocol::handle_unchoke(
+                        stream,
+                        piece,
+                        &mut self.requests,
+                        &mut self.am_choked,
+                        self.am_interested,
+                    )?,
+                    None => self.am_choked = false,
+                }
+            }
+            PeerMessage::Interested => self.is_interested = true,
+            PeerMessage::NotInterested => self.is_interested = false,
+            PeerMessage::Have(have) if (have.index() as usize) < self.bitfield.total_pieces() => {
+                peer_protocol::handle_have(
                     stream,
                     have,
                     &mut self.bitfield,
                     &mut self.am_interested,
-                    downloaded_mutex.clone(),
-                )?,
-                PeerMessage::Bitfield(bitfield) => {
-                    self.bitfield = bitfield;
+                    downloaded,
+                )?
+            }
+            PeerMessage::Bitfield(bitfield) => {
+                // Bitfields have one bit per piece of the torrent, rounded up to whole bytes.
+                let expected = self.bitfield.total_pieces();
+                if expected > 0 && bitfield.total_pieces() != expected {
+                    return Err(ProtocolError::Offense(
+                        Offense::MalformedMessage,
+                        format!(
+                            "Bitfield for {} pieces does not fit a torrent of up to {expected}",
+                            bitfield.total_pieces()
+                        ),
+                    ));
+                }
+                self.bitfield = bitfield;
+                if let Some(piece) = piece {
                     peer_protocol::handle_bitfield(
                         stream,
                         &mut self.bitfield,
@@ -206,28 +353,74 @@ impl Peer {
                         &mut self.am_interested,
                     )?;
                 }
-                PeerMessage::Block(block) => {
-                    peer_protocol::handle_block(
+            }
+            PeerMessage::Request(request) => {
+                let out_of_range =
+                    upload.is_some_and(|upload| request.index() as usize >= upload.total_pieces());
+                if out_of_range || request.length() > MAX_REQUEST_LEN {
+                    return Err(ProtocolError::Offense(
+                        Offense::OutOfRangeRequest,
+                        format!(
+                            "Request of {} bytes of piece {} is out of range",
+                            request.length(),
+                            request.index()
+                        ),
+                    ));
+                }
+                // Requests that cannot be served are answered with a cancel, and the peer stays connected.
+                // Torrents that have nothing to upload yet cannot serve any.
+                let bitfield = upload.and_then(UploadInfo::bitfield);
+                let download_path = upload.map(UploadInfo::download_path).unwrap_or_default();
+                let served = peer_protocol::handle_request(
+                    stream,
+                    request,
+                    self.is_choked,
+                    &download_path,
+                    disk_cache,
+                    bitfield.as_ref(),
+                );
+                if served.is_ok() {
+                    self.uploaded_bytes += request.length() as usize;
+                }
+            }
+            PeerMessage::HashRequest(request) => {
+                let v2 = upload.and_then(UploadInfo::v2);
+                let _ = peer_protocol::handle_hash_request(stream, request, v2.as_deref());
+            }
+            PeerMessage::Block(block) => {
+                self.downloaded_bytes += block.len();
+                self.last_block = Instant::now();
+                if let Some(piece) = piece {
+                    let len = piece.len();
+                    let result = peer_protocol::handle_block(
                         stream,
                         block,
                         piece,
-                        &mut self.last_request,
+                        &mut self.requests,
                         self.am_choked,
-                    )?;
-
-                    if piece.is_full() {
-                        break;
+                    );
+                    if piece.len() > len {
+                        piece.add_contributor(&self.ip);
                     }
+                    result?;
                 }
-                PeerMessage::Cancel(cancel) => {
-                    return Err(ProtocolError::Piece(format!(
-                        "Canceled request of piece {} beginning at {}",
-                        cancel.index(),
-                        cancel.begin()
-                    )))
-                }
-                _ => continue,
             }
+            PeerMessage::Cancel(cancel)
+                if piece
+                    .as_deref()
+                    .is_some_and(|piece| piece.index() == cancel.index() as usize) =>
+            {
+                return Err(ProtocolError::Piece(format!(
+                    "Canceled request of piece {} beginning at {}",
+                    cancel.index(),
+                    cancel.begin()
+                )))
+            }
+            _ => {}
+        }
+        // Peers that keep the client choked are only snubbing it once it has been interested for a while.
+        if self.am_interested && !was_interested {
+            self.last_block = Instant::now();
         }
         Ok(())
     }
@@ -248,8 +441,39 @@ impl Peer {
         self.is_choked
     }
 
-    pub fn unchoke(&mut self) {
-        self.is_choked = false
+    /// Chokes or unchokes the peer, telling it only if that changes anything.
+    pub fn set_choked<W: Write>(
+        &mut self,
+        stream: &mut W,
+        choked: bool,
+    ) -> Result<(), ProtocolError> {
+        if self.is_choked != choked {
+            let message = match choked {
+                true => PeerMessage::Choke,
+                false => PeerMessage::Unchoke,
+            };
+            message.send(stream)?;
+            self.is_choked = choked;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the peer kept the client choked while it was interested, or did not send any of the
+    /// requested blocks after unchoking it, for the snub timeout.
+    pub fn is_snubbed(&self, now: Instant) -> bool {
+        let waiting = match self.am_choked {
+            true => self.am_interested,
+            false => !self.requests.is_empty(),
+        };
+        waiting && now.saturating_duration_since(self.last_block) >= self.timers.snub_timeout
+    }
+
+    pub fn downloaded_bytes(&self) -> usize {
+        self.downloaded_bytes
+    }
+
+    pub fn uploaded_bytes(&self) -> usize {
+        self.uploaded_bytes
     }
 
     pub fn am_interested(&self) -> bool {
@@ -259,3 +483,139 @@ impl Peer {
         self.am_choked
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::download::peer_protocol::BLOCK_SIZE,
+        messages::message_types::{block::Block, have::Have},
+    };
+
+    /// Splits the bytes that a session sent into the messages they encode.
+    fn sent_messages(mut bytes: &[u8]) -> Result<Vec<PeerMessage>, ProtocolError> {
+        let mut messages = Vec::new();
+        while let Some(prefix) = bytes.get(..4) {
+            let len = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+            messages.push(PeerMessage::from(bytes[4..(4 + len)].to_vec())?);
+            bytes = &bytes[(4 + len)..];
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn sessions_upload_and_download_through_the_same_connection() -> Result<(), ProtocolError> {
+        let disk_cache = DiskCache::new(1 << 20);
+        disk_cache
+            .write_piece("peer_session_test", 0, vec![7; 64])
+            .map_err(ProtocolError::Piece)?;
+        let mut upload = UploadInfo::new([0; 20], "peer_session_test".to_string(), 2);
+        upload
+            .add_piece_to_bitfield(0)
+            .map_err(ProtocolError::Piece)?;
+        let mut piece = Piece::new(1, 2 * BLOCK_SIZE as usize, [0; 20]);
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let mut peer = Peer::from_accepted([1; 20], address, 2);
+
+        let mut sent = Vec::new();
+        peer.set_choked(&mut sent, false)?;
+        let received = vec![
+            PeerMessage::Interested,
+            PeerMessage::Request(Request::new(0, 0, 16)),
+            PeerMessage::Have(Have::new(1)),
+            PeerMessage::Unchoke,
+        ];
+        for message in received {
+            peer.handle_message(
+                &mut sent,
+                message,
+                Some(&mut piece),
+                Some(&upload),
+                &disk_cache,
+                DownloadedPieces::default(),
+            )?;
+        }
+
+        assert!(peer.is_interested() && !peer.is_choked());
+        assert!(peer.am_interested() && !peer.am_choked());
+        assert!(peer.has_piece(1));
+        assert_eq!(16, peer.uploaded_bytes());
+        assert_eq!(
+            vec![
+                PeerMessage::Unchoke,
+                PeerMessage::Block(Block::new(0, 0, vec![7; 16])),
+                PeerMessage::Interested,
+                PeerMessage::Request(Request::new(1, 0, BLOCK_SIZE)),
+                PeerMessage::Request(Request::new(1, BLOCK_SIZE, BLOCK_SIZE)),
+            ],
+            sent_messages(&sent)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn peers_that_keep_the_client_choked_are_snubbing_it() -> Result<(), ProtocolError> {
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let mut peer = Peer::from_accepted([1; 20], address, 2);
+        let snub_timeout = Timers::default().snub_timeout;
+        assert!(!peer.is_snubbed(Instant::now() + snub_timeout));
+
+        let mut sent = Vec::new();
+        let disk_cache = DiskCache::new(0);
+        peer.handle_message(
+            &mut sent,
+            PeerMessage::Have(Have::new(1)),
+            None,
+            None,
+            &disk_cache,
+            DownloadedPieces::default(),
+        )?;
+        assert!(peer.am_interested() && peer.am_choked());
+        let snubbed_at = Instant::now() + snub_timeout;
+        assert!(!peer.is_snubbed(Instant::now()));
+        assert!(peer.is_snubbed(snubbed_at));
+
+        peer.handle_message(
+            &mut sent,
+            PeerMessage::Unchoke,
+            None,
+            None,
+            &disk_cache,
+            DownloadedPieces::default(),
+        )?;
+        assert!(!peer.is_snubbed(snubbed_at));
+        Ok(())
+    }
+
+    #[test]
+    fn requests_without_anything_to_upload_are_canceled() -> Result<(), ProtocolError> {
+        let address = SocketAddr::from(([127, 0, 0, 1], 6881));
+        let mut peer = Peer::from_accepted([1; 20], address, 2);
+        let disk_cache = DiskCache::new(0);
+        let mut sent = Vec::new();
+        peer.set_choked(&mut sent, false)?;
+
+        let request = Request::new(0, 0, BLOCK_SIZE);
+        for upload in [None, Some(&UploadInfo::new([0; 20], String::new(), 2))] {
+            peer.handle_message(
+                &mut sent,
+                PeerMessage::Request(request),
+                None,
+                upload,
+                &disk_cache,
+                DownloadedPieces::default(),
+            )?;
+        }
+
+        assert_eq!(0, peer.uploaded_bytes());
+        assert_eq!(
+            vec![
+                PeerMessage::Unchoke,
+                PeerMessage::Cancel(request.cancel()),
+                PeerMessage::Cancel(request.cancel()),
+            ],
+            sent_messages(&sent)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use super::peer_protocol::ProtocolError;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Limits the amount of peer connections that are open at once, across every torrent of the client.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    open: Arc<Mutex<usize>>,
+    max: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            open: Arc::new(Mutex::new(0)),
+            max: Arc::new(AtomicUsize::new(max)),
+        }
+    }
+
+    /// Changes the limit for every clone. Connections that are already open stay open, but no more are
+    /// opened until they are fewer than the new limit.
+    pub fn set_max(&self, max: usize) {
+        self.max.store(max, Ordering::SeqCst);
+    }
+
+    pub fn max(&self) -> usize {
+        self.max.load(Ordering::SeqCst)
+    }
+
+    /// Returns a permit to open a connection, or `None` if the limit was reached.
+    pub fn try_acquire(&self) -> Result<Option<ConnectionPermit>, String> {
+        let mut open = self.open.lock().map_err(|e| e.to_string())?;
+        if *open >= self.max() {
+            return Ok(None);
+        }
+        *open += 1;
+        Ok(Some(ConnectionPermit {
+            open: self.open.clone(),
+        }))
+    }
+
+    pub fn open_connections(&self) -> Result<usize, String> {
+        Ok(*self.open.lock().map_err(|e| e.to_string())?)
+    }
+}
+
+/// Counts an open connection against its `ConnectionLimit` until it is dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    open: Arc<Mutex<usize>>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Ok(mut open) = self.open.lock() {
+            *open = open.saturating_sub(1);
+        }
+    }
+}
+
+/// Info hash of a torrent and id of one of its peers.
+type PeerKey = ([u8; 20], [u8; 20]);
+
+/// Ids of the peers that the client has a connection to, by torrent, so a second connection to the same peer
+/// is refused. Clones share the ids.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectedPeers {
+    ids: Arc<Mutex<HashSet<PeerKey>>>,
+}
+
+impl ConnectedPeers {
+    /// Registers a connection to the peer with `peer_id` for the torrent with `info_hash`, which stays
+    /// registered until the returned registration is dropped.
+    pub fn register(
+        &self,
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+    ) -> Result<PeerRegistration, ProtocolError> {
+        let mut ids = self
+            .ids
+            .lock()
+            .map_err(|e| ProtocolError::Peer(e.to_string()))?;
+        if !ids.insert((info_hash, peer_id)) {
+            return Err(ProtocolError::DuplicateConnection(peer_id));
+        }
+        Ok(PeerRegistration {
+            ids: self.ids.clone(),
+            key: (info_hash, peer_id),
+        })
+    }
+}
+
+/// Keeps a connection registered in its `ConnectedPeers` until it is dropped.
+#[derive(Debug)]
+pub struct PeerRegistration {
+    ids: Arc<Mutex<HashSet<PeerKey>>>,
+    key: PeerKey,
+}
+
+impl Drop for PeerRegistration {
+    fn drop(&mut self) {
+        if let Ok(mut ids) = self.ids.lock() {
+            ids.remove(&self.key);
+        }
+    }
+}
+
+/// Decides how many peers each torrent downloads from at once. The event loop connects to the queued peers
+/// of a torrent until it has that many sessions, each of which downloads piece after piece through its
+/// connection. Peers go back to the queue of the torrent once their connection closes.
+#[derive(Debug, Clone)]
+pub struct PeerManager {
+    target_connections: usize,
+    limit: ConnectionLimit,
+    connected: ConnectedPeers,
+}
+
+impl Default for PeerManager {
+    fn default() -> Self {
+        Self::new(1, ConnectionLimit::new(usize::MAX))
+    }
+}
+
+impl PeerManager {
+    /// Creates a manager that keeps up to `target_connections` sessions per torrent, sharing `limit` with
+    /// the torrents of every other manager clone.
+    pub fn new(target_connections: usize, limit: ConnectionLimit) -> Self {
+        Self {
+            target_connections: target_connections.max(1),
+            limit,
+            connected: ConnectedPeers::default(),
+        }
+    }
+
+    pub fn target_connections(&self) -> usize {
+        self.target_connections
+    }
+
+    pub fn limit(&self) -> &ConnectionLimit {
+        &self.limit
+    }
+
+    /// Returns the peers that the event loop is connected to.
+    pub fn connected_peers(&self) -> &ConnectedPeers {
+        &self.connected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_released_when_dropped() -> Result<(), String> {
+        let limit = ConnectionLimit::new(2);
+        let first = limit.try_acquire()?;
+        let second = limit.try_acquire()?;
+        assert!(first.is_some() && second.is_some());
+        assert!(limit.try_acquire()?.is_none());
+
+        drop(first);
+        assert_eq!(1, limit.open_connections()?);
+        let third = limit.try_acquire()?;
+        assert!(third.is_some());
+
+        limit.clone().set_max(3);
+        assert!(limit.try_acquire()?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn second_connections_to_a_peer_are_refused_until_the_first_one_closes() -> Result<(), String> {
+        let connected = ConnectedPeers::default();
+        let first = connected.register([0; 20], [1; 20]);
+        assert!(first.is_ok());
+        assert!(matches!(
+            connected.clone().register([0; 20], [1; 20]),
+            Err(ProtocolError::DuplicateConnection(peer_id)) if peer_id == [1; 20]
+        ));
+        assert!(connected.register([2; 20], [1; 20]).is_ok());
+
+        drop(first);
+        assert!(connected.register([0; 20], [1; 20]).is_ok());
+        Ok(())
+    }
+}
@@ -1,28 +1,54 @@
 use super::download_pool::DownloadedPieces;
 use crate::{
-    client::piece::Piece,
-    messages::message_types::{
-        bitfield::Bitfield,
-        block::Block,
-        handshake::{Handshake, HANDSHAKE_PSTR},
-        have::Have,
-        interested::Interested,
-        request::Request,
+    client::{piece::Piece, torrent_v2::V2Info},
+    messages::{
+        message_types::{
+            bitfield::Bitfield,
+            block::Block,
+            handshake::{Handshake, HANDSHAKE_PSTR},
+            hash_reject::HashReject,
+            hash_request::HashRequest,
+            hashes::Hashes,
+            have::Have,
+            interested::Interested,
+            request::Request,
+        },
+        peer_message::{MessageError, MAX_BITFIELD_LEN},
     },
-    utils,
+    network::ban_list::Offense,
+    storage::disk_cache::DiskCache,
 };
 use std::{
     fmt::{self, Display, Formatter},
-    io::Read,
-    net::TcpStream,
+    io::Write,
 };
 
 pub const BLOCK_SIZE: u32 = 16384;
+/// Amount of block requests that are sent to a peer before its answers arrive.
+pub const REQUEST_SLOTS: usize = 5;
+/// Longest message that a peer may send, which is the longest bitfield. Longer ones are taken as an attack,
+/// since they would make the client buffer that many bytes.
+pub const MAX_MESSAGE_LEN: usize = 1 + MAX_BITFIELD_LEN;
+/// Longest block that a peer may request.
+pub const MAX_REQUEST_LEN: u32 = 8 * BLOCK_SIZE;
 
 #[derive(Debug)]
 pub enum ProtocolError {
     Peer(String),
     Piece(String),
+    /// The peer misbehaved, which counts against it in the ban list. The connection is closed, except for
+    /// corrupt pieces, which are downloaded again.
+    Offense(Offense, String),
+    /// The handshake of the peer names a protocol other than `HANDSHAKE_PSTR`.
+    WrongProtocol(String),
+    /// The peer answered with the info hash of another torrent.
+    WrongInfoHash([u8; 20]),
+    /// The peer id of the handshake is not the one that the tracker announced.
+    WrongPeerId([u8; 20]),
+    /// The peer is the client itself.
+    SelfConnection,
+    /// The client already has a connection to the peer with this id for the same torrent.
+    DuplicateConnection([u8; 20]),
 }
 
 impl Display for ProtocolError {
@@ -30,109 +56,110 @@ impl Display for ProtocolError {
         let display = match self {
             Self::Peer(e) => format!("Peer error: {e}"),
             Self::Piece(e) => format!("Piece error: {e}"),
+            Self::Offense(offense, e) => format!("Peer offense ({offense:?}): {e}"),
+            Self::WrongProtocol(pstr) => format!("Peer speaks an unknown protocol: {pstr:?}"),
+            Self::WrongInfoHash(info_hash) => {
+                format!("Peer answered for another torrent: {info_hash:?}")
+            }
+            Self::WrongPeerId(peer_id) => {
+                format!("Peer id {peer_id:?} is not the one announced by the tracker")
+            }
+            Self::SelfConnection => "Peer is the client itself".to_string(),
+            Self::DuplicateConnection(peer_id) => {
+                format!("Peer {peer_id:?} is already connected")
+            }
         };
         write!(f, "{display}")
     }
 }
 
-pub fn handle_handshakes(
-    stream: &mut TcpStream,
-    peer_id: [u8; 20],
+/// Checks that the handshake of a peer speaks the BitTorrent protocol for the torrent with `info_hash`,
+/// that it does not come from the client itself, and that it has the peer id that was expected, if any.
+pub fn validate_handshake(
+    handshake: &Handshake,
+    client_id: [u8; 20],
     info_hash: [u8; 20],
+    expected_id: Option<[u8; 20]>,
 ) -> Result<(), ProtocolError> {
-    send_handshake(stream, peer_id, info_hash)?;
-    receive_handshake(stream)?;
+    if handshake.pstr() != HANDSHAKE_PSTR {
+        return Err(ProtocolError::WrongProtocol(handshake.pstr().to_string()));
+    }
+    if handshake.info_hash() != info_hash {
+        return Err(ProtocolError::WrongInfoHash(handshake.info_hash()));
+    }
+    if handshake.peer_id() == client_id {
+        return Err(ProtocolError::SelfConnection);
+    }
+    if expected_id.is_some_and(|expected_id| expected_id != handshake.peer_id()) {
+        return Err(ProtocolError::WrongPeerId(handshake.peer_id()));
+    }
     Ok(())
 }
 
-pub fn send_handshake(
-    stream: &mut TcpStream,
+/// Returns the handshake that the client with `peer_id` sends for the torrent with `info_hash`.
+pub fn handshake(peer_id: [u8; 20], info_hash: [u8; 20]) -> Handshake {
+    Handshake::new(HANDSHAKE_PSTR, [0; 8], info_hash, peer_id)
+}
+
+pub fn send_handshake<W: Write>(
+    stream: &mut W,
     peer_id: [u8; 20],
     info_hash: [u8; 20],
 ) -> Result<(), ProtocolError> {
-    let handshake = Handshake::new(HANDSHAKE_PSTR, [0; 8], info_hash, peer_id);
-    handshake.send(stream)
-}
-
-pub fn receive_handshake(stream: &mut TcpStream) -> Result<Handshake, ProtocolError> {
-    let pstrlen = read_pstrlen_from(stream)?;
-    let pstr = read_pstr_from(stream, pstrlen)?;
-    let reserved = read_reserved_from(stream)?;
-    let info_hash = read_info_hash_from(stream)?;
-    let peer_id = read_peer_id_from(stream)?;
-    Ok(Handshake::new(&pstr, reserved, info_hash, peer_id))
-}
-
-fn read_bytes_from(stream: &mut TcpStream, len: usize) -> Result<Vec<u8>, ProtocolError> {
-    let mut bytes = vec![0; len];
-    stream
-        .read_exact(&mut bytes)
-        .map_err(|e| ProtocolError::Peer(e.to_string()))?;
-    Ok(bytes)
+    handshake(peer_id, info_hash).send(stream)
 }
 
-fn read_pstrlen_from(stream: &mut TcpStream) -> Result<u8, ProtocolError> {
-    let pstrlen = read_bytes_from(stream, 1)?
-        .try_into()
-        .map_err(|_| ProtocolError::Peer("Conversion error for pstrlen field".to_string()))?;
-    Ok(u8::from_be_bytes(pstrlen))
-}
-
-fn read_pstr_from(stream: &mut TcpStream, pstrlen: u8) -> Result<String, ProtocolError> {
-    let pstr = read_bytes_from(stream, pstrlen as usize)?;
-    utils::bytes_to_string(&pstr).map_err(ProtocolError::Peer)
-}
-
-fn read_reserved_from(stream: &mut TcpStream) -> Result<[u8; 8], ProtocolError> {
-    read_bytes_from(stream, 8)?
-        .try_into()
-        .map_err(|_| ProtocolError::Peer("Conversion error for reserved field".to_string()))
-}
-
-fn read_info_hash_from(stream: &mut TcpStream) -> Result<[u8; 20], ProtocolError> {
-    read_bytes_from(stream, 20)?
-        .try_into()
-        .map_err(|_| ProtocolError::Peer("Conversion error for info hash field".to_string()))
-}
-
-fn read_peer_id_from(stream: &mut TcpStream) -> Result<[u8; 20], ProtocolError> {
-    read_bytes_from(stream, 20)?
-        .try_into()
-        .map_err(|_| ProtocolError::Peer("Conversion error for peer id field".to_string()))
-}
-
-fn read_len_from(stream: &mut TcpStream) -> Result<u32, ProtocolError> {
-    let len = read_bytes_from(stream, 4)?
-        .try_into()
-        .map_err(|_| ProtocolError::Peer("Conversion error for len field".to_string()))?;
-    Ok(u32::from_be_bytes(len))
-}
-
-pub fn read_message_bytes(stream: &mut TcpStream) -> Result<Vec<u8>, ProtocolError> {
-    let len = read_len_from(stream)?;
-    read_bytes_from(stream, len as usize)
+/// Rejects the length prefix of a message that is longer than `MAX_MESSAGE_LEN`.
+pub fn check_message_len(len: usize) -> Result<(), ProtocolError> {
+    if len > MAX_MESSAGE_LEN {
+        return Err(MessageError::TooLong(len).into());
+    }
+    Ok(())
 }
 
-pub fn handle_choke(last_request: &mut Request, am_choked: &mut bool) {
-    last_request.reset();
+pub fn handle_choke(requests: &mut Vec<Request>, am_choked: &mut bool) {
+    requests.clear();
     *am_choked = true
 }
 
-pub fn handle_unchoke(
-    stream: &mut TcpStream,
-    last_request: &mut Request,
+pub fn handle_unchoke<W: Write>(
+    stream: &mut W,
+    piece: &Piece,
+    requests: &mut Vec<Request>,
     am_choked: &mut bool,
     am_interested: bool,
 ) -> Result<(), ProtocolError> {
     *am_choked = false;
     if am_interested {
-        last_request.send(stream)?;
+        fill_request_slots(stream, piece, requests)?;
     }
     Ok(())
 }
 
-pub fn handle_have(
-    stream: &mut TcpStream,
+/// Requests the next blocks of a piece until `REQUEST_SLOTS` of them are outstanding, so the peer always
+/// has requests queued while the previous blocks travel.
+pub fn fill_request_slots<W: Write>(
+    stream: &mut W,
+    piece: &Piece,
+    requests: &mut Vec<Request>,
+) -> Result<(), ProtocolError> {
+    let mut next_begin = requests
+        .last()
+        .map_or(piece.len(), |request| request.next_begin());
+
+    while requests.len() < REQUEST_SLOTS {
+        let Some(mut request) = piece.request_block_at(next_begin) else {
+            break;
+        };
+        request.send(stream)?;
+        next_begin = request.next_begin();
+        requests.push(request);
+    }
+    Ok(())
+}
+
+pub fn handle_have<W: Write>(
+    stream: &mut W,
     have: Have,
     bitfield: &mut Bitfield,
     am_interested: &mut bool,
@@ -157,8 +184,8 @@ pub fn handle_have(
     Ok(())
 }
 
-pub fn handle_bitfield(
-    stream: &mut TcpStream,
+pub fn handle_bitfield<W: Write>(
+    stream: &mut W,
     bitfield: &mut Bitfield,
     piece_index: usize,
     am_interested: &mut bool,
@@ -174,12 +201,15 @@ pub fn handle_bitfield(
     Ok(())
 }
 
-pub fn handle_request(
-    stream: &mut TcpStream,
+/// Sends the requested block if the client has its piece, which it does only if `bitfield` has it.
+/// Requests of pieces that the client does not have are answered with a cancel.
+pub fn handle_request<W: Write>(
+    stream: &mut W,
     request: Request,
     peer_is_choked: bool,
-    download_path: String,
-    bitfield: &Bitfield,
+    download_path: &str,
+    disk_cache: &DiskCache,
+    bitfield: Option<&Bitfield>,
 ) -> Result<(), ProtocolError> {
     let err = ProtocolError::Peer;
 
@@ -188,45 +218,124 @@ pub fn handle_request(
         return Err(err(msg));
     }
 
-    if !bitfield.contains(request.index() as usize) {
+    let index = request.index() as usize;
+    let has_piece = bitfield
+        .is_some_and(|bitfield| index < bitfield.total_pieces() && bitfield.contains(index));
+    if !has_piece {
         let cancel = request.cancel();
         cancel.send(stream)?;
         let msg = format!("Requested piece {} is not being served", request.index());
         return Err(err(msg));
     }
 
-    let block_to_send = request.load_block_from(download_path).map_err(err)?;
+    let block_to_send = request
+        .load_block_from(download_path, disk_cache)
+        .map_err(err)?;
     block_to_send.send(stream)?;
     Ok(())
 }
 
-pub fn handle_block(
-    stream: &mut TcpStream,
+/// Answers a hash request with the hashes of the piece layer of a v2 torrent, or rejects it if they
+/// are not known or the torrent is not a v2 one.
+pub fn handle_hash_request<W: Write>(
+    stream: &mut W,
+    request: HashRequest,
+    v2: Option<&V2Info>,
+) -> Result<(), ProtocolError> {
+    let hashes = v2.and_then(|v2| {
+        v2.hashes(
+            &request.pieces_root(),
+            request.base_layer(),
+            request.index() as usize,
+            request.length() as usize,
+            request.proof_layers() as usize,
+        )
+    });
+
+    match hashes {
+        Some(hashes) => Hashes::new(request, hashes).send(stream),
+        None => HashReject::new(request).send(stream),
+    }
+}
+
+/// Stores a block that answers one of the outstanding requests. Peers answer requests in order, so a block
+/// that skips an earlier one means that the earlier ones were dropped, and they are requested again.
+pub fn handle_block<W: Write>(
+    stream: &mut W,
     block: Block,
     piece: &mut Piece,
-    last_request: &mut Request,
+    requests: &mut Vec<Request>,
     am_choked: bool,
 ) -> Result<(), ProtocolError> {
     if am_choked {
         return Ok(());
     }
 
-    if !last_request.matches(&block) {
-        last_request.send(stream)?;
+    let Some(position) = requests.iter().position(|request| request.matches(&block)) else {
         return Ok(());
+    };
+    if block.begin() as usize != piece.len() {
+        requests.clear();
+        return fill_request_slots(stream, piece, requests);
     }
 
+    requests.drain(..=position);
     piece.append(&block);
 
     if piece.is_full() {
         if !piece.hashes_match() {
             let msg = format!("Hash verification for piece {} failed", piece.index());
-            return Err(ProtocolError::Piece(msg));
+            return Err(ProtocolError::Offense(Offense::BadPiece, msg));
         }
         return Ok(());
     }
 
-    *last_request = piece.request_next_block();
-    last_request.send(stream)?;
-    Ok(())
+    fill_request_slots(stream, piece, requests)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshakes_are_validated_against_the_torrent_and_the_client() {
+        let (client_id, info_hash, peer_id) = ([1; 20], [2; 20], [3; 20]);
+        let validate = |handshake: Handshake, expected_id| {
+            validate_handshake(&handshake, client_id, info_hash, expected_id)
+        };
+
+        assert!(validate(
+            Handshake::new(HANDSHAKE_PSTR, [0; 8], info_hash, peer_id),
+            None
+        )
+        .is_ok());
+        assert!(matches!(
+            validate(
+                Handshake::new("Other protocol", [0; 8], info_hash, peer_id),
+                None
+            ),
+            Err(ProtocolError::WrongProtocol(_))
+        ));
+        assert!(matches!(
+            validate(
+                Handshake::new(HANDSHAKE_PSTR, [0; 8], [4; 20], peer_id),
+                None
+            ),
+            Err(ProtocolError::WrongInfoHash(info_hash)) if info_hash == [4; 20]
+        ));
+        assert!(matches!(
+            validate(
+                Handshake::new(HANDSHAKE_PSTR, [0; 8], info_hash, client_id),
+                None
+            ),
+            Err(ProtocolError::SelfConnection)
+        ));
+        assert!(matches!(
+            validate(
+                Handshake::new(HANDSHAKE_PSTR, [0; 8], info_hash, peer_id),
+                Some([5; 20])
+            ),
+            Err(ProtocolError::WrongPeerId(_))
+        ));
+    }
 }
@@ -0,0 +1,400 @@
+use super::peer_protocol::ProtocolError;
+use crate::{
+    client::{piece::Piece, torrent::Torrent},
+    url_encoding,
+};
+use native_tls::TlsConnector;
+use std::{
+    io::{Error, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+/// Time to wait after the first failed request to a web seed. Every consecutive failure doubles it.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest header section accepted in the response of a web seed.
+const MAX_HEADERS_LEN: usize = 16 * 1024;
+
+/// An HTTP server that holds the files of a torrent, listed in its `url-list` (BEP 19).
+/// Pieces are downloaded with range requests to the files they span.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WebSeed {
+    url: String,
+    https: bool,
+    host: String,
+    port: u16,
+    /// Path of the URL, without the query string.
+    path: String,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl WebSeed {
+    /// Creates a web seed from an "http://" or "https://" URL.
+    pub fn new(url: String) -> Result<Self, String> {
+        let (https, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            _ => return Err(format!("Unsupported web seed URL: {url}")),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(path_start) => rest.split_at(path_start),
+            None => (rest, "/"),
+        };
+        // IPv6 hosts are written between brackets, since their addresses have colons too.
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, rest)) => match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(format!("Invalid host in web seed URL: {url}")),
+                },
+                None => return Err(format!("Invalid host in web seed URL: {url}")),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| format!("Invalid port in web seed URL: {url}"))?,
+            None if https => 443,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in web seed URL: {url}"));
+        }
+
+        Ok(Self {
+            https,
+            host: host.to_string(),
+            port,
+            path: path.split('?').next().unwrap_or(path).to_string(),
+            url,
+            failures: 0,
+            retry_at: None,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the host as the `Host` header has it, with IPv6 addresses between brackets.
+    fn host_header(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        }
+    }
+
+    /// Returns how long the web seed has to wait before its next request. Sessions skip it until then.
+    pub fn backoff(&self) -> Duration {
+        self.retry_at.map_or(Duration::ZERO, |retry_at| {
+            retry_at.saturating_duration_since(Instant::now())
+        })
+    }
+
+    /// Downloads a whole piece. The piece is only filled once its bytes match its hash, and any failure makes
+    /// the web seed back off for longer.
+    pub fn download(&mut self, piece: &mut Piece, torrent: &Torrent) -> Result<(), ProtocolError> {
+        match self.fetch_piece(piece, torrent) {
+            Ok(bytes) => {
+                self.failures = 0;
+                self.retry_at = None;
+                piece.fill(bytes);
+                Ok(())
+            }
+            Err(e) => {
+                self.failures += 1;
+                let backoff = INITIAL_BACKOFF
+                    .saturating_mul(1 << (self.failures - 1).min(16))
+                    .min(MAX_BACKOFF);
+                self.retry_at = Some(Instant::now() + backoff);
+                Err(ProtocolError::Peer(format!("Web seed {}: {e}", self.url)))
+            }
+        }
+    }
+
+    fn fetch_piece(&self, piece: &Piece, torrent: &Torrent) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![0; piece.size()];
+        for span in torrent.piece_spans(piece) {
+            let data = self.fetch_range(
+                &self.file_path(&span.file.path)?,
+                span.file_begin,
+                span.length,
+            )?;
+            bytes[span.piece_begin..(span.piece_begin + span.length)].copy_from_slice(&data);
+        }
+
+        if !piece.hash().matches(&bytes) {
+            return Err(format!("piece {} does not match its hash", piece.index()));
+        }
+        Ok(bytes)
+    }
+
+    /// Returns the URL path of a file of the torrent. URLs that end with a slash are the directory that holds the
+    /// torrent, while single file torrents may also point to the file itself.
+    fn file_path(&self, file_path: &str) -> Result<String, String> {
+        let is_single_file = !file_path.contains('/');
+        if is_single_file && !self.path.ends_with('/') {
+            return Ok(self.path.clone());
+        }
+
+        let mut path = self.path.clone();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        let components = file_path
+            .split('/')
+            .map(|component| Ok(url_encoding::encode(component)?.replace('+', "%20")))
+            .collect::<Result<Vec<String>, String>>()?;
+        path.push_str(&components.join("/"));
+        Ok(path)
+    }
+
+    /// Requests `length` bytes of the file at `path`, starting at `begin`.
+    fn fetch_range(&self, path: &str, begin: usize, length: usize) -> Result<Vec<u8>, String> {
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: {}\r\nRange: bytes={begin}-{}\r\nConnection: close\r\n\r\n",
+            self.host_header(),
+            begin + length - 1
+        );
+        let err = |e: Error| e.to_string();
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).map_err(err)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(err)?;
+
+        if self.https {
+            let connector = TlsConnector::new().map_err(|e| e.to_string())?;
+            let mut stream = connector
+                .connect(&self.host, stream)
+                .map_err(|e| e.to_string())?;
+            send_request(&request, &mut stream, begin, length)
+        } else {
+            send_request(&request, &mut &stream, begin, length)
+        }
+    }
+}
+
+/// Sends a range request and reads the body of its response, which never goes past the `length` requested
+/// bytes, whatever the server sends.
+fn send_request<T: Read + Write>(
+    request: &str,
+    stream: &mut T,
+    begin: usize,
+    length: usize,
+) -> Result<Vec<u8>, String> {
+    let err = |e: Error| e.to_string();
+    stream.write_all(request.as_bytes()).map_err(err)?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+    let headers_end = loop {
+        if let Some(end) = response.windows(4).position(|bytes| bytes == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if response.len() > MAX_HEADERS_LEN {
+            return Err("web seed response headers are too long".to_string());
+        }
+        let read = stream.read(&mut buffer).map_err(err)?;
+        if read == 0 {
+            return Err("invalid formatting of web seed response".to_string());
+        }
+        response.extend_from_slice(&buffer[..read]);
+    };
+    check_headers(
+        &String::from_utf8_lossy(&response[..headers_end]),
+        begin,
+        length,
+    )?;
+
+    let mut body = response.split_off(headers_end);
+    if body.len() < length {
+        Read::take(&mut *stream, (length - body.len()) as u64)
+            .read_to_end(&mut body)
+            .map_err(err)?;
+    }
+    if body.len() < length {
+        return Err(format!("response body is shorter than {length} bytes"));
+    }
+    body.truncate(length);
+    Ok(body)
+}
+
+/// Checks that the response has exactly the requested range in its body. Servers that ignore the range
+/// header answer with the whole file, which is only accepted if the file is the range itself. Chunked
+/// bodies are not supported.
+fn check_headers(headers: &str, begin: usize, length: usize) -> Result<(), String> {
+    let mut lines = headers.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut content_length = None;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid content length {value}"))?,
+            );
+        } else if name.eq_ignore_ascii_case("transfer-encoding")
+            && !value.eq_ignore_ascii_case("identity")
+        {
+            return Err(format!("unsupported transfer encoding {value}"));
+        }
+    }
+
+    match status_line.get(9..12) {
+        Some("206") => {}
+        Some("200") if begin == 0 && content_length == Some(length) => {}
+        Some("200") => return Err("server does not support range requests".to_string()),
+        _ => return Err(format!("unexpected response {status_line}")),
+    }
+    match content_length {
+        Some(content_length) if content_length != length => Err(format!(
+            "response body has {content_length} bytes instead of {length}"
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::single_file::SingleFile, utils};
+    use std::{io::BufRead, io::BufReader, net::TcpListener, thread};
+
+    /// Serves range requests of the files of `files` from memory, answering `requests` connections.
+    fn serve_files(files: Vec<(&'static str, Vec<u8>)>, requests: usize) -> Result<u16, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().take(requests).flatten() {
+                let mut lines = BufReader::new(&stream).lines().map_while(Result::ok);
+                let path = lines.next().unwrap_or_default();
+                let range = lines
+                    .take_while(|line| !line.is_empty())
+                    .find_map(|line| line.strip_prefix("Range: bytes=").map(str::to_string))
+                    .unwrap_or_default();
+
+                let file = files
+                    .iter()
+                    .find(|(name, _)| path.split(' ').nth(1) == Some(*name));
+                let (begin, end) = range.split_once('-').unwrap_or_default();
+                let response = match (file, begin.parse::<usize>(), end.parse::<usize>()) {
+                    (Some((_, data)), Ok(begin), Ok(end)) => {
+                        let mut response = b"HTTP/1.1 206 Partial Content\r\n\r\n".to_vec();
+                        response.extend_from_slice(&data[begin..=end]);
+                        response
+                    }
+                    _ => b"HTTP/1.1 404 Not Found\r\n\r\n".to_vec(),
+                };
+                let _ = (&stream).write_all(&response);
+            }
+        });
+        Ok(port)
+    }
+
+    fn web_seed_torrent(first: &[u8], second: &[u8]) -> Result<Torrent, String> {
+        let data = [first, second].concat();
+        Torrent::new(
+            "web seed".to_string(),
+            "http://localhost/announce".to_string(),
+            8,
+            data.chunks(8)
+                .enumerate()
+                .map(|(index, piece)| Ok(Piece::new(index, piece.len(), utils::sha1(piece)?)))
+                .collect::<Result<_, String>>()?,
+            vec![
+                SingleFile::new(first.len() as i64, "web seed/a.txt".to_string()),
+                SingleFile::new(second.len() as i64, "web seed/b.txt".to_string()),
+            ],
+            [0; 20],
+        )
+    }
+
+    #[test]
+    fn pieces_are_downloaded_across_file_boundaries() -> Result<(), String> {
+        let (first, second) = (b"0123456789".to_vec(), b"abcdefghij".to_vec());
+        let files = vec![
+            ("/seeds/web%20seed/a.txt", first.clone()),
+            ("/seeds/web%20seed/b.txt", second.clone()),
+        ];
+        let port = serve_files(files, 4)?;
+        let torrent = web_seed_torrent(&first, &second)?;
+        let mut web_seed = WebSeed::new(format!("http://127.0.0.1:{port}/seeds/"))?;
+
+        let data = [first, second].concat();
+        let mut downloaded = Vec::new();
+        for (index, bytes) in data.chunks(8).enumerate() {
+            let mut piece = Piece::new(index, bytes.len(), utils::sha1(bytes)?);
+            web_seed
+                .download(&mut piece, &torrent)
+                .map_err(|e| format!("{e:?}"))?;
+            downloaded.extend(piece.bytes());
+        }
+        assert_eq!(data, downloaded);
+        Ok(())
+    }
+
+    #[test]
+    fn ipv6_hosts_are_parsed_from_between_brackets() -> Result<(), String> {
+        let web_seed = WebSeed::new("http://[::1]:8080/seeds/".to_string())?;
+        assert_eq!(
+            ("::1".to_string(), 8080),
+            (web_seed.host(), web_seed.port())
+        );
+        assert_eq!("[::1]", web_seed.host_header());
+
+        let web_seed = WebSeed::new("https://[2001:db8::1]/".to_string())?;
+        assert_eq!(
+            ("2001:db8::1".to_string(), 443),
+            (web_seed.host(), web_seed.port())
+        );
+        assert!(WebSeed::new("http://[::1/".to_string()).is_err());
+        assert!(WebSeed::new("http://[::1]8080/".to_string()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn failing_web_seeds_back_off() -> Result<(), String> {
+        let port = serve_files(vec![("/web%20seed/a.txt", b"XXXXXXXXXX".to_vec())], 1)?;
+        let torrent = web_seed_torrent(b"0123456789", b"abcdefghij")?;
+        let mut web_seed = WebSeed::new(format!("http://127.0.0.1:{port}/"))?;
+
+        let mut piece = Piece::new(0, 8, utils::sha1(b"01234567")?);
+        assert!(web_seed.download(&mut piece, &torrent).is_err());
+        assert!(piece.is_empty());
+        assert!(web_seed.backoff() > Duration::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn responses_without_the_exact_range_are_rejected() {
+        let partial = "HTTP/1.1 206 Partial Content\r\nContent-Length: 8\r\n\r\n";
+        assert!(check_headers(partial, 16, 8).is_ok());
+        assert!(check_headers(partial, 16, 9).is_err());
+
+        let whole_file = "HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\n";
+        assert!(check_headers(whole_file, 0, 8).is_ok());
+        assert!(check_headers(whole_file, 0, 4).is_err());
+        assert!(check_headers(whole_file, 4, 8).is_err());
+        assert!(check_headers("HTTP/1.1 200 OK\r\n\r\n", 0, 8).is_err());
+
+        let chunked = "HTTP/1.1 206 Partial Content\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert!(check_headers(chunked, 0, 8).is_err());
+        assert!(check_headers("HTTP/1.1 404 Not Found\r\n\r\n", 0, 8).is_err());
+    }
+}
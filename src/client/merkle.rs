@@ -0,0 +1,133 @@
+use crate::utils;
+
+/// Size of the blocks whose SHA-256 hashes are the leaves of the merkle trees of v2 torrents (BEP 52).
+pub const MERKLE_BLOCK_SIZE: usize = 16384;
+
+pub type MerkleHash = [u8; 32];
+
+/// Hash of the leaves that pad a tree until its width is a power of two.
+const ZERO_HASH: MerkleHash = [0; 32];
+
+fn parent(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+    utils::sha256([&left[..], &right[..]].concat())
+}
+
+/// Builds every layer of a merkle tree, from its leaves to its root.
+/// `leaves` are followed by as many `padding` hashes as needed to make the tree `width` leaves wide.
+pub fn layers(leaves: &[MerkleHash], width: usize, padding: MerkleHash) -> Vec<Vec<MerkleHash>> {
+    let mut layer = leaves.to_vec();
+    layer.resize(width.max(leaves.len()).next_power_of_two(), padding);
+
+    let mut layers = vec![layer];
+    while let Some(layer) = layers.last().filter(|layer| layer.len() > 1) {
+        let next = layer
+            .chunks_exact(2)
+            .map(|pair| parent(&pair[0], &pair[1]))
+            .collect();
+        layers.push(next);
+    }
+    layers
+}
+
+/// Returns the root of the tree that `layers` would build.
+pub fn root(leaves: &[MerkleHash], width: usize, padding: MerkleHash) -> MerkleHash {
+    layers(leaves, width, padding)
+        .last()
+        .and_then(|root| root.first().copied())
+        .unwrap_or(ZERO_HASH)
+}
+
+/// Hashes every 16 KiB block of `data`. The last block may be shorter.
+pub fn block_hashes(data: &[u8]) -> Vec<MerkleHash> {
+    data.chunks(MERKLE_BLOCK_SIZE).map(utils::sha256).collect()
+}
+
+/// Returns the amount of leaves of the tree of a piece, or of a whole file that fits in a single piece.
+pub fn width_of(length: usize) -> usize {
+    utils::round_up(length.max(1), MERKLE_BLOCK_SIZE) / MERKLE_BLOCK_SIZE
+}
+
+/// Returns the root of a tree that is `width` leaves wide and has no data.
+pub fn padding_root(width: usize) -> MerkleHash {
+    let mut hash = ZERO_HASH;
+    let mut width = width.next_power_of_two();
+    while width > 1 {
+        hash = parent(&hash, &hash);
+        width /= 2;
+    }
+    hash
+}
+
+/// Returns the root of the blocks of `data`, padded until the tree is `width` leaves wide.
+pub fn data_root(data: &[u8], width: usize) -> MerkleHash {
+    root(&block_hashes(data), width, ZERO_HASH)
+}
+
+/// Returns the pieces root of a file from its piece layer.
+pub fn piece_layer_root(piece_layer: &[MerkleHash], piece_length: usize) -> MerkleHash {
+    let padding = padding_root(piece_length / MERKLE_BLOCK_SIZE);
+    root(piece_layer, piece_layer.len(), padding)
+}
+
+/// Returns the sibling hashes needed to verify the subtree of `layers` that is `length` nodes wide and starts at
+/// node `index` of the bottom layer, going up `proof_layers` layers from the root of that subtree.
+pub fn uncle_hashes(
+    layers: &[Vec<MerkleHash>],
+    index: usize,
+    length: usize,
+    proof_layers: usize,
+) -> Option<Vec<MerkleHash>> {
+    let subtree_layer = length.trailing_zeros() as usize;
+    let mut node = index / length;
+    let mut uncles = Vec::with_capacity(proof_layers);
+
+    for layer in layers.iter().skip(subtree_layer).take(proof_layers) {
+        uncles.push(*layer.get(node ^ 1)?);
+        node /= 2;
+    }
+    (uncles.len() == proof_layers).then_some(uncles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_trees_hash_zero_leaves() {
+        let leaf = utils::sha256(b"block");
+        let expected = parent(&parent(&leaf, &ZERO_HASH), &parent(&ZERO_HASH, &ZERO_HASH));
+        assert_eq!(expected, root(&[leaf], 4, ZERO_HASH));
+        assert_eq!(parent(&ZERO_HASH, &ZERO_HASH), padding_root(2));
+    }
+
+    #[test]
+    fn piece_layer_roots_match_the_root_of_the_whole_file() {
+        let piece_length = 2 * MERKLE_BLOCK_SIZE;
+        let data: Vec<u8> = (0..(5 * MERKLE_BLOCK_SIZE + 10))
+            .map(|i| (i % 256) as u8)
+            .collect();
+
+        let piece_layer: Vec<MerkleHash> = data
+            .chunks(piece_length)
+            .map(|piece| data_root(piece, piece_length / MERKLE_BLOCK_SIZE))
+            .collect();
+        let file_root = data_root(&data, width_of(data.len()));
+
+        assert_eq!(3, piece_layer.len());
+        assert_eq!(file_root, piece_layer_root(&piece_layer, piece_length));
+    }
+
+    #[test]
+    fn uncle_hashes_prove_a_subtree() -> Result<(), String> {
+        let leaves: Vec<MerkleHash> = (0..8u8).map(|i| utils::sha256([i])).collect();
+        let layers = layers(&leaves, 8, ZERO_HASH);
+
+        let uncles = uncle_hashes(&layers, 2, 2, 2).ok_or("missing uncles")?;
+        let subtree = parent(&leaves[2], &leaves[3]);
+        let rebuilt = parent(&parent(&uncles[0], &subtree), &uncles[1]);
+        assert_eq!(layers[3][0], rebuilt);
+
+        assert_eq!(None, uncle_hashes(&layers, 0, 2, 3));
+        Ok(())
+    }
+}
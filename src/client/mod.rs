@@ -1,8 +1,18 @@
+pub mod bandwidth_schedule;
 pub mod client_side;
 pub mod download;
+pub mod merkle;
+pub mod peer_source;
 pub mod piece;
+pub mod recheck_state;
+mod safe_path;
+pub mod seeding_goals;
 mod single_file;
 pub mod torrent;
 mod torrent_decoding;
+pub mod torrent_handle;
+pub mod torrent_meta;
+pub mod torrent_queue;
+pub mod torrent_v2;
 mod tracker_decoding;
 mod tracker_info;
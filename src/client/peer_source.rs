@@ -0,0 +1,21 @@
+/// Describes where the address of a peer was learned from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PeerSource {
+    /// A response of one of the trackers listed in the .torrent file.
+    Tracker,
+    /// The distributed hash table (BEP 5).
+    Dht,
+    /// A peer list sent by another peer through the extension protocol (BEP 11).
+    Pex,
+    /// A local network announcement (BEP 14).
+    Lsd,
+    /// An HTTP server listed in the `url-list` of the .torrent file (BEP 19).
+    WebSeed,
+}
+
+impl PeerSource {
+    /// Private torrents (BEP 27) may only get peers from their own trackers and .torrent file.
+    pub fn is_allowed_for(&self, is_private: bool) -> bool {
+        !is_private || matches!(self, PeerSource::Tracker | PeerSource::WebSeed)
+    }
+}
@@ -1,27 +1,86 @@
-use std::cmp;
-
 use crate::{
     messages::message_types::{block::Block, request::Request},
     utils,
 };
 
-use super::download::peer_protocol::BLOCK_SIZE;
+use super::{
+    download::peer_protocol::BLOCK_SIZE,
+    merkle::{self, MerkleHash},
+};
+
+/// Hash that the bytes of a complete piece must match.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum PieceHash {
+    /// SHA-1 hash of the piece, taken from the "pieces" key of v1 torrents.
+    Sha1([u8; 20]),
+    /// Root of the merkle tree of the 16 KiB blocks of the piece (v2 torrents), which is `width` leaves wide.
+    Merkle { root: MerkleHash, width: usize },
+}
+
+impl PieceHash {
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        match self {
+            Self::Sha1(hash) => utils::sha1(bytes) == Ok(*hash),
+            Self::Merkle { root, width } => merkle::data_root(bytes, *width) == *root,
+        }
+    }
+}
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Piece {
     index: usize,
+    size: usize,
     bytes: Vec<u8>,
-    hash: [u8; 20],
+    hash: PieceHash,
     next_block_begin: usize,
+    /// IP addresses of the peers that sent blocks of the piece.
+    contributors: Vec<String>,
+}
+
+/// Iterates over the exact `(begin, length)` ranges in which a piece of `piece_size` bytes is requested,
+/// every one of them `BLOCK_SIZE` bytes long except for the last one, which can be shorter.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BlockRanges {
+    piece_size: u32,
+    next_begin: u32,
+}
+
+impl BlockRanges {
+    pub fn new(piece_size: u32, first_begin: u32) -> Self {
+        Self {
+            piece_size,
+            next_begin: first_begin,
+        }
+    }
+}
+
+impl Iterator for BlockRanges {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_begin >= self.piece_size {
+            return None;
+        }
+        let begin = self.next_begin;
+        let length = BLOCK_SIZE.min(self.piece_size - begin);
+        self.next_begin += length;
+        Some((begin, length))
+    }
 }
 
 impl Piece {
-    pub fn new(index: usize, length: usize, hash: [u8; 20]) -> Self {
+    pub fn new(index: usize, size: usize, hash: [u8; 20]) -> Self {
+        Self::with_hash(index, size, PieceHash::Sha1(hash))
+    }
+
+    pub fn with_hash(index: usize, size: usize, hash: PieceHash) -> Self {
         Self {
             index,
-            bytes: Vec::with_capacity(length),
+            size,
+            bytes: Vec::with_capacity(size),
             hash,
             next_block_begin: 0,
+            contributors: Vec::new(),
         }
     }
 
@@ -29,6 +88,11 @@ impl Piece {
         self.index
     }
 
+    /// Returns the amount of bytes that the piece has once it is complete.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn len(&self) -> usize {
         self.bytes.len()
     }
@@ -42,23 +106,25 @@ impl Piece {
     }
 
     pub fn bytes_left(&self) -> usize {
-        self.bytes.capacity() - self.bytes.len()
+        self.size.saturating_sub(self.bytes.len())
     }
 
     pub fn is_full(&self) -> bool {
         self.bytes_left() == 0
     }
 
-    pub fn hash(&self) -> [u8; 20] {
+    pub fn hash(&self) -> PieceHash {
         self.hash
     }
 
     pub fn hashes_match(&self) -> bool {
-        let mut new_hash = [0; 20];
-        if let Ok(hash) = utils::sha1(&self.bytes) {
-            new_hash = hash
-        };
-        self.hash == new_hash
+        self.hash.matches(&self.bytes)
+    }
+
+    /// Stores the whole piece at once, for sources that do not send it in blocks.
+    pub fn fill(&mut self, bytes: Vec<u8>) {
+        self.next_block_begin = bytes.len();
+        self.bytes = bytes;
     }
 
     pub fn append(&mut self, block: &Block) {
@@ -66,8 +132,91 @@ impl Piece {
         self.next_block_begin = block.next_begin()
     }
 
+    /// Records that the peer at `ip` sent blocks of the piece.
+    pub fn add_contributor(&mut self, ip: &str) {
+        if !self
+            .contributors
+            .iter()
+            .any(|contributor| contributor == ip)
+        {
+            self.contributors.push(ip.to_string());
+        }
+    }
+
+    pub fn contributors(&self) -> &[String] {
+        &self.contributors
+    }
+
+    /// Drops every byte of the piece, so it is downloaded again from scratch, and returns the peers that
+    /// had sent them.
+    pub fn reset(&mut self) -> Vec<String> {
+        self.bytes.clear();
+        self.next_block_begin = 0;
+        std::mem::take(&mut self.contributors)
+    }
+
+    /// Returns every block range of the piece, from its first byte to its last one.
+    pub fn block_ranges(&self) -> BlockRanges {
+        BlockRanges::new(self.size as u32, 0)
+    }
+
+    /// Returns the request of the block that starts at `begin`, or `None` if the piece ends before it.
+    pub fn request_block_at(&self, begin: usize) -> Option<Request> {
+        BlockRanges::new(self.size as u32, begin as u32)
+            .next()
+            .map(|(begin, length)| Request::new(self.index as u32, begin, length))
+    }
+
     pub fn request_next_block(&self) -> Request {
-        let length = cmp::min(BLOCK_SIZE, self.bytes_left() as u32);
-        Request::new(self.index as u32, self.next_block_begin as u32, length)
+        let begin = self.next_block_begin as u32;
+        let (begin, length) = BlockRanges::new(self.size as u32, begin)
+            .next()
+            .unwrap_or((begin, 0));
+        Request::new(self.index as u32, begin, length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_ranges_of_a_piece_multiple_of_block_size() {
+        let piece = Piece::new(0, 2 * BLOCK_SIZE as usize, [0; 20]);
+        let ranges: Vec<(u32, u32)> = piece.block_ranges().collect();
+        assert_eq!(vec![(0, BLOCK_SIZE), (BLOCK_SIZE, BLOCK_SIZE)], ranges);
+    }
+
+    #[test]
+    fn block_ranges_of_a_short_last_piece() {
+        let piece = Piece::new(7, 40000, [0; 20]);
+        let ranges: Vec<(u32, u32)> = piece.block_ranges().collect();
+        assert_eq!(vec![(0, 16384), (16384, 16384), (32768, 7232)], ranges);
+    }
+
+    #[test]
+    fn piece_is_full_after_receiving_every_block() {
+        let mut piece = Piece::new(3, 20000, [0; 20]);
+
+        for (begin, length) in piece.block_ranges() {
+            let request = piece.request_next_block();
+            assert_eq!(Request::new(3, begin, length), request);
+            piece.append(&Block::new(3, begin, vec![0; length as usize]));
+        }
+
+        assert!(piece.is_full());
+        assert_eq!(0, piece.bytes_left());
+        assert_eq!(Request::new(3, 20000, 0), piece.request_next_block());
+    }
+
+    #[test]
+    fn merkle_pieces_are_verified_against_their_root() {
+        let bytes = vec![7; 20000];
+        let root = merkle::data_root(&bytes, 4);
+        let mut piece = Piece::with_hash(0, 20000, PieceHash::Merkle { root, width: 4 });
+
+        piece.append(&Block::new(0, 0, bytes));
+        assert!(piece.hashes_match());
+        assert!(!PieceHash::Merkle { root, width: 2 }.matches(&piece.bytes()));
     }
 }
@@ -0,0 +1,84 @@
+use crate::{
+    client::torrent_meta::TorrentMeta, ui_notification_structs::torrent_state::TorrentState,
+};
+
+/// Progress of a hash verification of the data that a torrent already has on disk.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct RecheckState {
+    info_hash: [u8; 20],
+    torrent_name: String,
+    n_pieces: usize,
+    total_size: usize,
+    /// Pieces that the recheck verifies, which leaves out those that were already downloaded.
+    rechecked_pieces: usize,
+    checked_pieces: usize,
+    verified_pieces: usize,
+    meta: TorrentMeta,
+}
+
+impl RecheckState {
+    pub fn new(
+        info_hash: [u8; 20],
+        torrent_name: String,
+        n_pieces: usize,
+        total_size: usize,
+        rechecked_pieces: usize,
+    ) -> Self {
+        RecheckState {
+            info_hash,
+            torrent_name,
+            n_pieces,
+            total_size,
+            rechecked_pieces,
+            checked_pieces: 0,
+            verified_pieces: 0,
+            meta: TorrentMeta::default(),
+        }
+    }
+
+    pub fn torrent_name(&self) -> &str {
+        &self.torrent_name
+    }
+
+    pub fn set_checked_pieces(&mut self, checked_pieces: usize) {
+        self.checked_pieces = checked_pieces;
+    }
+
+    pub fn set_verified_pieces(&mut self, verified_pieces: usize) {
+        self.verified_pieces = verified_pieces;
+    }
+
+    pub fn set_meta(&mut self, meta: TorrentMeta) {
+        self.meta = meta;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.checked_pieces == self.rechecked_pieces
+    }
+
+    pub fn generate_torrent_state(&self) -> TorrentState {
+        let mut state = TorrentState::new(0);
+        state.set_metadata_info_hash(self.info_hash);
+        state.set_metadata_name(self.torrent_name.clone());
+        state.set_metadata_n_pieces(self.n_pieces as u32);
+        state.set_metadata_total_size(self.total_size as u32);
+        state.set_metadata_downloaded(self.verified_pieces as u32);
+        state.set_metadata_checked(self.checked_pieces as u32);
+        self.meta.fill_torrent_state(&mut state);
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recheck_finishes_once_the_rechecked_pieces_are_checked() {
+        let mut state = RecheckState::new([0; 20], "torrent".to_string(), 10, 100, 4);
+        state.set_checked_pieces(3);
+        assert!(!state.is_finished());
+        state.set_checked_pieces(4);
+        assert!(state.is_finished());
+    }
+}
@@ -0,0 +1,161 @@
+const MAX_COMPONENT_LEN: usize = 255;
+#[cfg(windows)]
+const REPLACEMENT_CHAR: char = '_';
+#[cfg(windows)]
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turns a raw path component of a .torrent file into a single, safe file or directory name.
+/// Components that could escape the download directory (`..`, separators, NUL bytes, drive prefixes)
+/// are rejected, while overlong names, invalid UTF-8 and, on Windows, reserved device names are rewritten.
+pub fn sanitize_component(bytes: &[u8]) -> Result<String, String> {
+    let component = String::from_utf8_lossy(bytes).replace(char::REPLACEMENT_CHARACTER, "_");
+    let err = |reason: &str| Err(format!("unsafe path component {component:?}: {reason}"));
+
+    if component.is_empty() || component == "." {
+        return err("empty names are not allowed");
+    }
+    if component == ".." {
+        return err("parent directory references are not allowed");
+    }
+    if component.contains(['/', '\\']) {
+        return err("path separators are not allowed");
+    }
+    if component.contains('\0') {
+        return err("NUL characters are not allowed");
+    }
+    if has_drive_prefix(&component) {
+        return err("drive prefixes are not allowed");
+    }
+
+    Ok(truncate(&windows_safe(&component)))
+}
+
+/// Windows drops trailing dots and spaces from names and reserves some of them for devices, so those
+/// names are rewritten there. Other platforms keep them, so the files match the ones of the torrent.
+#[cfg(windows)]
+fn windows_safe(component: &str) -> String {
+    rename_reserved(component.trim_end_matches(['.', ' ']))
+}
+
+#[cfg(not(windows))]
+fn windows_safe(component: &str) -> String {
+    component.to_string()
+}
+
+/// Returns true if `component` starts like a Windows drive, such as "C:".
+fn has_drive_prefix(component: &str) -> bool {
+    let mut chars = component.chars();
+    matches!(
+        (chars.next(), chars.next()),
+        (Some(letter), Some(':')) if letter.is_ascii_alphabetic()
+    )
+}
+
+/// Appends an underscore to names that Windows reserves for devices, such as "CON" or "LPT1.txt".
+#[cfg(windows)]
+fn rename_reserved(component: &str) -> String {
+    if component.is_empty() {
+        return REPLACEMENT_CHAR.to_string();
+    }
+
+    let (stem, extension) = match component.split_once('.') {
+        Some((stem, extension)) => (stem, Some(extension)),
+        None => (component, None),
+    };
+    if !RESERVED_NAMES.contains(&stem.to_ascii_uppercase().as_str()) {
+        return component.to_string();
+    }
+    match extension {
+        Some(extension) => format!("{stem}{REPLACEMENT_CHAR}.{extension}"),
+        None => format!("{stem}{REPLACEMENT_CHAR}"),
+    }
+}
+
+/// Shortens names longer than `MAX_COMPONENT_LEN` bytes, keeping their extension when it is short enough.
+fn truncate(component: &str) -> String {
+    if component.len() <= MAX_COMPONENT_LEN {
+        return component.to_string();
+    }
+
+    let extension = match component.rsplit_once('.') {
+        Some((_, extension)) if extension.len() < MAX_COMPONENT_LEN / 2 => format!(".{extension}"),
+        _ => String::new(),
+    };
+    let mut stem_len = MAX_COMPONENT_LEN - extension.len();
+    while !component.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    format!("{}{extension}", &component[..stem_len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regular_names_are_kept() -> Result<(), String> {
+        assert_eq!("main.rs", sanitize_component(b"main.rs")?);
+        assert_eq!(".hidden", sanitize_component(b".hidden")?);
+        assert_eq!("上海.txt", sanitize_component("上海.txt".as_bytes())?);
+        Ok(())
+    }
+
+    #[test]
+    fn traversal_and_absolute_components_are_rejected() {
+        for component in [
+            &b".."[..],
+            b".",
+            b"",
+            b"/etc",
+            b"sub/dir",
+            b"..\\windows",
+            b"C:",
+            b"c:evil",
+            b"nul\0byte",
+        ] {
+            assert!(sanitize_component(component).is_err(), "{component:?}");
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn reserved_names_are_rewritten() -> Result<(), String> {
+        assert_eq!("CON_", sanitize_component(b"CON")?);
+        assert_eq!("lpt1_.txt", sanitize_component(b"lpt1.txt")?);
+        assert_eq!("CONSOLE.txt", sanitize_component(b"CONSOLE.txt")?);
+        assert_eq!("file", sanitize_component(b"file. .")?);
+        assert_eq!("_", sanitize_component(b"...")?);
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn reserved_windows_names_are_kept_on_other_platforms() -> Result<(), String> {
+        assert_eq!("aux.rs", sanitize_component(b"aux.rs")?);
+        assert_eq!("CON", sanitize_component(b"CON")?);
+        assert_eq!("file. .", sanitize_component(b"file. .")?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() -> Result<(), String> {
+        assert_eq!("a_b.txt", sanitize_component(b"a\xffb.txt")?);
+        Ok(())
+    }
+
+    #[test]
+    fn overlong_names_are_truncated_keeping_their_extension() -> Result<(), String> {
+        let name = format!("{}.mkv", "a".repeat(300));
+        let sanitized = sanitize_component(name.as_bytes())?;
+        assert_eq!(MAX_COMPONENT_LEN, sanitized.len());
+        assert!(sanitized.ends_with("a.mkv"));
+
+        let name = "ñ".repeat(200);
+        let sanitized = sanitize_component(name.as_bytes())?;
+        assert!(sanitized.len() <= MAX_COMPONENT_LEN);
+        Ok(())
+    }
+}
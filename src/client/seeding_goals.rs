@@ -0,0 +1,122 @@
+use std::{str::FromStr, time::Duration};
+
+/// What is done with a torrent once it reaches a seeding goal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedingAction {
+    Pause,
+    Remove,
+    /// Removes the torrent and deletes every file it downloaded.
+    RemoveWithData,
+}
+
+impl FromStr for SeedingAction {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action.trim() {
+            "pause" => Ok(Self::Pause),
+            "remove" => Ok(Self::Remove),
+            "remove_with_data" => Ok(Self::RemoveWithData),
+            _ => Err(format!("Invalid seeding goal action: {action}")),
+        }
+    }
+}
+
+/// Goals that end the seeding of a completed torrent. The action is taken as soon as any of them is met,
+/// and goals that are `None` are never met.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeedingGoals {
+    /// Bytes uploaded for every byte downloaded.
+    pub ratio: Option<f64>,
+    /// How long the torrent seeds since it was completed.
+    pub seed_time: Option<Duration>,
+    /// How long the torrent seeds without uploading anything.
+    pub idle_time: Option<Duration>,
+    pub action: SeedingAction,
+}
+
+impl Default for SeedingGoals {
+    fn default() -> Self {
+        Self {
+            ratio: None,
+            seed_time: None,
+            idle_time: None,
+            action: SeedingAction::Pause,
+        }
+    }
+}
+
+impl SeedingGoals {
+    pub fn is_met(&self, stats: &SeedingStats) -> bool {
+        self.ratio.is_some_and(|ratio| stats.ratio() >= ratio)
+            || self
+                .seed_time
+                .is_some_and(|seed_time| stats.seed_time >= seed_time)
+            || self
+                .idle_time
+                .is_some_and(|idle_time| stats.idle_time >= idle_time)
+    }
+}
+
+/// How much a completed torrent seeded so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeedingStats {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    /// Size of the torrent, which the ratio is measured against when nothing was downloaded, as when its
+    /// data was already on disk.
+    pub size: usize,
+    pub seed_time: Duration,
+    pub idle_time: Duration,
+}
+
+impl SeedingStats {
+    pub fn ratio(&self) -> f64 {
+        let downloaded = match self.downloaded {
+            0 => self.size,
+            downloaded => downloaded,
+        };
+        self.uploaded as f64 / downloaded.max(1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_goal_that_is_met_ends_the_seeding() -> Result<(), String> {
+        let stats = SeedingStats {
+            uploaded: 3000,
+            downloaded: 0,
+            size: 2000,
+            seed_time: Duration::from_secs(600),
+            idle_time: Duration::from_secs(60),
+        };
+        assert_eq!(1.5, stats.ratio());
+
+        let goals = SeedingGoals {
+            ratio: Some(2.0),
+            seed_time: Some(Duration::from_secs(3600)),
+            ..SeedingGoals::default()
+        };
+        assert!(!goals.is_met(&stats));
+        assert!(!SeedingGoals::default().is_met(&stats));
+        assert!(SeedingGoals {
+            idle_time: Some(Duration::from_secs(60)),
+            ..goals
+        }
+        .is_met(&stats));
+        assert!(goals.is_met(&SeedingStats {
+            downloaded: 1000,
+            ..stats
+        }));
+
+        assert_eq!(
+            SeedingAction::RemoveWithData,
+            SeedingAction::from_str("remove_with_data")?
+        );
+        assert!(SeedingAction::from_str("delete").is_err());
+        Ok(())
+    }
+}
@@ -3,10 +3,45 @@
 pub struct SingleFile {
     pub length: i64,
     pub path: String,
+    /// The `attr` flags of the file (BEP 47): `p` padding, `x` executable, `h` hidden and `l` symbolic link.
+    pub attr: String,
+    /// Target of a symbolic link, with the same prefix as `path`.
+    pub symlink_path: Option<String>,
+    pub sha1: Option<[u8; 20]>,
 }
 
 impl SingleFile {
     pub fn new(length: i64, path: String) -> Self {
-        Self { length, path }
+        Self {
+            length,
+            path,
+            attr: String::new(),
+            symlink_path: None,
+            sha1: None,
+        }
     }
+
+    /// Padding files only align the next file to a piece boundary. Their bytes are zeros and never stored.
+    pub fn is_padding(&self) -> bool {
+        self.attr.contains('p')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.attr.contains('x')
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.attr.contains('l') && self.symlink_path.is_some()
+    }
+}
+
+/// Part of a piece that is stored in a single file.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FileSpan<'a> {
+    pub file: &'a SingleFile,
+    /// Offset of the span from the beginning of the file.
+    pub file_begin: usize,
+    /// Offset of the span from the beginning of the piece.
+    pub piece_begin: usize,
+    pub length: usize,
 }
@@ -1,26 +1,47 @@
 use crate::{
     client::{
+        peer_source::PeerSource,
         piece::Piece,
-        single_file::SingleFile,
-        torrent_decoding, tracker_decoding,
+        recheck_state::RecheckState,
+        seeding_goals::SeedingStats,
+        single_file::{FileSpan, SingleFile},
+        torrent_decoding,
+        torrent_handle::{TorrentHandle, TorrentStatus},
+        torrent_meta::TorrentMeta,
+        torrent_queue::QueueEntry,
+        torrent_v2::{TorrentVersion, V2Info},
+        tracker_decoding,
         tracker_info::{TrackerInfo, TrackerInfoState},
     },
     logging::log_handle::LogHandle,
+    network::{
+        ban_list::{BanList, Offense},
+        ip_filter::IpFilter,
+        rate_limit::{Bandwidth, RateLimits, Throttle},
+    },
     server::{server_side::Notification, upload::upload_info::UploadInfo},
+    storage::{
+        allocation::{self, AllocationMode},
+        disk_cache::DiskCache,
+        file_attributes,
+        piece_files::{FileRange, PieceInFiles},
+    },
     url_encoding, utils,
 };
 use native_tls::TlsConnector;
 use std::{
     collections::HashMap,
     fs::{self, OpenOptions},
-    io::{Error, Read, Write},
+    io::{Error, Read, Seek, SeekFrom, Write},
     net::TcpStream,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
-        mpsc::{self, Sender},
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
         Arc, Mutex,
     },
-    time::Instant,
+    thread,
+    time::{Duration, Instant},
 };
 
 use super::{
@@ -31,10 +52,18 @@ use super::{
         },
         download_worker_state::DownloadWorkerState,
         peer::Peer,
+        peer_manager::PeerManager,
         peer_protocol::ProtocolError,
+        web_seed::WebSeed,
     },
 };
 
+/// How often a worker waiting for a piece or a peer checks whether its torrent was stopped.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a worker waits between looks into an empty queue. The queue is only locked while looking into
+/// it, so sessions on the event loop and disconnects never wait for a worker.
+const QUEUE_WAIT_STEP: Duration = Duration::from_millis(10);
+
 /// Represents a web server complete address.
 #[derive(Debug)]
 struct ServerAddr {
@@ -59,21 +88,77 @@ impl ServerAddr {
     }
 }
 
+/// Counters shared by the threads that verify the pieces of a torrent during a recheck.
+struct RecheckProgress {
+    total: usize,
+    checked: AtomicUsize,
+    verified: AtomicUsize,
+}
+
+impl RecheckProgress {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            checked: AtomicUsize::new(0),
+            verified: AtomicUsize::new(0),
+        }
+    }
+
+    fn percentage(&self, checked: usize) -> usize {
+        (checked * 100) / self.total
+    }
+}
+
 /// Stores the information that a .torrent file contains.
 #[derive(Debug, Clone)]
 pub struct Torrent {
     name: String,
     announce: String,
+    merged_trackers: Vec<String>,
+    web_seeds: Vec<String>,
+    piece_length: usize,
     total_pieces: usize,
     files: Vec<SingleFile>,
     info_hash: [u8; 20],
+    v2: Option<Arc<V2Info>>,
     tracker_info: TrackerInfoState,
     piece_tx: PieceSender,
     piece_rx: PieceReceiver,
+    /// Peers that the event loop connects to.
     peer_tx: PeerSender,
     peer_rx: PeerReceiver,
+    /// Web seeds, which the download workers download from over HTTP.
+    web_seed_tx: PeerSender,
+    web_seed_rx: PeerReceiver,
     downloaded: DownloadedPieces,
     download_path: String,
+    disk_cache: DiskCache,
+    peer_manager: PeerManager,
+    rate_limits: RateLimits,
+    ban_list: BanList,
+    ip_filter: IpFilter,
+    meta: TorrentMeta,
+    handle: TorrentHandle,
+}
+
+/// Waits up to `timeout` for a message of a queue that several workers share. The queue is locked only while
+/// looking into it, so that nobody who takes from it without waiting finds it busy.
+fn wait_in_queue<T>(queue: &Mutex<Receiver<T>>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let received = match queue.lock() {
+            Ok(receiver) => receiver.try_recv(),
+            Err(_) => return Err(RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(message) => return Ok(message),
+            Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            Err(TryRecvError::Empty) if Instant::now() >= deadline => {
+                return Err(RecvTimeoutError::Timeout)
+            }
+            Err(TryRecvError::Empty) => thread::sleep(QUEUE_WAIT_STEP),
+        }
+    }
 }
 
 impl PartialEq for Torrent {
@@ -89,6 +174,7 @@ impl Torrent {
     pub fn new(
         name: String,
         announce: String,
+        piece_length: usize,
         pieces: Vec<Piece>,
         files: Vec<SingleFile>,
         info_hash: [u8; 20],
@@ -96,25 +182,49 @@ impl Torrent {
         let total_pieces = pieces.len();
         let (piece_tx, piece_rx) = Self::setup_pieces_queue(pieces)?;
         let (peer_tx, peer_rx) = Self::setup_peers_queue();
+        let (web_seed_tx, web_seed_rx) = Self::setup_peers_queue();
         let downloaded = Arc::new(Mutex::new(Vec::<Piece>::with_capacity(total_pieces)));
 
         Ok(Self {
             name,
             announce,
+            merged_trackers: Vec::new(),
+            web_seeds: Vec::new(),
+            piece_length,
             total_pieces,
             files,
             info_hash,
+            v2: None,
             tracker_info: TrackerInfoState::Unset,
             piece_tx,
             piece_rx,
             peer_tx,
             peer_rx,
+            web_seed_tx,
+            web_seed_rx,
             downloaded,
             download_path: String::new(),
+            disk_cache: DiskCache::default(),
+            peer_manager: PeerManager::default(),
+            rate_limits: RateLimits::default(),
+            ban_list: BanList::default(),
+            ip_filter: IpFilter::default(),
+            meta: TorrentMeta::default(),
+            handle: TorrentHandle::default(),
         })
     }
 
+    /// Returns the amount of bytes of the files of the torrent, without padding files.
     pub fn get_total_size(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| !file.is_padding())
+            .map(|f| f.length)
+            .sum::<i64>() as usize
+    }
+
+    /// Returns the amount of bytes that the pieces span, padding files included.
+    fn layout_size(&self) -> usize {
         self.files.iter().map(|f| f.length).sum::<i64>() as usize
     }
 
@@ -152,17 +262,52 @@ impl Torrent {
         peer_id: [u8; 20],
         port: u32,
     ) -> Result<TrackerInfo, String> {
-        let tracker_addr = self.tracker_address()?;
-        let query_dict = self.query_string_dict(peer_id, port)?;
-        let tracker_req = self.tracker_request(&tracker_addr.domain, query_dict);
-        let tracker_res = self.tracker_communication(tracker_addr, tracker_req)?;
-        tracker_decoding::from_bytes(self.response_body(tracker_res)?)
+        self.request_swarm_info(self.info_hash, peer_id, port)
     }
 
-    /// Attempts to create a valid `ServerAddr` struct from the announce field of `Torrent`.
-    fn tracker_address(&self) -> Result<ServerAddr, String> {
-        let tracker_addr = self.announce.replace("/announce", "");
+    /// Tells the tracker that the torrent stopped, so it is no longer handed out as a peer.
+    pub fn announce_stopped(&self, peer_id: [u8; 20], port: u32) -> Result<(), String> {
+        let mut query_dict = self.query_string_dict(self.info_hash, peer_id, port)?;
+        query_dict.insert("event", "stopped".to_string());
+        self.send_announce(query_dict).map(|_| ())
+    }
+
+    /// Requests the peers of the swarm identified by `info_hash`, which can be either of the two swarms of a hybrid torrent.
+    fn request_swarm_info(
+        &self,
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+        port: u32,
+    ) -> Result<TrackerInfo, String> {
+        let query_dict = self.query_string_dict(info_hash, peer_id, port)?;
+        tracker_decoding::from_bytes(self.send_announce(query_dict)?, &self.ip_filter)
+    }
+
+    /// Announces to the trackers of the torrent in order, until one of them accepts the request. It returns
+    /// the body of the response of that tracker, or the error of the last one if none did.
+    fn send_announce(&self, query_dict: HashMap<&str, String>) -> Result<Vec<u8>, String> {
+        let mut error = format!("{} has no trackers", self.name);
+        for tracker in self.trackers() {
+            let tracker_addr = self.tracker_address(&tracker)?;
+            let tracker_req = self.tracker_request(&tracker_addr.domain, query_dict.clone());
+            match self
+                .tracker_communication(tracker_addr, tracker_req)
+                .and_then(|tracker_res| self.response_body(tracker_res))
+            {
+                Ok(body) => return Ok(body),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// Attempts to create a valid `ServerAddr` struct from the announce URL of a tracker.
+    fn tracker_address(&self, announce: &str) -> Result<ServerAddr, String> {
+        let tracker_addr = announce.replace("/announce", "");
         let mut tracker_addr = tracker_addr.split("://").collect::<Vec<&str>>();
+        if tracker_addr.len() != 2 {
+            return Err(format!("Invalid tracker URL: {announce}"));
+        }
         tracker_addr.append(&mut tracker_addr[1].split(':').collect::<Vec<&str>>());
         tracker_addr.remove(1);
 
@@ -181,20 +326,26 @@ impl Torrent {
     }
 
     /// Attempts to create a `HashMap` including every key-value pair that the query string of the tracker request must have.
+    /// The transferred bytes are the totals of the torrent since the client started, so the tracker can keep
+    /// the ratio of the client.
     fn query_string_dict(
         &self,
+        info_hash: [u8; 20],
         peer_id: [u8; 20],
         port: u32,
     ) -> Result<HashMap<&str, String>, String> {
         let mut query_dict = HashMap::new();
-        query_dict.insert("info_hash", url_encoding::encode(self.info_hash)?);
+        query_dict.insert("info_hash", url_encoding::encode(info_hash)?);
         query_dict.insert("peer_id", url_encoding::encode(peer_id)?);
         query_dict.insert("port", port.to_string());
-        query_dict.insert("uploaded", 0.to_string());
-        query_dict.insert("downloaded", 0.to_string());
+        let bandwidth = self.rate_limits.torrent(self.info_hash)?;
+        query_dict.insert("uploaded", bandwidth.upload().total_bytes()?.to_string());
+        query_dict.insert(
+            "downloaded",
+            bandwidth.download().total_bytes()?.to_string(),
+        );
 
-        let left = self.files.iter().fold(0, |acc, file| acc + file.length);
-        query_dict.insert("left", left.to_string());
+        query_dict.insert("left", self.get_total_size().to_string());
         query_dict.insert("event", "started".to_string());
         query_dict.insert("compact", "1".to_string());
         Ok(query_dict)
@@ -264,160 +415,455 @@ impl Torrent {
             return Ok(());
         }
 
-        let tracker_info = self.request_tracker_info(client_id, 6881)?;
+        let tracker_info = match self.request_tracker_info(client_id, 6881) {
+            Ok(tracker_info) => tracker_info,
+            // Web seeds are enough to download the torrent while its tracker cannot be reached.
+            Err(_) if !self.web_seeds.is_empty() => TrackerInfo::new(0, Vec::new()),
+            Err(e) => return Err(e),
+        };
 
+        for url in &self.web_seeds {
+            if let Ok(web_seed) = WebSeed::new(url.clone()) {
+                self.add_peer(Peer::from_web_seed(web_seed), PeerSource::WebSeed)?;
+            }
+        }
         for peer in tracker_info.peers_list() {
-            self.peer_tx.send(peer).map_err(|e| e.to_string())?;
+            self.add_peer(peer, PeerSource::Tracker)?;
+        }
+
+        if let (TorrentVersion::Hybrid, Some(v2)) = (self.version(), &self.v2) {
+            let swarm_hash = v2.truncated_info_hash();
+            if let Ok(v2_tracker_info) = self.request_swarm_info(swarm_hash, client_id, 6881) {
+                for mut peer in v2_tracker_info.peers_list() {
+                    peer.set_swarm_hash(swarm_hash);
+                    self.add_peer(peer, PeerSource::Tracker)?;
+                }
+            }
         }
 
         self.tracker_info = TrackerInfoState::Set(tracker_info);
         Ok(())
     }
 
+    /// Queues a peer to download from, unless the torrent is private and the peer did not come from its trackers.
+    /// Returns true if the peer was queued.
+    pub fn add_peer(&self, peer: Peer, source: PeerSource) -> Result<bool, String> {
+        if !source.is_allowed_for(self.is_private()) || self.refuses(&peer.ip())? {
+            return Ok(false);
+        }
+        self.discard_peer(peer)?;
+        Ok(true)
+    }
+
+    /// Adds trackers that were not listed in the .torrent file, such as the ones of a magnet link.
+    /// Private torrents refuse them, since they must only announce to their own trackers.
+    pub fn merge_trackers(&mut self, trackers: Vec<String>) -> Result<(), String> {
+        if self.is_private() {
+            return Err(format!(
+                "{} is private and only announces to its own trackers",
+                self.name
+            ));
+        }
+        for tracker in trackers {
+            if tracker != self.announce && !self.merged_trackers.contains(&tracker) {
+                self.merged_trackers.push(tracker);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every tracker the torrent announces to, starting with the one of its .torrent file. Private
+    /// torrents only announce to that one.
+    pub fn trackers(&self) -> Vec<String> {
+        let mut trackers = vec![self.announce.clone()];
+        if !self.is_private() {
+            trackers.extend(self.merged_trackers.iter().cloned());
+        }
+        trackers
+    }
+
+    /// Sets the URLs of the `url-list` of the .torrent file, which are used as peers once the torrent starts.
+    pub fn set_web_seeds(&mut self, web_seeds: Vec<String>) {
+        self.web_seeds = web_seeds
+    }
+
+    pub fn web_seeds(&self) -> &[String] {
+        &self.web_seeds
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.meta.private
+    }
+
+    /// Lays out every file of the torrent in its download directory as `mode` describes.
+    /// It fails before creating anything if the disk does not have room for the whole torrent.
+    pub fn allocate_files(&self, mode: AllocationMode) -> Result<(), String> {
+        let download_dir = Path::new(&self.download_path);
+        fs::create_dir_all(download_dir).map_err(|e| e.to_string())?;
+
+        let required = self
+            .stored_files()
+            .map(|file| allocation::missing_bytes(&self.file_path(file), file.length as u64))
+            .sum();
+        allocation::check_free_space(download_dir, required)
+            .map_err(|e| format!("Cannot download {}: {e}", self.name))?;
+
+        for file in self.stored_files() {
+            allocation::allocate(&self.file_path(file), file.length as u64, mode)?;
+        }
+        Ok(())
+    }
+
+    fn file_path(&self, file: &SingleFile) -> PathBuf {
+        Path::new(&self.download_path).join(&file.path)
+    }
+
+    /// Returns the files whose bytes are written to disk, which excludes padding files and symbolic links.
+    fn stored_files(&self) -> impl Iterator<Item = &SingleFile> {
+        self.files
+            .iter()
+            .filter(|file| !file.is_padding() && !file.is_symlink())
+    }
+
+    /// Copies the bytes of every downloaded piece into the files of the torrent. Each file is filled from
+    /// the pieces that overlap with it, so files that start in the middle of a piece are supported.
+    /// Padding files are skipped, and symbolic links are created once every other file is in place.
     pub fn build_files(&self) -> Result<(), String> {
-        let mut current_piece_index = 0_usize;
-        let mut piece_offset = 0;
+        let err = |e: Error| e.to_string();
+        let mut file_begin = 0;
 
         for file in &self.files {
+            if file.is_padding() || file.is_symlink() {
+                file_begin += file.length as usize;
+                continue;
+            }
+
+            let file_path = self.file_path(file);
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent).map_err(err)?;
+            }
+
             let mut opened_file = OpenOptions::new()
                 .create(true)
-                .append(true)
-                .open(format!("{}/{}", self.download_path, &file.path))
-                .map_err(|e| e.to_string())?;
-
-            let mut saved_file_length = 0_usize;
-            let file_length = file.length as usize;
-
-            while saved_file_length < file_length {
-                let str_path = format!("{}/.tmp", self.download_path);
-                let read_bytes = utils::read_piece_file(str_path, current_piece_index)?;
-                let missing_bytes = file_length - saved_file_length;
-
-                if (read_bytes.len() - piece_offset) > missing_bytes {
-                    utils::append_to_file(
-                        &mut opened_file,
-                        read_bytes[piece_offset..(piece_offset + missing_bytes)].to_vec(),
-                    )?;
-                    saved_file_length += missing_bytes - piece_offset;
-                    piece_offset += missing_bytes;
-                } else {
-                    utils::append_to_file(
-                        &mut opened_file,
-                        read_bytes[piece_offset..read_bytes.len()].to_vec(),
-                    )?;
-                    saved_file_length += read_bytes.len() - piece_offset;
-                    piece_offset = 0;
-                    current_piece_index += 1;
+                .write(true)
+                .truncate(false)
+                .open(&file_path)
+                .map_err(err)?;
+            opened_file.set_len(file.length as u64).map_err(err)?;
+
+            let file_end = file_begin + file.length as usize;
+            let first_piece = file_begin / self.piece_length;
+            let last_piece = utils::round_up(file_end, self.piece_length) / self.piece_length;
+
+            for piece_index in first_piece..last_piece {
+                // Pieces that a recheck found in the files are already where they belong.
+                if self
+                    .disk_cache
+                    .is_in_files(&self.download_path, piece_index)?
+                {
+                    continue;
+                }
+                let piece_begin = piece_index * self.piece_length;
+                let read_bytes =
+                    utils::read_piece_file(format!("{}/.tmp", self.download_path), piece_index)?;
+                let begin = piece_begin.max(file_begin);
+                let end = (piece_begin + read_bytes.len()).min(file_end);
+                if begin >= end {
+                    continue;
                 }
+
+                opened_file
+                    .seek(SeekFrom::Start((begin - file_begin) as u64))
+                    .map_err(err)?;
+                utils::append_to_file(
+                    &mut opened_file,
+                    read_bytes[(begin - piece_begin)..(end - piece_begin)].to_vec(),
+                )?;
+            }
+            self.apply_attributes(file, &file_path)?;
+            file_begin = file_end;
+        }
+
+        for file in self.files.iter().filter(|file| file.is_symlink()) {
+            if let Some(target) = &file.symlink_path {
+                file_attributes::create_symlink(
+                    Path::new(&self.download_path),
+                    &file.path,
+                    target,
+                )?;
             }
         }
         Ok(())
     }
 
+    /// Marks a built file as executable if its attributes say so, and checks its whole-file hash if it has one.
+    fn apply_attributes(&self, file: &SingleFile, file_path: &Path) -> Result<(), String> {
+        if file.is_executable() {
+            file_attributes::make_executable(file_path)?;
+        }
+        match file.sha1 {
+            Some(sha1) if file_attributes::file_sha1(file_path)? != sha1 => Err(format!(
+                "{} does not match the hash of the torrent",
+                file.path
+            )),
+            _ => Ok(()),
+        }
+    }
+
     pub fn info_hash(&self) -> [u8; 20] {
         self.info_hash
     }
 
+    pub fn set_v2(&mut self, v2: V2Info) {
+        self.v2 = Some(Arc::new(v2))
+    }
+
+    pub fn v2(&self) -> Option<&V2Info> {
+        self.v2.as_deref()
+    }
+
+    /// Pure v2 torrents are identified by their truncated v2 info hash, while hybrid ones keep the v1 hash.
+    pub fn version(&self) -> TorrentVersion {
+        match &self.v2 {
+            None => TorrentVersion::V1,
+            Some(v2) if v2.truncated_info_hash() == self.info_hash => TorrentVersion::V2,
+            Some(_) => TorrentVersion::Hybrid,
+        }
+    }
+
     pub fn total_pieces(&self) -> usize {
         self.total_pieces
     }
 
+    #[cfg(test)]
+    pub(crate) fn files(&self) -> &[SingleFile] {
+        &self.files
+    }
+
+    /// Returns the name of the directory that the torrent is downloaded to, inside the download directory.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn save_in(&mut self, path: String) {
         self.download_path = format!("{}/{}", path, self.name)
     }
 
+    pub fn set_disk_cache(&mut self, disk_cache: DiskCache) {
+        self.disk_cache = disk_cache
+    }
+
+    pub fn set_peer_manager(&mut self, peer_manager: PeerManager) {
+        self.peer_manager = peer_manager;
+    }
+
+    pub fn set_rate_limits(&mut self, rate_limits: RateLimits) {
+        self.rate_limits = rate_limits;
+    }
+
+    pub fn set_ban_list(&mut self, ban_list: BanList) {
+        self.ban_list = ban_list;
+    }
+
+    pub fn set_ip_filter(&mut self, ip_filter: IpFilter) {
+        self.ip_filter = ip_filter;
+    }
+
+    /// Returns how many peers the event loop downloads the torrent from at once.
+    pub fn target_connections(&self) -> usize {
+        self.peer_manager.target_connections()
+    }
+
+    /// Returns true if the peer at `ip` is banned or blocked by the IP filter, so the torrent neither
+    /// connects to it nor downloads from it.
+    pub fn refuses(&self, ip: &str) -> Result<bool, String> {
+        Ok(self.ban_list.is_banned(ip)? || self.ip_filter.is_blocked(ip)?)
+    }
+
+    /// Returns the throttle of a session with a peer whose own limiters are `peer`.
+    pub fn throttle(&self, peer: &Bandwidth) -> Result<Throttle, String> {
+        self.rate_limits.throttle(self.info_hash, peer)
+    }
+
+    pub fn set_meta(&mut self, meta: TorrentMeta) {
+        self.meta = meta
+    }
+
+    pub fn meta(&self) -> &TorrentMeta {
+        &self.meta
+    }
+
+    /// Downloads from the web seeds of the torrent until it has `pieces_to_download` more pieces, counting the
+    /// ones that the event loop downloads from its peers meanwhile, or until it stops or completes. Peers
+    /// that talk the peer protocol are connected to by the event loop, so no thread waits on them.
     pub fn download(
-        &mut self,
+        &self,
         pieces_to_download: usize,
-        client_id: [u8; 20],
         downloaded_torrents_mutex: DownloadedTorrents,
         notif_tx: Sender<Notification>,
         log_handle: &LogHandle,
         download_worker_id: usize,
     ) -> Result<(), String> {
-        let (mut have_piece, mut have_peer) = (None, None);
-        let mut download_counter = 0;
+        let slice_end = self.downloaded_count()? + pieces_to_download;
+        let mut web_seed = None;
 
-        while download_counter < pieces_to_download {
+        let result = loop {
             if self.all_pieces_downloaded()? {
-                return self.finish_download(downloaded_torrents_mutex);
+                break self.finish_download(downloaded_torrents_mutex);
             }
-
-            let mut piece = self.get_new_piece(have_piece.take())?;
-            let mut peer = self.get_new_peer(have_peer.take())?;
-            let downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
-            if (self.total_pieces - downloaded.len()) <= 20 {
-                self.discard_piece(piece.clone())?
+            if !self.handle.is_active() || self.downloaded_count()? >= slice_end {
+                break Ok(());
             }
-            drop(downloaded);
-
-            match peer.download(&mut piece, self, client_id, log_handle) {
-                Ok(()) => {
-                    let last_download_time = Instant::now();
-                    let final_path = format!("{}/.tmp/{}", self.download_path, piece.index());
-                    if Path::new(&final_path).is_file() {
-                        have_peer = Some(peer);
-                        continue;
-                    }
-
-                    self.save_piece(&piece)?;
-                    self.notify_piece(piece.clone(), notif_tx.clone())?;
-                    self.update_status(piece, log_handle.clone())?;
-                    download_counter += 1;
-                    let downloaded_pieces = self.downloaded.lock().map_err(|e| e.to_string())?;
-                    let current_pieces = downloaded_pieces.len();
-
-                    drop(downloaded_pieces);
-
-                    let mut total_peers = 0;
-                    if let TrackerInfoState::Set(tracker_info) = self.tracker_info.clone() {
-                        total_peers = tracker_info.peers_list().len()
-                    }
-
-                    let mut new_state = DownloadWorkerState::new(
-                        download_worker_id,
-                        self.info_hash,
-                        peer.clone(),
-                        self.name.clone(),
-                        self.total_pieces,
-                        Some(last_download_time),
-                        total_peers,
-                    );
-                    new_state.total_size = self.get_total_size();
-                    new_state.set_am_interested(peer.am_interested());
-                    new_state.set_am_choked(peer.am_choked());
-                    new_state.set_downloaded_pieces(current_pieces);
-                    new_state.set_is_interested(peer.is_interested());
-                    new_state.set_is_choked(peer.is_choked());
+            web_seed = match self.get_web_seed(web_seed.take())? {
+                Some(peer) => {
+                    self.download_from_web_seed(peer, &notif_tx, log_handle, download_worker_id)?
+                }
+                None => None,
+            };
+        };
+        if let Some(peer) = web_seed {
+            self.discard_peer(peer)?;
+        }
+        result
+    }
 
+    /// Downloads the next queued piece from a web seed. Returns the web seed if it can be used right away
+    /// for the next piece.
+    fn download_from_web_seed(
+        &self,
+        mut peer: Peer,
+        notif_tx: &Sender<Notification>,
+        log_handle: &LogHandle,
+        session_id: usize,
+    ) -> Result<Option<Peer>, String> {
+        let mut piece = match wait_in_queue(&self.piece_rx, QUEUE_POLL_INTERVAL) {
+            Ok(DownloadMessage::Piece(piece)) => piece,
+            // Killed workers check whether the torrent was stopped before they wait again.
+            Ok(DownloadMessage::Kill) | Err(RecvTimeoutError::Timeout) => return Ok(Some(peer)),
+            Err(e) => return Err(e.to_string()),
+        };
+        let downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
+        if (self.total_pieces - downloaded.len()) <= 20 {
+            self.discard_piece(piece.clone())?
+        }
+        drop(downloaded);
+
+        match peer.download(&mut piece, self) {
+            Ok(()) => {
+                let last_download_time = Instant::now();
+                if self.store_piece(piece, notif_tx, log_handle)? {
+                    let new_state =
+                        self.worker_state(session_id, &peer, Some(last_download_time))?;
                     notif_tx
                         .send(Notification::UpdateUi(new_state))
                         .map_err(|err| err.to_string())?;
-                    have_peer = Some(peer);
                 }
-                Err(ProtocolError::Piece(_)) => {
-                    self.discard_piece(piece)?;
-                    have_peer = Some(peer);
-                }
-                Err(ProtocolError::Peer(_)) => {
-                    self.discard_peer(peer)?;
-                    have_piece = Some(piece);
+                Ok(Some(peer))
+            }
+            Err(ProtocolError::Offense(offense, e)) => {
+                log_handle.log(&format!("Web seed {} misbehaved: {e}", peer.address()))?;
+                // Blocks from a web seed that misbehaved are not trusted, so the piece starts over.
+                let contributors = piece.reset();
+                let offenders = match offense {
+                    Offense::BadPiece => contributors,
+                    _ => vec![peer.ip()],
+                };
+                self.ban_list.report(&offenders, offense, log_handle)?;
+                self.discard_piece(piece)?;
+                // Banned web seeds are dropped for good.
+                match self.ban_list.is_banned(&peer.ip())? {
+                    true => Ok(None),
+                    false => Ok(Some(peer)),
                 }
             }
+            // Web seeds back off after failed requests, which the queue waits for.
+            Err(_) => {
+                self.discard_piece(piece)?;
+                self.discard_peer(peer)?;
+                Ok(None)
+            }
         }
+    }
 
-        if let Some(piece) = have_piece {
-            self.discard_piece(piece)?;
+    /// Describes the session with a peer, so the UI shows it.
+    pub(crate) fn worker_state(
+        &self,
+        session_id: usize,
+        peer: &Peer,
+        last_download_time: Option<Instant>,
+    ) -> Result<DownloadWorkerState, String> {
+        let downloaded_pieces = self.downloaded.lock().map_err(|e| e.to_string())?;
+        let current_pieces = downloaded_pieces.len();
+        drop(downloaded_pieces);
+
+        let mut total_peers = 0;
+        if let TrackerInfoState::Set(tracker_info) = &self.tracker_info {
+            total_peers = tracker_info.peers_list().len()
         }
-        if let Some(peer) = have_peer {
-            self.discard_peer(peer)?;
+
+        let mut new_state = DownloadWorkerState::new(
+            session_id,
+            self.info_hash,
+            peer.clone(),
+            self.name.clone(),
+            self.total_pieces,
+            last_download_time,
+            total_peers,
+        );
+        new_state.total_size = self.get_total_size();
+        new_state.set_meta(self.meta.clone());
+        new_state.set_am_interested(peer.am_interested());
+        new_state.set_am_choked(peer.am_choked());
+        new_state.set_downloaded_pieces(current_pieces);
+        new_state.set_is_interested(peer.is_interested());
+        new_state.set_is_choked(peer.is_choked());
+        new_state.set_choker_flags(false, peer.is_snubbed(Instant::now()));
+        Ok(new_state)
+    }
+
+    /// Saves a piece that a peer finished sending and starts serving it. Returns false if the piece was
+    /// already saved, as when two peers sent it.
+    fn store_piece(
+        &self,
+        piece: Piece,
+        notif_tx: &Sender<Notification>,
+        log_handle: &LogHandle,
+    ) -> Result<bool, String> {
+        if self
+            .disk_cache
+            .has_piece(&self.download_path, piece.index())?
+        {
+            return Ok(false);
+        }
+
+        self.save_piece(&piece)?;
+        self.notify_piece(piece.clone(), notif_tx.clone())?;
+        self.update_status(piece, log_handle.clone())?;
+        Ok(true)
+    }
+
+    /// Stores a piece that was downloaded outside of the download sessions of the torrent, as by the
+    /// sessions of peers that connected to the client, and finishes the download with the last one.
+    pub(crate) fn complete_piece(
+        &self,
+        piece: Piece,
+        notif_tx: &Sender<Notification>,
+        log_handle: &LogHandle,
+        downloaded_torrents_mutex: DownloadedTorrents,
+    ) -> Result<(), String> {
+        self.store_piece(piece, notif_tx, log_handle)?;
+        if self.all_pieces_downloaded()? {
+            self.finish_download(downloaded_torrents_mutex)?;
         }
         Ok(())
     }
 
-    fn all_pieces_downloaded(&self) -> Result<bool, String> {
-        let downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
-        Ok(downloaded.len() == self.total_pieces)
+    pub(crate) fn all_pieces_downloaded(&self) -> Result<bool, String> {
+        Ok(self.downloaded_count()? == self.total_pieces)
+    }
+
+    fn downloaded_count(&self) -> Result<usize, String> {
+        Ok(self.downloaded.lock().map_err(|e| e.to_string())?.len())
     }
 
     fn finish_download(&self, downloaded_torrents_mutex: DownloadedTorrents) -> Result<(), String> {
@@ -427,69 +873,396 @@ impl Torrent {
 
         let am_downloaded = downloaded_torrents.iter().any(|torr| *torr == *self);
         if !am_downloaded {
+            self.disk_cache.flush()?;
             self.build_files()?;
+            self.handle.start_seeding(Instant::now())?;
             downloaded_torrents.push(self.clone());
         }
         Ok(())
     }
 
-    fn get_new_piece(&self, have_piece: Option<Piece>) -> Result<Piece, String> {
-        have_piece.map_or_else(
-            || {
-                let piece_rx = self.piece_rx.lock().map_err(|e| e.to_string())?;
-                let message = piece_rx.recv().map_err(|e| e.to_string())?;
-                drop(piece_rx);
+    /// Returns what the queue needs to know about the torrent to decide whether it runs.
+    pub fn queue_entry(&self) -> Result<QueueEntry, String> {
+        let bandwidth = self.rate_limits.torrent(self.info_hash)?;
+        let last_transfer = bandwidth
+            .upload()
+            .last_transfer()?
+            .max(bandwidth.download().last_transfer()?);
+
+        Ok(QueueEntry {
+            info_hash: self.info_hash,
+            running: self.handle.status()? == TorrentStatus::Active,
+            seeding: self.all_pieces_downloaded()?,
+            last_transfer,
+        })
+    }
+
+    /// Returns how much the torrent seeded since it was completed, or `None` if it is still downloading.
+    /// The idle time counts from the last upload, or from the completion if nothing was uploaded since.
+    pub fn seeding_stats(&self, now: Instant) -> Result<Option<SeedingStats>, String> {
+        let Some(since) = self.handle.seeding_since()? else {
+            return Ok(None);
+        };
+        let bandwidth = self.rate_limits.torrent(self.info_hash)?;
+        let last_upload = bandwidth
+            .upload()
+            .last_transfer()?
+            .map_or(since, |last| last.max(since));
+
+        Ok(Some(SeedingStats {
+            uploaded: bandwidth.upload().total_bytes()?,
+            downloaded: bandwidth.download().total_bytes()?,
+            size: self.get_total_size(),
+            seed_time: now.saturating_duration_since(since),
+            idle_time: now.saturating_duration_since(last_upload),
+        }))
+    }
+
+    /// Waits for a web seed to download from. Returns `None` if none can be used yet, so the worker checks
+    /// whether the torrent stopped or its time slice ended before waiting again. Web seeds that back off
+    /// after failed requests go back to the queue until they can be retried.
+    fn get_web_seed(&self, have_peer: Option<Peer>) -> Result<Option<Peer>, String> {
+        if have_peer.is_some() {
+            return Ok(have_peer);
+        }
+
+        match wait_in_queue(&self.web_seed_rx, QUEUE_POLL_INTERVAL) {
+            // Web seeds that were banned or filtered after they were queued are dropped.
+            Ok(peer) if self.refuses(&peer.ip())? => Ok(None),
+            Ok(peer) if !peer.backoff().is_zero() => {
+                let backoff = peer.backoff();
+                self.discard_peer(peer)?;
+                thread::sleep(backoff.min(QUEUE_POLL_INTERVAL));
+                Ok(None)
+            }
+            Ok(peer) => Ok(Some(peer)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Takes the next queued peer to connect to without waiting, as the event loop cannot block. Peers that
+    /// were banned or filtered after they were queued are dropped.
+    pub(crate) fn take_peer(&self) -> Result<Option<Peer>, String> {
+        let peer_rx = self.peer_rx.lock().map_err(|e| e.to_string())?;
+        while let Ok(peer) = peer_rx.try_recv() {
+            if !self.refuses(&peer.ip())? {
+                return Ok(Some(peer));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Takes the first queued piece that the peer has without waiting, as sessions that run on an event loop
+    /// cannot block. Workers never wait while holding the queue, so locking it is brief.
+    pub(crate) fn take_piece_for(&self, peer: &Peer) -> Result<Option<Piece>, String> {
+        let piece_rx = self.piece_rx.lock().map_err(|e| e.to_string())?;
+        self.take_queued_piece(&piece_rx, Vec::new(), |piece| peer.has_piece(piece.index()))
+    }
 
-                match message {
-                    DownloadMessage::Piece(piece) => Ok(piece),
-                    DownloadMessage::Kill => Err("Ending download".to_string()),
+    /// Scans the queue for the first piece that `wanted` accepts. Skipped pieces go back to the queue.
+    fn take_queued_piece(
+        &self,
+        piece_rx: &Receiver<DownloadMessage>,
+        mut skipped: Vec<Piece>,
+        wanted: impl Fn(&Piece) -> bool,
+    ) -> Result<Option<Piece>, String> {
+        let mut assigned = None;
+        while let Ok(message) = piece_rx.try_recv() {
+            match message {
+                DownloadMessage::Piece(piece) if wanted(&piece) => {
+                    assigned = Some(piece);
+                    break;
                 }
-            },
-            Ok,
-        )
+                DownloadMessage::Piece(piece) => skipped.push(piece),
+                DownloadMessage::Kill => {
+                    // Leaves the message for the session that waits for the next piece.
+                    self.piece_tx
+                        .send(DownloadMessage::Kill)
+                        .map_err(|e| e.to_string())?;
+                    break;
+                }
+            }
+        }
+
+        for piece in skipped {
+            self.discard_piece(piece)?;
+        }
+        Ok(assigned)
     }
 
-    fn get_new_peer(&self, have_peer: Option<Peer>) -> Result<Peer, String> {
-        have_peer.map_or_else(
-            || {
-                let peer_rx = self.peer_rx.lock().map_err(|e| e.to_string())?;
-                peer_rx.recv().map_err(|e| e.to_string())
-            },
-            Ok,
-        )
+    pub fn handle(&self) -> TorrentHandle {
+        self.handle.clone()
+    }
+
+    /// Wakes up to `workers` download workers that wait for a piece of this torrent, so they notice it was stopped.
+    pub fn kill_workers(&self, workers: usize) -> Result<(), String> {
+        for _ in 0..workers {
+            self.piece_tx
+                .send(DownloadMessage::Kill)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Writes every buffered piece to disk once the torrent stops. The event loop closes the connections to
+    /// its peers on their next tick.
+    pub fn flush_pieces(&self) -> Result<(), String> {
+        self.disk_cache.flush()
+    }
+
+    /// Starts serving every piece that was already downloaded again, as when the torrent is resumed.
+    pub fn notify_downloaded(&self, notif_tx: &Sender<Notification>) -> Result<(), String> {
+        let downloaded = self.downloaded.lock().map_err(|e| e.to_string())?.clone();
+        for piece in downloaded {
+            self.notify_piece(piece, notif_tx.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Deletes every file of the torrent, along with its downloaded pieces.
+    pub fn delete_data(&self) -> Result<(), String> {
+        self.disk_cache.forget(&self.download_path)?;
+        let download_path = Path::new(&self.download_path);
+        if download_path.exists() {
+            fs::remove_dir_all(download_path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
     }
 
     fn save_piece(&self, piece: &Piece) -> Result<(), String> {
-        let err = |e: Error| e.to_string();
+        self.disk_cache
+            .write_piece(&self.download_path, piece.index(), piece.bytes())
+    }
+
+    /// Returns true if any of the files of the torrent is already present in its download directory.
+    pub fn has_data_on_disk(&self) -> bool {
+        self.stored_files()
+            .any(|file| Path::new(&format!("{}/{}", self.download_path, file.path)).is_file())
+    }
+
+    /// Verifies the data that is already present in the download directory against the hash of every piece.
+    /// Pieces that match are stored and served as if they had been downloaded, and only the ones that fail
+    /// are queued again for download. Progress is reported through `notif_tx`.
+    /// It returns the amount of verified pieces.
+    pub fn recheck(
+        &self,
+        notif_tx: Sender<Notification>,
+        log_handle: &LogHandle,
+    ) -> Result<usize, String> {
+        let (pieces, kill_messages) = self.drain_pieces_queue()?;
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = utils::round_up(pieces.len().max(1), threads) / threads;
+        let progress = RecheckProgress::new(pieces.len());
+
+        log_handle.log(&format!(
+            "Rechecking {} pieces of {}",
+            progress.total, self.name
+        ))?;
+
+        let result = thread::scope(|scope| {
+            let handles: Vec<_> = pieces
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let notif_tx = notif_tx.clone();
+                    let progress = &progress;
+                    scope.spawn(move || self.recheck_chunk(chunk, progress, &notif_tx))
+                })
+                .collect();
+
+            // Every thread is joined before the pieces go back to the queue, even after one of them failed.
+            let mut result = Ok(());
+            for handle in handles {
+                let joined = handle
+                    .join()
+                    .map_err(|_| "Error joining recheck thread".to_string());
+                result = result.and(joined.and_then(|chunk_result| chunk_result));
+            }
+            result
+        });
+
+        // The queue was drained for the recheck, so the pieces that were not verified go back to it and the
+        // stopped workers are woken even if the recheck failed.
+        let downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
+        let unverified: Vec<Piece> = pieces
+            .into_iter()
+            .filter(|piece| !downloaded.iter().any(|d| d.index() == piece.index()))
+            .collect();
+        drop(downloaded);
+        for piece in unverified {
+            self.discard_piece(piece)?;
+        }
+        for message in kill_messages {
+            self.piece_tx.send(message).map_err(|e| e.to_string())?;
+        }
+        result?;
+
+        let verified = progress.verified.into_inner();
+        log_handle.log(&format!(
+            "Recheck of {} finished: {verified}/{} pieces verified",
+            self.name, progress.total
+        ))?;
+        Ok(verified)
+    }
+
+    fn recheck_chunk(
+        &self,
+        chunk: &[Piece],
+        progress: &RecheckProgress,
+        notif_tx: &Sender<Notification>,
+    ) -> Result<(), String> {
+        for piece in chunk {
+            if self.recheck_piece(piece, notif_tx)? {
+                progress.verified.fetch_add(1, Ordering::SeqCst);
+            }
+
+            let checked = progress.checked.fetch_add(1, Ordering::SeqCst) + 1;
+            if progress.percentage(checked) > progress.percentage(checked - 1) {
+                let verified = progress.verified.load(Ordering::SeqCst);
+                self.notify_recheck(progress.total, checked, verified, notif_tx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes every piece that is waiting in the download queue.
+    fn drain_pieces_queue(&self) -> Result<(Vec<Piece>, Vec<DownloadMessage>), String> {
+        let piece_rx = self.piece_rx.lock().map_err(|e| e.to_string())?;
+        let (mut pieces, mut others) = (Vec::new(), Vec::new());
+
+        loop {
+            match piece_rx.try_recv() {
+                Ok(DownloadMessage::Piece(piece)) => pieces.push(piece),
+                Ok(message) => others.push(message),
+                Err(TryRecvError::Empty) => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok((pieces, others))
+    }
 
-        let final_path = format!("{}/.tmp/{}", self.download_path, piece.index());
-        if let Some(path) = Path::new(&final_path).parent() {
-            fs::create_dir_all(path).map_err(err)?
+    /// Reads a piece from the files on disk and, if its hash matches, marks it as downloaded. The piece is
+    /// served from the files, so it is not copied anywhere.
+    fn recheck_piece(
+        &self,
+        piece: &Piece,
+        notif_tx: &Sender<Notification>,
+    ) -> Result<bool, String> {
+        let in_files = self.piece_in_files(piece);
+        let bytes = match in_files.read() {
+            Ok(bytes) => bytes,
+            Err(_) => return Ok(false),
         };
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(final_path)
-            .map_err(err)?;
-        file.write_all(&piece.bytes()).map_err(err)
+        if !piece.hash().matches(&bytes) {
+            return Ok(false);
+        }
+
+        self.disk_cache
+            .keep_in_files(&self.download_path, piece.index(), in_files)?;
+        self.notify_piece(piece.clone(), notif_tx.clone())?;
+        let mut downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
+        downloaded.push(piece.clone());
+        Ok(true)
     }
 
-    fn notify_piece(&self, piece: Piece, notif_tx: Sender<Notification>) -> Result<(), String> {
-        let upload = UploadInfo::new(
+    /// Returns the parts of a piece that each file holds, in order. Padding files are left out, since their
+    /// bytes are always zeros.
+    pub fn piece_spans(&self, piece: &Piece) -> Vec<FileSpan<'_>> {
+        let piece_begin = piece.index() * self.piece_length;
+        let piece_end = (piece_begin + piece.size()).min(self.layout_size());
+        let mut spans = Vec::new();
+        let mut file_begin = 0;
+
+        for file in &self.files {
+            let file_end = file_begin + file.length as usize;
+            let (begin, end) = (piece_begin.max(file_begin), piece_end.min(file_end));
+
+            if begin < end && !file.is_padding() {
+                spans.push(FileSpan {
+                    file,
+                    file_begin: begin - file_begin,
+                    piece_begin: begin - piece_begin,
+                    length: end - begin,
+                });
+            }
+            file_begin = file_end;
+        }
+        spans
+    }
+
+    /// Locates the bytes that a piece spans across the files of the torrent.
+    fn piece_in_files(&self, piece: &Piece) -> PieceInFiles {
+        let ranges = self
+            .piece_spans(piece)
+            .into_iter()
+            .map(|span| FileRange {
+                path: self.file_path(span.file),
+                file_begin: span.file_begin as u64,
+                piece_begin: span.piece_begin,
+                length: span.length,
+            })
+            .collect();
+        PieceInFiles {
+            size: piece.size(),
+            ranges,
+        }
+    }
+
+    fn notify_recheck(
+        &self,
+        rechecked_pieces: usize,
+        checked_pieces: usize,
+        verified_pieces: usize,
+        notif_tx: &Sender<Notification>,
+    ) -> Result<(), String> {
+        let mut state = RecheckState::new(
+            self.info_hash,
+            self.name.clone(),
+            self.total_pieces,
+            self.get_total_size(),
+            rechecked_pieces,
+        );
+        state.set_meta(self.meta.clone());
+        state.set_checked_pieces(checked_pieces);
+        state.set_verified_pieces(verified_pieces);
+
+        notif_tx
+            .send(Notification::Recheck(state))
+            .map_err(|e| e.to_string())
+    }
+
+    fn new_upload_info(&self) -> UploadInfo {
+        let mut upload = UploadInfo::new(
             self.info_hash,
             self.download_path.clone(),
             self.total_pieces,
         );
+        if let Some(v2) = &self.v2 {
+            upload.set_v2(v2.clone());
+        }
+        upload
+    }
 
+    /// Returns what peers can download from the client, which is every piece that was downloaded so far.
+    pub fn upload_info(&self) -> Result<UploadInfo, String> {
+        let mut upload = self.new_upload_info();
+        let downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
+        for piece in downloaded.iter() {
+            upload.add_piece_to_bitfield(piece.index())?;
+        }
+        Ok(upload)
+    }
+
+    fn notify_piece(&self, piece: Piece, notif_tx: Sender<Notification>) -> Result<(), String> {
         notif_tx
-            .send(Notification::NewPiece(piece, upload))
+            .send(Notification::NewPiece(piece, self.new_upload_info()))
             .map_err(|e| e.to_string())
     }
 
     fn update_status(&self, piece: Piece, log_handle: LogHandle) -> Result<(), String> {
         let mut downloaded = self.downloaded.lock().map_err(|e| e.to_string())?;
-        downloaded.push(piece.clone());
+        downloaded.push(Piece::with_hash(piece.index(), piece.size(), piece.hash()));
 
         let current_pieces = downloaded.len();
         let status = (current_pieces as f32 * 100_f32) / (self.total_pieces as f32);
@@ -503,24 +1276,101 @@ impl Torrent {
         log_handle.log(&msg)
     }
 
-    fn discard_piece(&self, piece: Piece) -> Result<(), String> {
+    /// Puts a piece back in the queue, so another session downloads it.
+    pub(crate) fn discard_piece(&self, piece: Piece) -> Result<(), String> {
         self.piece_tx
             .send(DownloadMessage::Piece(piece))
             .map_err(|e| e.to_string())
     }
 
-    fn discard_peer(&self, peer: Peer) -> Result<(), String> {
-        self.peer_tx.send(peer).map_err(|e| e.to_string())
+    /// Puts a peer back in its queue: web seeds in the one of the download workers, and every other peer in
+    /// the one that the event loop connects to.
+    pub(crate) fn discard_peer(&self, peer: Peer) -> Result<(), String> {
+        let peer_tx = match peer.is_web_seed() {
+            true => &self.web_seed_tx,
+            false => &self.peer_tx,
+        };
+        peer_tx.send(peer).map_err(|e| e.to_string())
     }
 
     pub fn downloaded(&self) -> DownloadedPieces {
         self.downloaded.clone()
     }
+
+    pub fn disk_cache(&self) -> &DiskCache {
+        &self.disk_cache
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client::{download::peer_protocol::BLOCK_SIZE, torrent_handle::TorrentStatus};
+    use std::net::TcpListener;
+
+    /// Answers a single announce with one peer, and returns the URL to announce to.
+    fn serve_announce() -> Result<String, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        thread::spawn(move || -> std::io::Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request)?;
+            stream.write_all(
+                b"HTTP/1.1 200 OK\r\n\r\nd8:intervali1800e5:peers6:\x0a\0\0\x03\x1a\xe1e",
+            )
+        });
+        Ok(format!("http://127.0.0.1:{port}/announce"))
+    }
+
+    /// Returns the URL of a tracker that refuses every connection.
+    fn closed_tracker() -> Result<String, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        Ok(format!("http://127.0.0.1:{port}/announce"))
+    }
+
+    #[test]
+    fn stopped_torrents_release_their_workers() -> Result<(), String> {
+        let torrent = Torrent::new(
+            "stopped".to_string(),
+            "http://localhost/announce".to_string(),
+            4,
+            vec![Piece::new(0, 4, utils::sha1(b"abcd")?)],
+            vec![SingleFile::new(4, "stopped".to_string())],
+            [0; 20],
+        )?;
+        let downloaded_torrents = Arc::new(Mutex::new(Vec::new()));
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (notif_tx, _notif_rx) = mpsc::channel();
+
+        let log_handle = LogHandle::new(log_tx);
+        torrent.handle().set_status(TorrentStatus::Paused)?;
+        torrent.download(
+            1,
+            downloaded_torrents.clone(),
+            notif_tx.clone(),
+            &log_handle,
+            0,
+        )?;
+        let (queued, _) = torrent.drain_pieces_queue()?;
+        assert_eq!(1, queued.len());
+
+        // Without web seeds, an active worker waits until the event loop downloads the piece or the torrent stops.
+        torrent.handle().set_status(TorrentStatus::Active)?;
+        let worker_torrent = torrent.clone();
+        let worker = thread::spawn(move || {
+            worker_torrent.download(1, downloaded_torrents, notif_tx, &log_handle, 0)
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(!worker.is_finished());
+
+        torrent.handle().set_status(TorrentStatus::Paused)?;
+        worker
+            .join()
+            .map_err(|_| "The worker panicked".to_string())??;
+        Ok(())
+    }
 
     #[test]
     fn sending_bytes_to_tracker() -> Result<(), String> {
@@ -530,4 +1380,285 @@ mod tests {
         println!("> TRACKER INFO FINAL:\n{:#?}", tracker_info);
         Ok(())
     }
+
+    #[test]
+    fn announces_report_the_bytes_that_the_torrent_transferred() -> Result<(), String> {
+        let torrent = Torrent::from("tests/sample.torrent")?;
+        let bandwidth = torrent.rate_limits.torrent(torrent.info_hash())?;
+        bandwidth.upload().consume(300, Instant::now())?;
+        bandwidth.download().consume(200, Instant::now())?;
+
+        let query_dict = torrent.query_string_dict(torrent.info_hash(), [0; 20], 6881)?;
+        assert_eq!(Some(&"300".to_string()), query_dict.get("uploaded"));
+        assert_eq!(Some(&"200".to_string()), query_dict.get("downloaded"));
+        Ok(())
+    }
+
+    #[test]
+    fn private_torrent_only_uses_peers_and_trackers_of_its_own() -> Result<(), String> {
+        let mut torrent = Torrent::from("tests/sample.torrent")?;
+        assert!(torrent.is_private());
+
+        for source in [PeerSource::Dht, PeerSource::Pex, PeerSource::Lsd] {
+            let peer = Peer::new(None, "10.0.0.1".to_string(), 6881);
+            assert!(!torrent.add_peer(peer, source)?);
+        }
+        let tracker_peer = Peer::new(None, "10.0.0.2".to_string(), 6881);
+        assert!(torrent.add_peer(tracker_peer, PeerSource::Tracker)?);
+
+        let peer_rx = torrent.peer_rx.lock().map_err(|e| e.to_string())?;
+        let queued: Vec<String> = peer_rx.try_iter().map(|peer| peer.ip()).collect();
+        assert_eq!(vec!["10.0.0.2".to_string()], queued);
+        drop(peer_rx);
+
+        assert!(torrent
+            .merge_trackers(vec!["http://other/announce".to_string()])
+            .is_err());
+        assert_eq!(vec![torrent.announce.clone()], torrent.trackers());
+
+        // Trackers that got in some other way are never announced to either.
+        torrent.announce = closed_tracker()?;
+        torrent.merged_trackers = vec![serve_announce()?];
+        assert!(torrent.request_tracker_info([0; 20], 6881).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn public_torrent_accepts_every_peer_and_tracker_source() -> Result<(), String> {
+        let mut torrent = Torrent::from("tests/bla.torrent")?;
+        assert!(!torrent.is_private());
+
+        for source in [PeerSource::Dht, PeerSource::Pex, PeerSource::Lsd] {
+            let peer = Peer::new(None, "10.0.0.1".to_string(), 6881);
+            assert!(torrent.add_peer(peer, source)?);
+        }
+        torrent.merge_trackers(vec!["http://other/announce".to_string()])?;
+        assert_eq!(2, torrent.trackers().len());
+
+        torrent.announce = closed_tracker()?;
+        torrent.merged_trackers = vec![serve_announce()?];
+        torrent.request_tracker_info([0; 20], 6881)?;
+        Ok(())
+    }
+
+    #[test]
+    fn recheck_verifies_data_on_disk_and_queues_failing_pieces() -> Result<(), String> {
+        let pieces = vec![
+            Piece::new(0, 4, utils::sha1(b"abcd")?),
+            Piece::new(1, 4, utils::sha1(b"efgh")?),
+            Piece::new(2, 4, utils::sha1(b"ij")?),
+        ];
+        let files = vec![
+            SingleFile::new(6, "recheck/a.txt".to_string()),
+            SingleFile::new(4, "recheck/b.txt".to_string()),
+        ];
+        let mut torrent = Torrent::new(
+            "recheck".to_string(),
+            "http://localhost/announce".to_string(),
+            4,
+            pieces,
+            files,
+            [0; 20],
+        )?;
+
+        let download_path = utils::test_dir("recheck");
+        torrent.save_in(download_path.to_string_lossy().to_string());
+        let files_dir = format!("{}/recheck", torrent.download_path);
+        fs::create_dir_all(&files_dir).map_err(|e| e.to_string())?;
+        fs::write(format!("{files_dir}/a.txt"), b"abcdef").map_err(|e| e.to_string())?;
+        fs::write(format!("{files_dir}/b.txt"), b"ghiX").map_err(|e| e.to_string())?;
+
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (notif_tx, notif_rx) = mpsc::channel();
+        assert!(torrent.has_data_on_disk());
+        assert_eq!(2, torrent.recheck(notif_tx, &LogHandle::new(log_tx))?);
+
+        let mut downloaded: Vec<usize> = torrent
+            .downloaded
+            .lock()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|piece| piece.index())
+            .collect();
+        downloaded.sort();
+        assert_eq!(vec![0, 1], downloaded);
+
+        let (queued, _) = torrent.drain_pieces_queue()?;
+        assert_eq!(
+            vec![2],
+            queued.iter().map(|p| p.index()).collect::<Vec<_>>()
+        );
+
+        let served = notif_rx
+            .try_iter()
+            .filter(|notif| matches!(notif, Notification::NewPiece(_, _)))
+            .count();
+        assert_eq!(2, served);
+
+        // Verified pieces are served from the files instead of being copied to `.tmp`.
+        assert!(!Path::new(&format!("{}/.tmp/1", torrent.download_path)).exists());
+        assert_eq!(
+            b"efgh".to_vec(),
+            torrent
+                .disk_cache
+                .read_block(&torrent.download_path, 1, 0, 4)?
+        );
+
+        fs::remove_dir_all(download_path).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn workers_do_not_hold_a_queue_while_waiting_in_it() -> Result<(), String> {
+        let (tx, rx) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(rx));
+        let waiting_queue = queue.clone();
+        let waiter = thread::spawn(move || wait_in_queue(&waiting_queue, Duration::from_secs(5)));
+
+        thread::sleep(Duration::from_millis(50));
+        let is_free = (0..10).any(|_| {
+            let free = queue.try_lock().is_ok();
+            thread::sleep(Duration::from_millis(1));
+            free
+        });
+        assert!(is_free);
+
+        tx.send(7).map_err(|e| e.to_string())?;
+        let received = waiter
+            .join()
+            .map_err(|_| "Error joining waiter".to_string())?;
+        assert_eq!(Ok(7), received);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_recheck_gives_every_drained_piece_back() -> Result<(), String> {
+        let mut torrent = Torrent::new(
+            "failed recheck".to_string(),
+            "http://localhost/announce".to_string(),
+            4,
+            vec![
+                Piece::new(0, 4, utils::sha1(b"abcd")?),
+                Piece::new(1, 4, utils::sha1(b"efgh")?),
+            ],
+            vec![SingleFile::new(8, "failed recheck".to_string())],
+            [0; 20],
+        )?;
+        let download_path = utils::test_dir("failed_recheck");
+        torrent.save_in(download_path.to_string_lossy().to_string());
+        fs::create_dir_all(&torrent.download_path).map_err(|e| e.to_string())?;
+        fs::write(
+            format!("{}/failed recheck", torrent.download_path),
+            b"abcdefgh",
+        )
+        .map_err(|e| e.to_string())?;
+        torrent.kill_workers(1)?;
+
+        // Verified pieces can not be served without the notification thread, so the recheck fails.
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (notif_tx, notif_rx) = mpsc::channel();
+        drop(notif_rx);
+        assert!(torrent.recheck(notif_tx, &LogHandle::new(log_tx)).is_err());
+
+        let (queued, others) = torrent.drain_pieces_queue()?;
+        assert_eq!(2, queued.len());
+        assert_eq!(1, others.len());
+        fs::remove_dir_all(download_path).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn built_files_follow_their_attributes() -> Result<(), String> {
+        let err = |e: Error| e.to_string();
+        let script = b"echo".to_vec();
+        let first_piece = [&script[..], &[0; 4]].concat();
+        let pieces = vec![
+            Piece::new(0, 8, utils::sha1(&first_piece)?),
+            Piece::new(1, 3, utils::sha1(b"abc")?),
+        ];
+
+        let mut executable = SingleFile::new(4, "attrs/run.sh".to_string());
+        executable.attr = "x".to_string();
+        executable.sha1 = Some(utils::sha1(&script)?);
+        let mut padding = SingleFile::new(4, "attrs/.pad/4".to_string());
+        padding.attr = "p".to_string();
+        let mut link = SingleFile::new(0, "attrs/sub/link.sh".to_string());
+        link.attr = "l".to_string();
+        link.symlink_path = Some("attrs/run.sh".to_string());
+        let files = vec![
+            executable,
+            padding,
+            SingleFile::new(3, "attrs/b.txt".to_string()),
+            link,
+        ];
+
+        let mut torrent = Torrent::new(
+            "attrs".to_string(),
+            "http://localhost/announce".to_string(),
+            8,
+            pieces,
+            files,
+            [0; 20],
+        )?;
+        assert_eq!(7, torrent.get_total_size());
+        assert_eq!(11, torrent.layout_size());
+
+        let download_path = utils::test_dir("attributes");
+        torrent.save_in(download_path.to_string_lossy().to_string());
+        let tmp_dir = format!("{}/.tmp", torrent.download_path);
+        fs::create_dir_all(&tmp_dir).map_err(err)?;
+        fs::write(format!("{tmp_dir}/0"), &first_piece).map_err(err)?;
+        fs::write(format!("{tmp_dir}/1"), b"abc").map_err(err)?;
+        torrent.build_files()?;
+
+        let files_dir = Path::new(&torrent.download_path).join("attrs");
+        assert!(!files_dir.join(".pad").exists());
+        assert_eq!(
+            b"abc".to_vec(),
+            fs::read(files_dir.join("b.txt")).map_err(err)?
+        );
+        assert_eq!(
+            script,
+            fs::read(files_dir.join("sub/link.sh")).map_err(err)?
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(files_dir.join("run.sh")).map_err(err)?;
+            assert_ne!(0, metadata.permissions().mode() & 0o111);
+        }
+
+        fs::write(format!("{tmp_dir}/0"), b"exit\0\0\0\0").map_err(err)?;
+        assert!(torrent.build_files().is_err());
+        fs::remove_dir_all(download_path).map_err(err)
+    }
+
+    #[test]
+    fn pieces_of_fixture_torrents_cover_their_total_size() -> Result<(), String> {
+        for entry in fs::read_dir("tests").map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("torrent") {
+                continue;
+            }
+
+            let torrent = Torrent::from(&path)?;
+            let (pieces, _) = torrent.drain_pieces_queue()?;
+            assert_eq!(torrent.total_pieces(), pieces.len());
+
+            let mut covered = 0;
+            for piece in &pieces {
+                assert_eq!(covered, piece.index() * torrent.piece_length);
+                assert!(piece.size() > 0 && piece.size() <= torrent.piece_length);
+
+                let mut block_begin = 0;
+                for (begin, length) in piece.block_ranges() {
+                    assert_eq!(block_begin, begin);
+                    assert!(length > 0 && length <= BLOCK_SIZE);
+                    block_begin += length;
+                }
+                assert_eq!(piece.size(), block_begin as usize);
+                covered += piece.size();
+            }
+            assert_eq!(torrent.layout_size(), covered, "{path:?}");
+        }
+        Ok(())
+    }
 }
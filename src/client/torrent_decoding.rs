@@ -1,12 +1,16 @@
 use super::{
     super::{
-        bdecoding::{BDecoder, BType},
+        bdecoding::{BDecoder, BDictionary, BType},
         utils,
     },
+    merkle::{self, MerkleHash, MERKLE_BLOCK_SIZE},
+    safe_path,
     single_file::SingleFile,
     torrent::Torrent,
+    torrent_meta::TorrentMeta,
+    torrent_v2::{V2File, V2Info},
 };
-use crate::client::piece::Piece;
+use crate::client::piece::{Piece, PieceHash};
 use std::{array::TryFromSliceError, collections::HashMap, vec};
 
 pub fn from_bytes(bytes: Vec<u8>) -> Result<Torrent, String> {
@@ -21,32 +25,117 @@ pub fn from_bytes(bytes: Vec<u8>) -> Result<Torrent, String> {
     };
 
     let announce = get_string_from(&file, "announce")?;
-    let name = get_string_from(info, "name")?;
+    let name = match info.get("name") {
+        Some(BType::String(bytes)) => safe_path::sanitize_component(bytes)?,
+        _ => return Err("name key not present or has invalid type".to_string()),
+    };
     let piece_length = get_integer_from(info, "piece length")? as usize;
 
-    let pieces = match info.get("pieces") {
-        Some(BType::String(bytes)) => torrent_pieces_list(bytes, piece_length as usize)?,
+    let info_bytes = match BDecoder::value_span(bytes.clone(), "info")? {
+        Some(span) => &bytes[span],
+        None => return Err("info key not present".to_string()),
+    };
+
+    let v2 = match info.get("meta version") {
+        Some(BType::Integer(2)) => Some(v2_info(
+            &file,
+            info,
+            &name,
+            piece_length,
+            utils::sha256(info_bytes),
+        )?),
+        Some(BType::Integer(1)) | None => None,
+        _ => return Err("meta version key has an unsupported value".to_string()),
+    };
+
+    let (files, pieces, info_hash) = match (info.get("pieces"), &v2) {
+        (Some(BType::String(bytes)), _) => {
+            let files = match (info.get("length"), info.get("files")) {
+                (Some(BType::Integer(length)), None) => vec![with_attributes(
+                    SingleFile::new(*length, name.clone()),
+                    info,
+                    None,
+                )?],
+                (None, Some(BType::List(list))) => multiple_file_list(name.clone(), list)?,
+                _ => {
+                    return Err(
+                        "length and files keys not present or have invalid types".to_string()
+                    )
+                }
+            };
+            let total_size = files.iter().map(|file| file.length).sum::<i64>() as usize;
+            let pieces = torrent_pieces_list(bytes, piece_length, total_size)?;
+            (files, pieces, utils::sha1(info_bytes)?)
+        }
+        (None, Some(v2)) => (
+            v2_file_list(v2, piece_length),
+            v2_pieces_list(v2, piece_length),
+            v2.truncated_info_hash(),
+        ),
         _ => return Err("pieces key not present or has invalid value type".to_string()),
     };
 
-    let files = match (info.get("length"), info.get("files")) {
-        (Some(BType::Integer(length)), None) => single_file_list(name.clone(), *length),
-        (None, Some(BType::List(list))) => multiple_file_list(name.clone(), list)?,
-        _ => return Err("length and files keys not present or have invalid types".to_string()),
+    // Names that are only an extension would put the files of the torrent right in the download directory.
+    let name = match utils::remove_extension(&name) {
+        name if name.is_empty() => utils::to_hex(&info_hash),
+        name => name,
+    };
+    let mut torrent = Torrent::new(name, announce, piece_length, pieces, files, info_hash)?;
+    if let Some(v2) = v2 {
+        torrent.set_v2(v2);
+    }
+    torrent.set_meta(torrent_meta(&file, info));
+    torrent.set_web_seeds(web_seeds(&file));
+    Ok(torrent)
+}
+
+/// Collects the optional keys of the root and info dictionaries into a `TorrentMeta`.
+fn torrent_meta(file: &BDictionary, info: &BDictionary) -> TorrentMeta {
+    let file_attributes = match info.get("files") {
+        Some(BType::List(list)) => list
+            .iter()
+            .map(|btype| match btype {
+                BType::Dictionary(file_dict) => get_optional_string_from(file_dict, "attr"),
+                _ => None,
+            })
+            .map(Option::unwrap_or_default)
+            .collect(),
+        _ => vec![get_optional_string_from(info, "attr").unwrap_or_default()],
     };
 
-    Torrent::new(
-        utils::remove_extension(&name),
-        announce,
-        pieces,
-        files,
-        get_info_hash(bytes)?,
-    )
+    TorrentMeta {
+        comment: get_optional_string_from(file, "comment"),
+        created_by: get_optional_string_from(file, "created by"),
+        creation_date: get_integer_from(file, "creation date").ok(),
+        encoding: get_optional_string_from(file, "encoding"),
+        source: get_optional_string_from(info, "source"),
+        private: matches!(info.get("private"), Some(BType::Integer(1))),
+        file_attributes,
+    }
+}
+
+/// Reads the "url-list" key (BEP 19), which holds either a single URL or a list of them.
+/// Only HTTP and HTTPS URLs are kept.
+fn web_seeds(file: &BDictionary) -> Vec<String> {
+    let urls = match file.get("url-list") {
+        Some(BType::String(url)) => vec![String::from_utf8_lossy(url).to_string()],
+        Some(BType::List(list)) => list
+            .iter()
+            .filter_map(|btype| match btype {
+                BType::String(url) => Some(String::from_utf8_lossy(url).to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    urls.into_iter()
+        .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+        .collect()
 }
 
 /// Looks up a `BType`'d string value associated to a `key`, in a given `dict`.
 /// If a string value is found for `key`, it is then converted from its "raw" state (bytes) to a UTF-8 valid format.
-fn get_string_from(dict: &HashMap<String, BType>, key: &str) -> Result<String, String> {
+fn get_string_from(dict: &BDictionary, key: &str) -> Result<String, String> {
     let value = match dict.get(key) {
         Some(BType::String(bytes)) => utils::bytes_to_string(bytes)?,
         _ => return Err(format!("{} key not present or has invalid type", key)),
@@ -54,8 +143,17 @@ fn get_string_from(dict: &HashMap<String, BType>, key: &str) -> Result<String, S
     Ok(value)
 }
 
+/// Looks up an informative string value associated to a `key`, which is not required for the torrent to be valid.
+/// Invalid UTF-8 sequences are replaced instead of rejecting the whole torrent.
+fn get_optional_string_from(dict: &BDictionary, key: &str) -> Option<String> {
+    match dict.get(key) {
+        Some(BType::String(bytes)) => Some(String::from_utf8_lossy(bytes).to_string()),
+        _ => None,
+    }
+}
+
 /// Looks up a `BType`'d integer value associated to a `key`, in a given `dict`.
-fn get_integer_from(dict: &HashMap<String, BType>, key: &str) -> Result<i64, String> {
+fn get_integer_from(dict: &BDictionary, key: &str) -> Result<i64, String> {
     let value = match dict.get(key) {
         Some(BType::Integer(int)) => *int,
         _ => return Err(format!("{} key not present or has invalid type", key)),
@@ -63,17 +161,33 @@ fn get_integer_from(dict: &HashMap<String, BType>, key: &str) -> Result<i64, Str
     Ok(value)
 }
 
-/// Checks if the pieces key bytes are multiple of 20.
-fn torrent_pieces_list(pieces: &[u8], piece_length: usize) -> Result<Vec<Piece>, String> {
+/// Checks if the pieces key bytes are multiple of 20, and builds every `Piece` with its exact size.
+/// Every piece is `piece_length` bytes long except for the last one, which holds whatever is left of `total_size`.
+fn torrent_pieces_list(
+    pieces: &[u8],
+    piece_length: usize,
+    total_size: usize,
+) -> Result<Vec<Piece>, String> {
     if pieces.len() % 20 != 0 {
         return Err("pieces string is not a multiple of 20".to_string());
     }
+    if piece_length == 0 {
+        return Err("piece length must be greater than zero".to_string());
+    }
+
+    let total_pieces = pieces.len() / 20;
+    if total_pieces != utils::round_up(total_size, piece_length) / piece_length {
+        return Err(format!(
+            "{total_pieces} pieces of {piece_length} bytes do not match a total size of {total_size} bytes"
+        ));
+    }
 
-    let mut final_pieces = Vec::with_capacity(pieces.len() / 20);
+    let mut final_pieces = Vec::with_capacity(total_pieces);
     for piece in pieces.chunks_exact(20).enumerate() {
+        let piece_size = piece_length.min(total_size - piece.0 * piece_length);
         final_pieces.push(Piece::new(
             piece.0,
-            piece_length,
+            piece_size,
             piece
                 .1
                 .try_into()
@@ -83,11 +197,6 @@ fn torrent_pieces_list(pieces: &[u8], piece_length: usize) -> Result<Vec<Piece>,
     Ok(final_pieces)
 }
 
-/// Given a `name` and `length` for a file, it creates a vector of only one `SingleFile` struct.
-fn single_file_list(name: String, length: i64) -> Vec<SingleFile> {
-    vec![SingleFile::new(length, name)]
-}
-
 /// Given a `name` and a vector of `BType`'d dictionaries, it creates a vector of valid `SingleFile` structs.
 fn multiple_file_list(name: String, btype_list: &[BType]) -> Result<Vec<SingleFile>, String> {
     let mut file_list: Vec<SingleFile> = Vec::with_capacity(btype_list.len());
@@ -109,52 +218,226 @@ fn multiple_file_list(name: String, btype_list: &[BType]) -> Result<Vec<SingleFi
 /// The `directory_name` parameter is used to set it as a prefix for the path field of the `SingleFile`.
 fn single_file_from_dict(
     directory_name: &str,
-    file_dict: &HashMap<String, BType>,
+    file_dict: &BDictionary,
 ) -> Result<SingleFile, String> {
     let single_file = match (file_dict.get("length"), file_dict.get("path")) {
-        (Some(BType::Integer(length)), Some(BType::List(path))) => SingleFile {
-            length: *length,
-            path: get_complete_path(directory_name, path)?,
-        },
+        (Some(BType::Integer(length)), Some(BType::List(path))) => {
+            SingleFile::new(*length, get_complete_path(directory_name, path)?)
+        }
         _ => return Err("missing keys in single file dictionary".to_string()),
     };
-    Ok(single_file)
+    with_attributes(single_file, file_dict, Some(directory_name))
+}
+
+/// Reads the optional keys of a file dictionary (BEP 47): its attributes, the target of a symbolic link
+/// and the SHA-1 hash of the whole file. The link target gets the same `directory_name` prefix as the file.
+fn with_attributes(
+    mut file: SingleFile,
+    file_dict: &BDictionary,
+    directory_name: Option<&str>,
+) -> Result<SingleFile, String> {
+    file.attr = get_optional_string_from(file_dict, "attr").unwrap_or_default();
+    file.symlink_path = match (file_dict.get("symlink path"), directory_name) {
+        (Some(BType::List(path)), Some(directory_name)) => {
+            Some(get_complete_path(directory_name, path)?)
+        }
+        (Some(BType::List(path)), None) => Some(path_components(path)?.join("/")),
+        (None, _) => None,
+        _ => return Err("symlink path key has invalid type".to_string()),
+    };
+    file.sha1 = match file_dict.get("sha1") {
+        Some(BType::String(hash)) => Some(
+            hash.as_slice()
+                .try_into()
+                .map_err(|e: TryFromSliceError| e.to_string())?,
+        ),
+        _ => None,
+    };
+    Ok(file)
 }
 
 /// Given a `directory_name` and a vector of `BTyped`'d strings, it creates a valid UTF-8 `String`
 /// that represents the whole path to a file.
 /// `directory_name` is used as the root directory by putting it at the beginning of the path.
 fn get_complete_path(directory_name: &str, btype_path: &[BType]) -> Result<String, String> {
+    Ok(format!(
+        "{directory_name}/{}",
+        path_components(btype_path)?.join("/")
+    ))
+}
+
+/// Converts a list of `BType`'d strings into path components. Every component is sanitized so the path
+/// cannot leave the download directory.
+fn path_components(btype_path: &[BType]) -> Result<Vec<String>, String> {
     if btype_path.is_empty() {
         return Err("missing name in single file".to_string());
     }
 
-    let mut complete_path = directory_name.to_string();
-    for btype in btype_path {
-        match btype {
-            BType::String(bytes) => {
-                let sub_directory = utils::bytes_to_string(bytes)?;
-                complete_path.push('/');
-                complete_path.push_str(&sub_directory)
+    btype_path
+        .iter()
+        .map(|btype| match btype {
+            BType::String(bytes) => safe_path::sanitize_component(bytes),
+            _ => Err("element in path list is not a string".to_string()),
+        })
+        .collect()
+}
+
+/// Builds the v2 (BEP 52) information of a torrent from its "file tree" and the "piece layers" of the root dictionary.
+/// Paths follow the same layout as v1 torrents: files are placed under `name`, unless the tree only has one file.
+fn v2_info(
+    file: &BDictionary,
+    info: &BDictionary,
+    name: &str,
+    piece_length: usize,
+    info_hash: [u8; 32],
+) -> Result<V2Info, String> {
+    let file_tree = match info.get("file tree") {
+        Some(BType::Dictionary(file_tree)) => file_tree,
+        _ => return Err("file tree key not present or has invalid type".to_string()),
+    };
+    let mut tree_files = Vec::new();
+    file_tree_list(file_tree, &mut Vec::new(), &mut tree_files)?;
+
+    let is_single_file = match tree_files.as_slice() {
+        [] => return Err("file tree does not have any files".to_string()),
+        [(path, _, _)] => path.len() == 1,
+        _ => false,
+    };
+    let files = tree_files
+        .into_iter()
+        .map(|(path, length, pieces_root)| V2File {
+            path: match is_single_file {
+                true => path.join("/"),
+                false => format!("{name}/{}", path.join("/")),
+            },
+            length,
+            pieces_root,
+        })
+        .collect();
+
+    let piece_layers = match file.get("piece layers") {
+        Some(BType::Dictionary(piece_layers)) => piece_layers_map(piece_layers)?,
+        None => HashMap::new(),
+        _ => return Err("piece layers key has invalid type".to_string()),
+    };
+    V2Info::new(info_hash, piece_length, files, piece_layers)
+}
+
+/// Walks a "file tree" dictionary in order, collecting the path, length and pieces root of every file.
+/// Files are the nodes that have an empty key, whose value holds their properties.
+fn file_tree_list(
+    tree: &BDictionary,
+    path: &mut Vec<String>,
+    files: &mut Vec<(Vec<String>, usize, Option<MerkleHash>)>,
+) -> Result<(), String> {
+    let mut entries: Vec<(&[u8], &BType)> = tree.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+
+    for (key, value) in entries {
+        let node = match value {
+            BType::Dictionary(node) if !key.is_empty() => node,
+            _ => return Err("file tree has an invalid node".to_string()),
+        };
+        path.push(safe_path::sanitize_component(key)?);
+
+        match node.get("") {
+            Some(BType::Dictionary(properties)) => {
+                let length = usize::try_from(get_integer_from(properties, "length")?)
+                    .map_err(|_| "file tree has a negative file length".to_string())?;
+                let pieces_root = match properties.get("pieces root") {
+                    Some(BType::String(root)) => Some(
+                        root.as_slice()
+                            .try_into()
+                            .map_err(|e: TryFromSliceError| e.to_string())?,
+                    ),
+                    None if length == 0 => None,
+                    _ => return Err(format!("{} does not have a pieces root", path.join("/"))),
+                };
+                files.push((path.clone(), length, pieces_root));
             }
-            _ => return Err("element in path list is not a string".to_string()),
+            Some(_) => return Err("file tree has invalid file properties".to_string()),
+            None => file_tree_list(node, path, files)?,
         }
+        path.pop();
     }
-    Ok(complete_path)
+    Ok(())
 }
 
-fn get_info_hash(bytes: Vec<u8>) -> Result<[u8; 20], String> {
-    let info_start = match bytes.windows(7).position(|bytes| bytes == b"4:infod") {
-        Some(info_key) => info_key + 6,
-        None => return Err("info_key not present".to_string()),
-    };
-    utils::sha1(&bytes[info_start..bytes.len() - 1])
+/// Splits the value of every entry of the "piece layers" dictionary into the hashes of the pieces of a file.
+fn piece_layers_map(
+    piece_layers: &BDictionary,
+) -> Result<HashMap<MerkleHash, Vec<MerkleHash>>, String> {
+    let err = |e: TryFromSliceError| e.to_string();
+    piece_layers
+        .iter()
+        .map(|(root, hashes)| match hashes {
+            BType::String(hashes) if hashes.len() % 32 == 0 => Ok((
+                root.try_into().map_err(err)?,
+                hashes
+                    .chunks_exact(32)
+                    .map(|hash| hash.try_into().map_err(err))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err("piece layer is not a multiple of 32 bytes".to_string()),
+        })
+        .collect()
+}
+
+/// Lays the files of a pure v2 torrent out as a v1 torrent would. Since v2 pieces never span more than one file,
+/// padding files are added so that every file starts at a piece boundary.
+fn v2_file_list(v2: &V2Info, piece_length: usize) -> Vec<SingleFile> {
+    let mut files = Vec::with_capacity(v2.files().len());
+    let mut offset = 0;
+    for file in v2.files().iter().filter(|file| file.length > 0) {
+        let padding = utils::round_up(offset, piece_length) - offset;
+        if padding > 0 {
+            let directory = file.path.split('/').next().unwrap_or_default();
+            let mut padding_file =
+                SingleFile::new(padding as i64, format!("{directory}/.pad/{padding}"));
+            padding_file.attr = "p".to_string();
+            files.push(padding_file);
+        }
+        files.push(SingleFile::new(file.length as i64, file.path.clone()));
+        offset += padding + file.length;
+    }
+    files
+}
+
+/// Builds the pieces of a pure v2 torrent, following the layout of `v2_file_list`.
+/// Pieces of files larger than a piece are verified against their piece layer, and smaller files against their root.
+fn v2_pieces_list(v2: &V2Info, piece_length: usize) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    for file in v2.files() {
+        let Some(pieces_root) = file.pieces_root else {
+            continue;
+        };
+
+        let hashes = match v2.piece_layer(&pieces_root) {
+            Some(piece_layer) => piece_layer
+                .iter()
+                .map(|root| (*root, piece_length / MERKLE_BLOCK_SIZE))
+                .collect(),
+            None => vec![(pieces_root, merkle::width_of(file.length))],
+        };
+        for (i, (root, width)) in hashes.into_iter().enumerate() {
+            let size = piece_length.min(file.length - i * piece_length);
+            pieces.push(Piece::with_hash(
+                pieces.len(),
+                size,
+                PieceHash::Merkle { root, width },
+            ));
+        }
+    }
+    pieces
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
+    use crate::{
+        bencoding::bencode, client::torrent_v2::TorrentVersion, logging::log_handle::LogHandle,
+    };
+    use std::{fs, sync::mpsc};
 
     #[test]
     fn test_single_file_torrent() -> Result<(), String> {
@@ -163,7 +446,8 @@ mod tests {
         let expected_torrent = Torrent::new(
             "sample".to_string(),
             "udp://tracker.openbittorrent.com:80".to_string(),
-            torrent_pieces_list(&file_bytes[148..168].to_vec(), 65536)?,
+            65536,
+            torrent_pieces_list(&file_bytes[148..168].to_vec(), 65536, 20)?,
             vec![SingleFile::new(20, "sample.txt".to_string())],
             [
                 0xd0, 0xd1, 0x4c, 0x92, 0x6e, 0x6e, 0x99, 0x76, 0x1a, 0x2f, 0xdc, 0xff, 0x27, 0xb4,
@@ -183,7 +467,8 @@ mod tests {
         let expected_torrent = Torrent::new(
             "bla".to_string(),
             "udp://tracker.opentrackr.org:1337/announce".to_string(),
-            torrent_pieces_list(&file_bytes[392..412].to_vec(), 16384)?,
+            16384,
+            torrent_pieces_list(&file_bytes[392..412].to_vec(), 16384, 7184)?,
             vec![
                 SingleFile::new(8, "bla/sub_bla/a.txt".to_string()),
                 SingleFile::new(8, "bla/sub_bla/neo_bla/b.txt".to_string()),
@@ -200,4 +485,218 @@ mod tests {
         assert_eq!(expected_torrent, torrent);
         Ok(())
     }
+
+    #[test]
+    fn test_torrent_meta_is_decoded() -> Result<(), String> {
+        let lubuntu = Torrent::from("tests/lubuntu.torrent")?;
+        assert_eq!(
+            &TorrentMeta {
+                comment: Some("Lubuntu CD cdimage.ubuntu.com".to_string()),
+                created_by: Some("mktorrent 1.1".to_string()),
+                creation_date: Some(1650550206),
+                encoding: None,
+                source: None,
+                private: false,
+                file_attributes: vec![String::new()],
+            },
+            lubuntu.meta()
+        );
+
+        let linuxmint = Torrent::from("tests/linuxmint.torrent")?;
+        assert_eq!(Some("UTF-8".to_string()), linuxmint.meta().encoding);
+
+        let sample = Torrent::from("tests/sample.torrent")?;
+        assert!(sample.meta().private);
+        assert_eq!(None, sample.meta().comment);
+
+        let bla = Torrent::from("tests/bla.torrent")?;
+        assert_eq!(4, bla.meta().file_attributes.len());
+        Ok(())
+    }
+
+    fn multiple_file_torrent_with_path(path: &[u8]) -> Vec<u8> {
+        multiple_file_torrent(b"3:bla", path)
+    }
+
+    fn multiple_file_torrent(name: &[u8], path: &[u8]) -> Vec<u8> {
+        let mut bytes =
+            b"d8:announce21:http://localhost:80804:infod5:filesld6:lengthi3e4:pathl".to_vec();
+        bytes.extend_from_slice(path);
+        bytes.extend_from_slice(b"eee4:name");
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(b"12:piece lengthi16384e6:pieces20:");
+        bytes.extend_from_slice(&[0; 20]);
+        bytes.extend_from_slice(b"ee");
+        bytes
+    }
+
+    #[test]
+    fn test_torrent_with_path_traversal_is_rejected() {
+        for path in [&b"2:..6:passwd"[..], b"4:/etc6:passwd", b"3:C:x"] {
+            let result = from_bytes(multiple_file_torrent_with_path(path));
+            assert!(result.is_err(), "{}", String::from_utf8_lossy(path));
+        }
+    }
+
+    #[test]
+    fn test_torrent_with_unsafe_names_is_rewritten() -> Result<(), String> {
+        let torrent = from_bytes(multiple_file_torrent_with_path(b"3:CON3:a\xffb"))?;
+        let expected = if cfg!(windows) {
+            "bla/CON_/a_b"
+        } else {
+            "bla/CON/a_b"
+        };
+        assert_eq!(expected, torrent.files()[0].path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_torrent_named_only_by_an_extension_is_named_by_its_info_hash() -> Result<(), String> {
+        let torrent = from_bytes(multiple_file_torrent(b"2:.x", b"1:a"))?;
+        assert_eq!(utils::to_hex(&torrent.info_hash()), torrent.name());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hybrid_torrent_keeps_the_v1_info_hash() -> Result<(), String> {
+        let data = b"hybrid torrent".to_vec();
+        let bytes = v2_torrent_bytes(&[("v2", &data)], Some(utils::sha1(&data)?.to_vec()));
+        let torrent = from_bytes(bytes.clone())?;
+
+        let span = BDecoder::value_span(bytes.clone(), "info")?.ok_or("missing info")?;
+        assert_eq!(TorrentVersion::Hybrid, torrent.version());
+        assert_eq!(utils::sha1(&bytes[span])?, torrent.info_hash());
+        assert_eq!(vec![SingleFile::new(14, "v2".to_string())], torrent.files());
+        Ok(())
+    }
+
+    #[test]
+    fn test_v2_torrent_with_wrong_piece_layer_is_rejected() {
+        let data = vec![7; 5 * MERKLE_BLOCK_SIZE];
+        let mut bytes = v2_torrent_bytes(&[("a.bin", &data)], None);
+        let last = bytes.len() - 3;
+        bytes[last] ^= 1;
+        assert!(from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn test_file_attributes_are_decoded() -> Result<(), String> {
+        let mut bytes =
+            b"d8:announce16:http://localhost4:infod5:filesld6:lengthi4e4:pathl6:run.she4:attr1:x4:sha120:"
+                .to_vec();
+        bytes.extend_from_slice(&[1; 20]);
+        bytes.extend_from_slice(b"ed6:lengthi16380e4:pathl4:.pad5:16380e4:attr1:pe");
+        bytes.extend_from_slice(b"d6:lengthi0e4:pathl4:linke4:attr1:l12:symlink pathl6:run.sheee");
+        bytes.extend_from_slice(b"4:name5:attrs12:piece lengthi16384e6:pieces20:");
+        bytes.extend_from_slice(&[0; 20]);
+        bytes.extend_from_slice(b"ee");
+
+        let torrent = from_bytes(bytes)?;
+        let files = torrent.files();
+        assert!(files[0].is_executable() && files[0].sha1 == Some([1; 20]));
+        assert!(files[1].is_padding());
+        assert!(files[2].is_symlink());
+        assert_eq!(Some("attrs/run.sh".to_string()), files[2].symlink_path);
+        assert_eq!(4, torrent.get_total_size());
+        Ok(())
+    }
+
+    #[test]
+    fn test_web_seeds_are_decoded() -> Result<(), String> {
+        let file_bytes = fs::read("tests/sample.torrent").map_err(|e| e.to_string())?;
+        let mut file = match BDecoder::bdecode(file_bytes)? {
+            BType::Dictionary(file) => file,
+            _ => return Err("expected a dictionary".to_string()),
+        };
+        let urls = vec![
+            BType::String(b"http://mirror.local/releases/".to_vec()),
+            BType::String(b"ftp://mirror.local/releases/".to_vec()),
+        ];
+        file.insert("url-list", BType::List(urls));
+
+        let torrent = from_bytes(bencode(&BType::Dictionary(file)))?;
+        assert_eq!(
+            &["http://mirror.local/releases/".to_string()],
+            torrent.web_seeds()
+        );
+        Ok(())
+    }
+
+    /// Builds a v2 torrent with `files`, adding the v1 keys as well when `hybrid_pieces` is present.
+    fn v2_torrent_bytes(files: &[(&str, &[u8])], hybrid_pieces: Option<Vec<u8>>) -> Vec<u8> {
+        let piece_length = 2 * MERKLE_BLOCK_SIZE;
+        let mut file_tree = BDictionary::new();
+        let mut piece_layers = BDictionary::new();
+        for (name, data) in files {
+            let root = merkle::data_root(data, merkle::width_of(data.len()));
+            if data.len() > piece_length {
+                let layer: Vec<u8> = data
+                    .chunks(piece_length)
+                    .flat_map(|piece| merkle::data_root(piece, piece_length / MERKLE_BLOCK_SIZE))
+                    .collect();
+                piece_layers.insert(root.to_vec(), BType::String(layer));
+            }
+
+            let mut properties = BDictionary::new();
+            properties.insert("length", BType::Integer(data.len() as i64));
+            properties.insert("pieces root", BType::String(root.to_vec()));
+            let mut node = BDictionary::new();
+            node.insert("", BType::Dictionary(properties));
+            file_tree.insert(*name, BType::Dictionary(node));
+        }
+
+        let mut info = BDictionary::new();
+        info.insert("file tree", BType::Dictionary(file_tree));
+        info.insert("meta version", BType::Integer(2));
+        info.insert("name", BType::String(b"v2".to_vec()));
+        info.insert("piece length", BType::Integer(piece_length as i64));
+        if let Some(pieces) = hybrid_pieces {
+            info.insert("length", BType::Integer(files[0].1.len() as i64));
+            info.insert("pieces", BType::String(pieces));
+        }
+
+        let mut file = BDictionary::new();
+        file.insert("announce", BType::String(b"http://localhost".to_vec()));
+        file.insert("info", BType::Dictionary(info));
+        file.insert("piece layers", BType::Dictionary(piece_layers));
+        bencode(&BType::Dictionary(file))
+    }
+
+    #[test]
+    fn test_v2_torrent_is_laid_out_in_pieces_of_single_files() -> Result<(), String> {
+        let first: Vec<u8> = (0..40000).map(|i| (i % 251) as u8).collect();
+        let second = b"0123456789".to_vec();
+        let bytes = v2_torrent_bytes(&[("a.bin", &first), ("b.txt", &second)], None);
+        let mut torrent = from_bytes(bytes.clone())?;
+
+        let span = BDecoder::value_span(bytes.clone(), "info")?.ok_or("missing info")?;
+        let v2 = torrent.v2().ok_or("missing v2 info")?;
+        assert_eq!(TorrentVersion::V2, torrent.version());
+        assert_eq!(utils::sha256(&bytes[span]), v2.info_hash());
+        assert_eq!(v2.truncated_info_hash(), torrent.info_hash());
+
+        assert_eq!(
+            vec![
+                SingleFile::new(40000, "v2/a.bin".to_string()),
+                SingleFile {
+                    attr: "p".to_string(),
+                    ..SingleFile::new(25536, "v2/.pad/25536".to_string())
+                },
+                SingleFile::new(10, "v2/b.txt".to_string()),
+            ],
+            torrent.files()
+        );
+        assert_eq!(3, torrent.total_pieces());
+
+        let dir = utils::test_dir("v2_layout");
+        fs::create_dir_all(dir.join("v2/v2")).map_err(|e| e.to_string())?;
+        fs::write(dir.join("v2/v2/a.bin"), &first).map_err(|e| e.to_string())?;
+        fs::write(dir.join("v2/v2/b.txt"), &second).map_err(|e| e.to_string())?;
+
+        torrent.save_in(dir.to_string_lossy().to_string());
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (notif_tx, _notif_rx) = mpsc::channel();
+        assert_eq!(3, torrent.recheck(notif_tx, &LogHandle::new(log_tx))?);
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
 }
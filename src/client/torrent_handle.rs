@@ -0,0 +1,119 @@
+use super::seeding_goals::SeedingGoals;
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Tells the download workers whether they should keep working on a torrent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentStatus {
+    Active,
+    /// The torrent keeps its data and can be resumed.
+    Paused,
+    /// The torrent waits in the queue until there is room for it to run.
+    Queued,
+    /// The torrent was removed from the client and is never downloaded again.
+    Removed,
+}
+
+#[derive(Debug, Default)]
+struct Seeding {
+    since: Option<Instant>,
+    /// Goals of this torrent, which replace the global ones of the client.
+    goals: Option<SeedingGoals>,
+    goal_reached: bool,
+}
+
+/// Status shared by every clone of a `Torrent`, so the client can stop the workers that download it.
+#[derive(Debug, Clone)]
+pub struct TorrentHandle {
+    status: Arc<Mutex<TorrentStatus>>,
+    /// True while a clone of the torrent circulates among the download workers.
+    scheduled: Arc<Mutex<bool>>,
+    seeding: Arc<Mutex<Seeding>>,
+}
+
+impl Default for TorrentHandle {
+    fn default() -> Self {
+        Self {
+            status: Arc::new(Mutex::new(TorrentStatus::Active)),
+            scheduled: Arc::new(Mutex::new(false)),
+            seeding: Arc::new(Mutex::new(Seeding::default())),
+        }
+    }
+}
+
+impl TorrentHandle {
+    pub fn status(&self) -> Result<TorrentStatus, String> {
+        Ok(*self.status.lock().map_err(|e| e.to_string())?)
+    }
+
+    pub fn set_status(&self, status: TorrentStatus) -> Result<(), String> {
+        *self.status.lock().map_err(|e| e.to_string())? = status;
+        Ok(())
+    }
+
+    /// Returns true unless the torrent was paused or removed. A poisoned status counts as stopped.
+    pub fn is_active(&self) -> bool {
+        matches!(self.status(), Ok(TorrentStatus::Active))
+    }
+
+    /// Marks the torrent as sent to the download workers. Returns false if a clone of it still circulates
+    /// among them, which picks the current status up, so the torrent must not be sent again.
+    pub fn schedule(&self) -> Result<bool, String> {
+        let mut scheduled = self.scheduled.lock().map_err(|e| e.to_string())?;
+        Ok(!std::mem::replace(&mut *scheduled, true))
+    }
+
+    /// Returns true if a download worker should keep working on the torrent. Otherwise the worker drops
+    /// it, so it is no longer scheduled. The status is read under the same lock that `schedule` takes, so
+    /// a torrent that is started again meanwhile is either kept or sent again.
+    pub fn keep_scheduled(&self) -> Result<bool, String> {
+        let mut scheduled = self.scheduled.lock().map_err(|e| e.to_string())?;
+        if self.status()? == TorrentStatus::Active {
+            return Ok(true);
+        }
+        *scheduled = false;
+        Ok(false)
+    }
+
+    /// Marks the torrent as dropped by the download workers, as when it was already downloaded.
+    pub fn unschedule(&self) -> Result<(), String> {
+        *self.scheduled.lock().map_err(|e| e.to_string())? = false;
+        Ok(())
+    }
+
+    /// Marks the torrent as complete, so it seeds from now on. Torrents that were already seeding keep
+    /// the time they started at.
+    pub fn start_seeding(&self, now: Instant) -> Result<(), String> {
+        let mut seeding = self.seeding.lock().map_err(|e| e.to_string())?;
+        seeding.since.get_or_insert(now);
+        Ok(())
+    }
+
+    /// Returns when the torrent was completed, or `None` if it is still downloading.
+    pub fn seeding_since(&self) -> Result<Option<Instant>, String> {
+        Ok(self.seeding.lock().map_err(|e| e.to_string())?.since)
+    }
+
+    pub fn set_seeding_goals(&self, goals: SeedingGoals) -> Result<(), String> {
+        let mut seeding = self.seeding.lock().map_err(|e| e.to_string())?;
+        seeding.goals = Some(goals);
+        seeding.goal_reached = false;
+        Ok(())
+    }
+
+    pub fn seeding_goals(&self) -> Result<Option<SeedingGoals>, String> {
+        Ok(self.seeding.lock().map_err(|e| e.to_string())?.goals)
+    }
+
+    /// Marks that a seeding goal was reached, so the torrent can still be resumed to seed beyond it.
+    pub fn set_goal_reached(&self) -> Result<(), String> {
+        self.seeding.lock().map_err(|e| e.to_string())?.goal_reached = true;
+        Ok(())
+    }
+
+    pub fn goal_reached(&self) -> Result<bool, String> {
+        Ok(self.seeding.lock().map_err(|e| e.to_string())?.goal_reached)
+    }
+}
@@ -0,0 +1,25 @@
+use crate::ui_notification_structs::torrent_state::TorrentState;
+
+/// Optional information of a .torrent file that is not needed to download it.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TorrentMeta {
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    pub creation_date: Option<i64>,
+    pub encoding: Option<String>,
+    pub source: Option<String>,
+    pub private: bool,
+    /// The `attr` string of every file, in the same order as the files of the torrent.
+    pub file_attributes: Vec<String>,
+}
+
+impl TorrentMeta {
+    /// Copies the metadata into the `Metadata` of a `TorrentState`, so the UI can show it.
+    pub fn fill_torrent_state(&self, state: &mut TorrentState) {
+        state.set_metadata_comment(self.comment.clone().unwrap_or_default());
+        state.set_metadata_created_by(self.created_by.clone().unwrap_or_default());
+        state.set_metadata_creation_date(self.creation_date);
+        state.set_metadata_source(self.source.clone().unwrap_or_default());
+        state.set_metadata_is_private(self.private);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::utils;
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Key of the lines of the session state that hold the queue, one torrent per line.
+const QUEUE_KEY: &str = "queue";
+
+/// How a torrent of the queue is doing, as the client sees it when it updates the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub info_hash: [u8; 20],
+    /// Whether the torrent is being downloaded or seeded, as opposed to waiting in the queue.
+    pub running: bool,
+    pub seeding: bool,
+    /// When the torrent last uploaded or downloaded anything.
+    pub last_transfer: Option<Instant>,
+}
+
+/// Torrents that the queue starts and stops to keep within its limits.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct QueueChanges {
+    pub to_start: Vec<[u8; 20]>,
+    pub to_queue: Vec<[u8; 20]>,
+}
+
+/// Decides which torrents run at once. Torrents earlier in the queue go first, up to `max_downloads`
+/// downloads and `max_seeds` seeds, where zero means unlimited. Running torrents that did not transfer
+/// anything for `stall_time` do not count against the limits, so they let the next ones run.
+///
+/// The order of the queue is kept in the session state file, so it survives restarts.
+#[derive(Debug)]
+pub struct TorrentQueue {
+    order: Vec<[u8; 20]>,
+    max_downloads: usize,
+    max_seeds: usize,
+    stall_time: Duration,
+    /// When each running torrent was started, so it is not stalled before it had the chance to transfer.
+    started: HashMap<[u8; 20], Instant>,
+    session_path: String,
+}
+
+impl TorrentQueue {
+    pub fn new(
+        max_downloads: usize,
+        max_seeds: usize,
+        stall_time: Duration,
+        session_path: String,
+    ) -> Self {
+        Self {
+            order: Vec::new(),
+            max_downloads,
+            max_seeds,
+            stall_time,
+            started: HashMap::new(),
+            session_path,
+        }
+    }
+
+    /// Restores the order saved in the session state, if there is one.
+    pub fn load(&mut self) -> Result<(), String> {
+        if !Path::new(&self.session_path).is_file() {
+            return Ok(());
+        }
+        let session = fs::read_to_string(&self.session_path).map_err(|e| e.to_string())?;
+        self.order = session
+            .lines()
+            .filter_map(|line| line.strip_prefix(QUEUE_KEY)?.strip_prefix('='))
+            .map(Self::info_hash_from_hex)
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let session: String = self
+            .order
+            .iter()
+            .map(|info_hash| format!("{QUEUE_KEY}={}\n", utils::to_hex(info_hash)))
+            .collect();
+        fs::write(&self.session_path, session).map_err(|e| e.to_string())
+    }
+
+    fn info_hash_from_hex(hex: &str) -> Result<[u8; 20], String> {
+        let invalid = || format!("Invalid info hash in session state: {hex}");
+        if hex.len() != 40 {
+            return Err(invalid());
+        }
+        let mut info_hash = [0; 20];
+        for (i, byte) in info_hash.iter_mut().enumerate() {
+            let digits = hex.get(i * 2..i * 2 + 2).ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(info_hash)
+    }
+
+    /// Returns the info hashes of the queue in order, including torrents of the session state that were
+    /// not loaded again.
+    pub fn order(&self) -> &[[u8; 20]] {
+        &self.order
+    }
+
+    /// Adds a torrent at the bottom of the queue, unless it kept its position from a previous session.
+    pub fn add(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        if self.order.contains(&info_hash) {
+            return Ok(());
+        }
+        self.order.push(info_hash);
+        self.save()
+    }
+
+    pub fn remove(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        self.order.retain(|queued| *queued != info_hash);
+        self.started.remove(&info_hash);
+        self.save()
+    }
+
+    /// Moves a torrent to another position of the queue, clamped to the length of the queue.
+    fn move_to(
+        &mut self,
+        info_hash: [u8; 20],
+        position: impl Fn(usize) -> usize,
+    ) -> Result<(), String> {
+        let current = self
+            .order
+            .iter()
+            .position(|queued| *queued == info_hash)
+            .ok_or_else(|| format!("Torrent {info_hash:?} is not queued"))?;
+        self.order.remove(current);
+        let position = position(current).min(self.order.len());
+        self.order.insert(position, info_hash);
+        self.save()
+    }
+
+    pub fn move_up(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        self.move_to(info_hash, |current| current.saturating_sub(1))
+    }
+
+    pub fn move_down(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        self.move_to(info_hash, |current| current + 1)
+    }
+
+    pub fn move_to_top(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        self.move_to(info_hash, |_| 0)
+    }
+
+    pub fn move_to_bottom(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        self.move_to(info_hash, |_| usize::MAX)
+    }
+
+    fn is_stalled(&self, entry: &QueueEntry, now: Instant) -> bool {
+        if self.stall_time.is_zero() {
+            return false;
+        }
+        let started = self.started.get(&entry.info_hash).copied().unwrap_or(now);
+        let last_activity = entry
+            .last_transfer
+            .map_or(started, |last| last.max(started));
+        now.saturating_duration_since(last_activity) >= self.stall_time
+    }
+
+    /// Decides which torrents to start and which to send back to the queue. `entries` are the torrents
+    /// that are either running or queued, in the order of the queue.
+    pub fn update(&mut self, entries: &[QueueEntry], now: Instant) -> QueueChanges {
+        let mut changes = QueueChanges::default();
+        let (mut downloads, mut seeds) = (0, 0);
+
+        for entry in entries {
+            let (count, max) = match entry.seeding {
+                true => (&mut seeds, self.max_seeds),
+                false => (&mut downloads, self.max_downloads),
+            };
+            let has_room = max == 0 || *count < max;
+
+            if entry.running {
+                self.started.entry(entry.info_hash).or_insert(now);
+                if self.is_stalled(entry, now) {
+                    continue;
+                }
+                match has_room {
+                    true => *count += 1,
+                    false => {
+                        self.started.remove(&entry.info_hash);
+                        changes.to_queue.push(entry.info_hash);
+                    }
+                }
+            } else if has_room {
+                *count += 1;
+                self.started.insert(entry.info_hash, now);
+                changes.to_start.push(entry.info_hash);
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    fn entry(id: u8, running: bool, seeding: bool) -> QueueEntry {
+        QueueEntry {
+            info_hash: [id; 20],
+            running,
+            seeding,
+            last_transfer: None,
+        }
+    }
+
+    #[test]
+    fn earlier_torrents_run_first_and_stalled_ones_leave_room() -> Result<(), String> {
+        let dir = test_dir("queue_limits");
+        let path = dir.join("session");
+        let mut queue = TorrentQueue::new(
+            1,
+            1,
+            Duration::from_secs(60),
+            path.to_string_lossy().to_string(),
+        );
+        let now = Instant::now();
+
+        let mut entries = vec![
+            entry(0, false, false),
+            entry(1, true, false),
+            entry(2, false, true),
+            entry(3, false, true),
+        ];
+        let changes = queue.update(&entries, now);
+        assert_eq!(vec![[0; 20], [2; 20]], changes.to_start);
+        assert_eq!(vec![[1; 20]], changes.to_queue);
+
+        // The first download stalls, so the next one runs too.
+        entries[0].running = true;
+        entries[1].running = false;
+        entries[2].running = true;
+        entries[2].last_transfer = Some(now + Duration::from_secs(50));
+        let changes = queue.update(&entries, now + Duration::from_secs(60));
+        assert_eq!(vec![[1; 20]], changes.to_start);
+        assert!(changes.to_queue.is_empty());
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn positions_are_kept_in_the_session_state() -> Result<(), String> {
+        let dir = test_dir("queue_session");
+        let path = dir.join("session").to_string_lossy().to_string();
+
+        let mut queue = TorrentQueue::new(0, 0, Duration::ZERO, path.clone());
+        for id in 0..4 {
+            queue.add([id; 20])?;
+        }
+        queue.move_to_top([2; 20])?;
+        queue.move_down([0; 20])?;
+        queue.move_to_bottom([2; 20])?;
+        queue.move_up([3; 20])?;
+        queue.remove([1; 20])?;
+
+        let mut queue = TorrentQueue::new(0, 0, Duration::ZERO, path);
+        queue.load()?;
+        assert_eq!(&[[3; 20], [0; 20], [2; 20]], queue.order());
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+}
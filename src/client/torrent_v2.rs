@@ -0,0 +1,123 @@
+use super::merkle::{self, MerkleHash, MERKLE_BLOCK_SIZE};
+use std::collections::HashMap;
+
+/// A file of the "file tree" of a v2 torrent.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct V2File {
+    pub path: String,
+    pub length: usize,
+    /// Root of the merkle tree of the file. Empty files do not have one.
+    pub pieces_root: Option<MerkleHash>,
+}
+
+/// Describes which swarms a torrent belongs to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TorrentVersion {
+    V1,
+    V2,
+    /// Torrents with both layouts, whose pieces are the same for both swarms (BEP 52).
+    Hybrid,
+}
+
+/// Stores the v2 (BEP 52) information of a torrent: its SHA-256 info hash, file tree and piece layers.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct V2Info {
+    info_hash: [u8; 32],
+    piece_length: usize,
+    files: Vec<V2File>,
+    piece_layers: HashMap<MerkleHash, Vec<MerkleHash>>,
+}
+
+impl V2Info {
+    /// Builds the v2 information of a torrent, checking that every piece layer matches the pieces root of its file.
+    pub fn new(
+        info_hash: [u8; 32],
+        piece_length: usize,
+        files: Vec<V2File>,
+        piece_layers: HashMap<MerkleHash, Vec<MerkleHash>>,
+    ) -> Result<Self, String> {
+        if !piece_length.is_power_of_two() || piece_length < MERKLE_BLOCK_SIZE {
+            return Err(format!(
+                "piece length {piece_length} of a v2 torrent is not a power of two of at least 16 KiB"
+            ));
+        }
+
+        for file in files.iter().filter(|file| file.length > piece_length) {
+            let pieces_root = file
+                .pieces_root
+                .ok_or_else(|| format!("{} does not have a pieces root", file.path))?;
+            let piece_layer = piece_layers
+                .get(&pieces_root)
+                .ok_or_else(|| format!("{} does not have a piece layer", file.path))?;
+
+            let pieces = file.length.div_ceil(piece_length);
+            if piece_layer.len() != pieces
+                || merkle::piece_layer_root(piece_layer, piece_length) != pieces_root
+            {
+                return Err(format!(
+                    "piece layer of {} does not match its pieces root",
+                    file.path
+                ));
+            }
+        }
+
+        Ok(Self {
+            info_hash,
+            piece_length,
+            files,
+            piece_layers,
+        })
+    }
+
+    pub fn info_hash(&self) -> [u8; 32] {
+        self.info_hash
+    }
+
+    /// Returns the info hash that identifies the v2 swarm in handshakes and tracker requests.
+    pub fn truncated_info_hash(&self) -> [u8; 20] {
+        let mut truncated = [0; 20];
+        truncated.copy_from_slice(&self.info_hash[..20]);
+        truncated
+    }
+
+    pub fn files(&self) -> &[V2File] {
+        &self.files
+    }
+
+    /// Returns the hashes of the pieces of a file with the given root, or `None` if the file fits in a single piece.
+    pub fn piece_layer(&self, pieces_root: &MerkleHash) -> Option<&Vec<MerkleHash>> {
+        self.piece_layers.get(pieces_root)
+    }
+
+    /// Returns the layer of the merkle trees that holds one hash per piece.
+    pub fn piece_layer_index(&self) -> u32 {
+        (self.piece_length / MERKLE_BLOCK_SIZE).trailing_zeros()
+    }
+
+    /// Returns `length` hashes of the piece layer of a file starting at `index`, followed by the uncle hashes
+    /// of `proof_layers` layers above them. Only requests whose base layer is the piece layer can be served.
+    pub fn hashes(
+        &self,
+        pieces_root: &MerkleHash,
+        base_layer: u32,
+        index: usize,
+        length: usize,
+        proof_layers: usize,
+    ) -> Option<Vec<MerkleHash>> {
+        if base_layer != self.piece_layer_index()
+            || !length.is_power_of_two()
+            || index % length != 0
+        {
+            return None;
+        }
+
+        let piece_layer = self.piece_layer(pieces_root)?;
+        let padding = merkle::padding_root(self.piece_length / MERKLE_BLOCK_SIZE);
+        let layers = merkle::layers(piece_layer, piece_layer.len(), padding);
+        let requested = layers.first()?.get(index..(index + length))?;
+
+        let mut hashes = requested.to_vec();
+        hashes.extend(merkle::uncle_hashes(&layers, index, length, proof_layers)?);
+        Some(hashes)
+    }
+}
@@ -1,14 +1,15 @@
 use super::{
     super::{
-        bdecoding::{BDecoder, BType},
+        bdecoding::{BDecoder, BDictionary, BType},
         utils,
     },
     download::peer::Peer,
     tracker_info::TrackerInfo,
 };
-use std::collections::HashMap;
+use crate::network::ip_filter::IpFilter;
 
-pub fn from_bytes(bytes: Vec<u8>) -> Result<TrackerInfo, String> {
+/// Decodes a tracker response, leaving out the peers that `ip_filter` blocks.
+pub fn from_bytes(bytes: Vec<u8>, ip_filter: &IpFilter) -> Result<TrackerInfo, String> {
     let body = match BDecoder::bdecode(bytes)? {
         BType::Dictionary(body) => body,
         _ => return Err("tracker response body is not a bencoded dictionary".to_string()),
@@ -28,8 +29,14 @@ pub fn from_bytes(bytes: Vec<u8>) -> Result<TrackerInfo, String> {
         Some(BType::String(peers)) => compact_peer_list(peers)?,
         _ => return Err("peers key not present or has invalid value type".to_string()),
     };
+    let mut allowed = Vec::with_capacity(peers.len());
+    for peer in peers {
+        if !ip_filter.is_blocked(&peer.ip())? {
+            allowed.push(peer);
+        }
+    }
 
-    Ok(TrackerInfo::new(interval, peers))
+    Ok(TrackerInfo::new(interval, allowed))
 }
 
 fn detailed_failure(reason: &[u8], code: Option<&BType>) -> Result<String, String> {
@@ -87,7 +94,7 @@ fn regular_peer_list(peers: &[BType]) -> Result<Vec<Peer>, String> {
     Ok(peer_list)
 }
 
-fn peer_from_dict(peer_dict: &HashMap<String, BType>) -> Result<Peer, String> {
+fn peer_from_dict(peer_dict: &BDictionary) -> Result<Peer, String> {
     let id = match peer_dict.get("peer id") {
         Some(BType::String(id)) => peer_id_from_bytes(id),
         _ => None,
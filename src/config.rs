@@ -1,7 +1,16 @@
+use crate::{
+    client::{
+        bandwidth_schedule::{BandwidthProfile, ScheduleRule},
+        seeding_goals::{SeedingAction, SeedingGoals},
+    },
+    network::event_loop::Timers,
+    storage::allocation::AllocationMode,
+};
 use std::{
     fs::File,
     io::{BufRead, BufReader},
     str::FromStr,
+    time::Duration,
 };
 
 #[derive(Clone)]
@@ -11,6 +20,40 @@ pub struct Config {
     download_path: String,
     torrent_time_slice: usize,
     max_download_connections: usize,
+    connections_per_torrent: usize,
+    max_peer_connections: usize,
+    upload_slots: usize,
+    /// Upload and download rate limits in KiB/s, where zero means unlimited.
+    upload_rate_limit: usize,
+    download_rate_limit: usize,
+    torrent_upload_rate_limit: usize,
+    torrent_download_rate_limit: usize,
+    peer_upload_rate_limit: usize,
+    peer_download_rate_limit: usize,
+    /// Rules that change the global limits and the connection limit depending on the time of the week.
+    bandwidth_schedule: Vec<ScheduleRule>,
+    /// Goals that end the seeding of completed torrents, where zero means no goal.
+    seed_ratio: f64,
+    seed_minutes: u64,
+    seed_idle_minutes: u64,
+    seed_goal_action: SeedingAction,
+    /// Torrents that are downloaded and seeded at once, where zero means unlimited.
+    max_active_downloads: usize,
+    max_active_seeds: usize,
+    /// Minutes without traffic after which a torrent stops counting against the active limits.
+    stalled_minutes: u64,
+    session_state_path: String,
+    /// Blocklist in eMule or P2P format of the addresses that peers can't have, or empty for none.
+    ip_filter_path: String,
+    /// Time limits of the connections to peers, in seconds.
+    connect_timeout_secs: u64,
+    handshake_timeout_secs: u64,
+    read_timeout_secs: u64,
+    keep_alive_secs: u64,
+    idle_timeout_secs: u64,
+    snub_timeout_secs: u64,
+    allocation_mode: AllocationMode,
+    disk_cache_mb: usize,
 }
 
 impl Default for Config {
@@ -21,6 +64,33 @@ impl Default for Config {
             download_path: "downloads".to_string(),
             torrent_time_slice: 10,
             max_download_connections: 20,
+            connections_per_torrent: 4,
+            max_peer_connections: 100,
+            upload_slots: 4,
+            upload_rate_limit: 0,
+            download_rate_limit: 0,
+            torrent_upload_rate_limit: 0,
+            torrent_download_rate_limit: 0,
+            peer_upload_rate_limit: 0,
+            peer_download_rate_limit: 0,
+            bandwidth_schedule: Vec::new(),
+            seed_ratio: 0.0,
+            seed_minutes: 0,
+            seed_idle_minutes: 0,
+            seed_goal_action: SeedingAction::Pause,
+            max_active_downloads: 3,
+            max_active_seeds: 5,
+            stalled_minutes: 5,
+            session_state_path: "session_state.txt".to_string(),
+            ip_filter_path: String::new(),
+            connect_timeout_secs: 10,
+            handshake_timeout_secs: 10,
+            read_timeout_secs: 5,
+            keep_alive_secs: 120,
+            idle_timeout_secs: 180,
+            snub_timeout_secs: 60,
+            allocation_mode: AllocationMode::Sparse,
+            disk_cache_mb: 64,
         }
     }
 }
@@ -45,6 +115,67 @@ impl Config {
             } else if line.starts_with("max_download_connections") {
                 config.max_download_connections =
                     usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("connections_per_torrent") {
+                config.connections_per_torrent =
+                    usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("max_peer_connections") {
+                config.max_peer_connections = usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("upload_slots") {
+                config.upload_slots = usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("upload_rate_limit") {
+                config.upload_rate_limit = usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("download_rate_limit") {
+                config.download_rate_limit = usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("torrent_upload_rate_limit") {
+                config.torrent_upload_rate_limit =
+                    usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("torrent_download_rate_limit") {
+                config.torrent_download_rate_limit =
+                    usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("peer_upload_rate_limit") {
+                config.peer_upload_rate_limit =
+                    usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("peer_download_rate_limit") {
+                config.peer_download_rate_limit =
+                    usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("bandwidth_schedule") {
+                config
+                    .bandwidth_schedule
+                    .push(ScheduleRule::from_str(&value)?);
+            } else if line.starts_with("seed_ratio") {
+                config.seed_ratio = f64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("seed_minutes") {
+                config.seed_minutes = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("seed_idle_minutes") {
+                config.seed_idle_minutes = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("seed_goal_action") {
+                config.seed_goal_action = SeedingAction::from_str(&value)?;
+            } else if line.starts_with("max_active_downloads") {
+                config.max_active_downloads = usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("max_active_seeds") {
+                config.max_active_seeds = usize::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("stalled_minutes") {
+                config.stalled_minutes = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("session_state_path") {
+                config.session_state_path = value;
+            } else if line.starts_with("ip_filter_path") {
+                config.ip_filter_path = value;
+            } else if line.starts_with("connect_timeout_secs") {
+                config.connect_timeout_secs = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("handshake_timeout_secs") {
+                config.handshake_timeout_secs = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("read_timeout_secs") {
+                config.read_timeout_secs = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("keep_alive_secs") {
+                config.keep_alive_secs = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("idle_timeout_secs") {
+                config.idle_timeout_secs = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("snub_timeout_secs") {
+                config.snub_timeout_secs = u64::from_str(&value).map_err(|e| e.to_string())?;
+            } else if line.starts_with("allocation_mode") {
+                config.allocation_mode = AllocationMode::from_str(&value)?;
+            } else if line.starts_with("disk_cache_mb") {
+                config.disk_cache_mb = usize::from_str(&value).map_err(|e| e.to_string())?;
             }
         }
 
@@ -67,10 +198,124 @@ impl Config {
         self.max_download_connections
     }
 
+    /// Amount of peers that each torrent downloads from at once.
+    pub fn connections_per_torrent(&self) -> usize {
+        self.connections_per_torrent
+    }
+
+    /// Amount of peer connections that can be open at once, between every torrent, whether the client opened
+    /// them or accepted them.
+    pub fn max_peer_connections(&self) -> usize {
+        self.max_peer_connections
+    }
+
+    /// Amount of peers that are unchoked at once, one of them being the optimistic unchoke.
+    pub fn upload_slots(&self) -> usize {
+        self.upload_slots
+    }
+
+    /// Upload and download limits of the whole client, in bytes per second.
+    pub fn global_rate_limits(&self) -> (usize, usize) {
+        (
+            self.upload_rate_limit * 1024,
+            self.download_rate_limit * 1024,
+        )
+    }
+
+    /// Upload and download limits of each torrent, in bytes per second.
+    pub fn torrent_rate_limits(&self) -> (usize, usize) {
+        (
+            self.torrent_upload_rate_limit * 1024,
+            self.torrent_download_rate_limit * 1024,
+        )
+    }
+
+    /// Upload and download limits of each peer connection, in bytes per second.
+    pub fn peer_rate_limits(&self) -> (usize, usize) {
+        (
+            self.peer_upload_rate_limit * 1024,
+            self.peer_download_rate_limit * 1024,
+        )
+    }
+
+    /// Rules of the bandwidth schedule, each from a `bandwidth_schedule` line, in the order they were written.
+    pub fn bandwidth_schedule(&self) -> Vec<ScheduleRule> {
+        self.bandwidth_schedule.clone()
+    }
+
+    /// Limits that apply when no rule of the bandwidth schedule does.
+    pub fn default_bandwidth_profile(&self) -> BandwidthProfile {
+        let (upload_limit, download_limit) = self.global_rate_limits();
+        BandwidthProfile {
+            upload_limit,
+            download_limit,
+            max_connections: self.max_peer_connections,
+        }
+    }
+
+    /// Seeding goals of every torrent that has none of its own.
+    pub fn seeding_goals(&self) -> SeedingGoals {
+        let minutes = |minutes: u64| (minutes > 0).then(|| Duration::from_secs(minutes * 60));
+        SeedingGoals {
+            ratio: (self.seed_ratio > 0.0).then_some(self.seed_ratio),
+            seed_time: minutes(self.seed_minutes),
+            idle_time: minutes(self.seed_idle_minutes),
+            action: self.seed_goal_action,
+        }
+    }
+
+    /// Torrents that are downloaded at once, where zero means unlimited.
+    pub fn max_active_downloads(&self) -> usize {
+        self.max_active_downloads
+    }
+
+    /// Torrents that are seeded at once, where zero means unlimited.
+    pub fn max_active_seeds(&self) -> usize {
+        self.max_active_seeds
+    }
+
+    /// Time without traffic after which a torrent stops counting against the active limits.
+    pub fn stall_time(&self) -> Duration {
+        Duration::from_secs(self.stalled_minutes * 60)
+    }
+
+    /// File where the state of the client is kept between runs, such as the order of the queue.
+    pub fn session_state_path(&self) -> String {
+        self.session_state_path.clone()
+    }
+
+    /// Blocklist of the addresses that peers can't have, if any.
+    pub fn ip_filter_path(&self) -> Option<String> {
+        match self.ip_filter_path.is_empty() {
+            true => None,
+            false => Some(self.ip_filter_path.clone()),
+        }
+    }
+
+    pub fn timers(&self) -> Timers {
+        Timers {
+            connect_timeout: Duration::from_secs(self.connect_timeout_secs),
+            handshake_timeout: Duration::from_secs(self.handshake_timeout_secs),
+            read_timeout: Duration::from_secs(self.read_timeout_secs),
+            keep_alive: Duration::from_secs(self.keep_alive_secs),
+            idle_timeout: Duration::from_secs(self.idle_timeout_secs),
+            snub_timeout: Duration::from_secs(self.snub_timeout_secs),
+        }
+    }
+
     pub fn torrent_time_slice(&self) -> usize {
         self.torrent_time_slice
     }
 
+    pub fn allocation_mode(&self) -> AllocationMode {
+        self.allocation_mode
+    }
+
+    /// Memory budget of the disk cache, in bytes.
+    pub fn disk_cache_size(&self) -> usize {
+        self.disk_cache_mb * 1024 * 1024
+    }
+
     fn value_from_line(line: &str) -> String {
         let split_line: Vec<&str> = line.rsplit('=').collect();
         split_line[0].to_string()
@@ -0,0 +1,438 @@
+use crate::{
+    bdecoding::{BDictionary, BType},
+    bencoding, utils,
+};
+use std::{
+    fs::{self, File},
+    io::{Error, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MIN_PIECE_LENGTH: usize = 16 * 1024;
+const MAX_PIECE_LENGTH: usize = 16 * 1024 * 1024;
+const TARGET_PIECES: usize = 1500;
+
+/// A file that will be shared by the created torrent, with its path relative to the shared root.
+#[derive(Debug, Clone)]
+struct SourceFile {
+    full_path: PathBuf,
+    components: Vec<String>,
+    length: usize,
+}
+
+/// Builds .torrent files out of a file or a directory on disk.
+#[derive(Debug, Clone)]
+pub struct TorrentCreator {
+    path: PathBuf,
+    announce: String,
+    announce_list: Vec<Vec<String>>,
+    comment: Option<String>,
+    created_by: String,
+    piece_length: Option<usize>,
+    private: bool,
+}
+
+impl TorrentCreator {
+    /// Creates a `TorrentCreator` that shares whatever is located at `path` through the tracker at `announce`.
+    pub fn new<P>(path: P, announce: String) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            announce,
+            announce_list: Vec::new(),
+            comment: None,
+            created_by: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            piece_length: None,
+            private: false,
+        }
+    }
+
+    /// Adds a tier of trackers to the announce-list. The announce URL is always put in the first tier.
+    pub fn add_tier(&mut self, trackers: Vec<String>) {
+        self.announce_list.push(trackers);
+    }
+
+    pub fn set_comment(&mut self, comment: String) {
+        self.comment = Some(comment);
+    }
+
+    pub fn set_created_by(&mut self, created_by: String) {
+        self.created_by = created_by;
+    }
+
+    /// Fixes the piece length instead of choosing one from the total size.
+    /// It must be a power of two between 16 KiB and 16 MiB.
+    pub fn set_piece_length(&mut self, piece_length: usize) -> Result<(), String> {
+        if !piece_length.is_power_of_two()
+            || !(MIN_PIECE_LENGTH..=MAX_PIECE_LENGTH).contains(&piece_length)
+        {
+            return Err(format!(
+                "piece length {piece_length} is not a power of two between {MIN_PIECE_LENGTH} and {MAX_PIECE_LENGTH}"
+            ));
+        }
+        self.piece_length = Some(piece_length);
+        Ok(())
+    }
+
+    pub fn set_private(&mut self, private: bool) {
+        self.private = private;
+    }
+
+    /// Walks the shared path, hashes its pieces and returns the bencoded .torrent file.
+    pub fn create(&self) -> Result<Vec<u8>, String> {
+        let name = file_name(&self.path)?;
+        let files = self.source_files()?;
+        let total_size = files.iter().map(|file| file.length).sum::<usize>();
+        if total_size == 0 {
+            return Err(format!("{} has no data to share", self.path.display()));
+        }
+        let piece_length = self
+            .piece_length
+            .unwrap_or_else(|| auto_piece_length(total_size));
+
+        let mut info = BDictionary::new();
+        info.insert("name", string(name));
+        info.insert("piece length", BType::Integer(piece_length as i64));
+        info.insert(
+            "pieces",
+            BType::String(hash_pieces(&files, piece_length, total_size)?),
+        );
+        if self.path.is_dir() {
+            info.insert("files", files_list(&files));
+        } else {
+            info.insert("length", BType::Integer(total_size as i64));
+        }
+        if self.private {
+            info.insert("private", BType::Integer(1));
+        }
+
+        let mut torrent = BDictionary::new();
+        torrent.insert("announce", string(&self.announce));
+        if !self.announce_list.is_empty() {
+            torrent.insert("announce-list", self.announce_list_btype());
+        }
+        if let Some(comment) = &self.comment {
+            torrent.insert("comment", string(comment));
+        }
+        torrent.insert("created by", string(&self.created_by));
+        torrent.insert("creation date", BType::Integer(unix_timestamp()? as i64));
+        torrent.insert("info", BType::Dictionary(info));
+
+        Ok(bencoding::bencode(&BType::Dictionary(torrent)))
+    }
+
+    /// Creates the .torrent file and writes it at `output`.
+    pub fn write_to<P>(&self, output: P) -> Result<(), String>
+    where
+        P: AsRef<Path>,
+    {
+        fs::write(output, self.create()?).map_err(|e| e.to_string())
+    }
+
+    /// Lists every file to share, sorted by path so the same directory always produces the same pieces.
+    fn source_files(&self) -> Result<Vec<SourceFile>, String> {
+        let mut files = Vec::new();
+        if self.path.is_dir() {
+            walk_directory(&self.path, &mut Vec::new(), &mut files)?;
+            if files.is_empty() {
+                return Err(format!(
+                    "{} does not contain any files",
+                    self.path.display()
+                ));
+            }
+        } else {
+            files.push(SourceFile {
+                full_path: self.path.clone(),
+                components: Vec::new(),
+                length: file_length(&self.path)?,
+            });
+        }
+        Ok(files)
+    }
+
+    fn announce_list_btype(&self) -> BType {
+        let mut tiers = vec![vec![self.announce.clone()]];
+        tiers.extend(self.announce_list.iter().cloned());
+        BType::List(
+            tiers
+                .iter()
+                .map(|tier| BType::List(tier.iter().map(string).collect()))
+                .collect(),
+        )
+    }
+}
+
+/// Picks the smallest power of two that keeps the torrent close to `TARGET_PIECES` pieces.
+pub fn auto_piece_length(total_size: usize) -> usize {
+    (total_size / TARGET_PIECES)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+/// Collects the regular files under `directory`, sorted by path. Symbolic links are skipped, since they
+/// could point outside of the directory or to one of its ancestors.
+fn walk_directory(
+    directory: &Path,
+    components: &mut Vec<String>,
+    files: &mut Vec<SourceFile>,
+) -> Result<(), String> {
+    let mut entries = fs::read_dir(directory)
+        .map_err(|e| e.to_string())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, Error>>()
+        .map_err(|e| e.to_string())?;
+    entries.sort();
+
+    for entry in entries {
+        let file_type = fs::symlink_metadata(&entry)
+            .map_err(|e| e.to_string())?
+            .file_type();
+        components.push(file_name(&entry)?.to_string());
+        if file_type.is_dir() {
+            walk_directory(&entry, components, files)?;
+        } else if file_type.is_file() {
+            files.push(SourceFile {
+                length: file_length(&entry)?,
+                full_path: entry,
+                components: components.clone(),
+            });
+        }
+        components.pop();
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> Result<&str, String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("{} does not have a valid UTF-8 name", path.display()))
+}
+
+fn file_length(path: &Path) -> Result<usize, String> {
+    Ok(fs::metadata(path).map_err(|e| e.to_string())?.len() as usize)
+}
+
+fn files_list(files: &[SourceFile]) -> BType {
+    BType::List(
+        files
+            .iter()
+            .map(|file| {
+                let mut dict = BDictionary::new();
+                dict.insert("length", BType::Integer(file.length as i64));
+                dict.insert(
+                    "path",
+                    BType::List(file.components.iter().map(string).collect()),
+                );
+                BType::Dictionary(dict)
+            })
+            .collect(),
+    )
+}
+
+/// Hashes every piece of the concatenated `files`, splitting the pieces between as many threads as the machine has.
+fn hash_pieces(
+    files: &[SourceFile],
+    piece_length: usize,
+    total_size: usize,
+) -> Result<Vec<u8>, String> {
+    let total_pieces = utils::round_up(total_size, piece_length) / piece_length;
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = utils::round_up(total_pieces, threads) / threads;
+    let piece_indexes: Vec<usize> = (0..total_pieces).collect();
+
+    let hashes = thread::scope(|scope| {
+        let handles: Vec<_> = piece_indexes
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || hash_chunk(files, chunk, piece_length, total_size)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().map_err(|_| "Error joining hashing thread")?)
+            .collect::<Result<Vec<Vec<u8>>, String>>()
+    })?;
+    Ok(hashes.concat())
+}
+
+fn hash_chunk(
+    files: &[SourceFile],
+    piece_indexes: &[usize],
+    piece_length: usize,
+    total_size: usize,
+) -> Result<Vec<u8>, String> {
+    let mut reader = FilesReader::new(files);
+    let mut hashes = Vec::with_capacity(piece_indexes.len() * 20);
+    let mut buffer = vec![0; piece_length];
+
+    for &index in piece_indexes {
+        let offset = index * piece_length;
+        let size = piece_length.min(total_size - offset);
+        reader.read_at(offset, &mut buffer[..size])?;
+        hashes.extend_from_slice(&utils::sha1(&buffer[..size])?);
+    }
+    Ok(hashes)
+}
+
+/// Reads the shared files as if they were a single stream of bytes.
+struct FilesReader<'a> {
+    files: &'a [SourceFile],
+    open: Option<(usize, File)>,
+}
+
+impl<'a> FilesReader<'a> {
+    fn new(files: &'a [SourceFile]) -> Self {
+        Self { files, open: None }
+    }
+
+    /// Fills `buffer` with the bytes found at `offset` of the stream, crossing file boundaries if needed.
+    fn read_at(&mut self, mut offset: usize, buffer: &mut [u8]) -> Result<(), String> {
+        let err = |e: Error| e.to_string();
+        let mut filled = 0;
+        let mut file_start = 0;
+
+        for (index, source) in self.files.iter().enumerate() {
+            let file_end = file_start + source.length;
+            if offset < file_end && filled < buffer.len() {
+                let to_read = (file_end - offset).min(buffer.len() - filled);
+                let file = self.file(index)?;
+                file.seek(SeekFrom::Start((offset - file_start) as u64))
+                    .map_err(err)?;
+                file.read_exact(&mut buffer[filled..filled + to_read])
+                    .map_err(err)?;
+                filled += to_read;
+                offset += to_read;
+            }
+            file_start = file_end;
+        }
+
+        if filled < buffer.len() {
+            return Err("shared files are shorter than expected".to_string());
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, index: usize) -> Result<&mut File, String> {
+        if !matches!(&self.open, Some((open_index, _)) if *open_index == index) {
+            let file = File::open(&self.files[index].full_path).map_err(|e| e.to_string())?;
+            self.open = Some((index, file));
+        }
+        match &mut self.open {
+            Some((_, file)) => Ok(file),
+            None => Err("no shared file is open".to_string()),
+        }
+    }
+}
+
+fn string(value: impl AsRef<str>) -> BType {
+    BType::String(value.as_ref().as_bytes().to_vec())
+}
+
+fn unix_timestamp() -> Result<u64, String> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::torrent::Torrent, logging::log_handle::LogHandle};
+    use crate::utils::test_dir;
+    use std::sync::mpsc;
+
+    #[test]
+    fn piece_length_is_chosen_from_the_total_size() {
+        assert_eq!(MIN_PIECE_LENGTH, auto_piece_length(1000));
+        assert_eq!(1024 * 1024, auto_piece_length(1024 * 1024 * 1024));
+        assert_eq!(MAX_PIECE_LENGTH, auto_piece_length(usize::MAX / 2));
+    }
+
+    #[test]
+    fn invalid_piece_lengths_are_rejected() {
+        let mut creator = TorrentCreator::new("data", "http://localhost/announce".to_string());
+        assert!(creator.set_piece_length(1000).is_err());
+        assert!(creator.set_piece_length(8 * 1024).is_err());
+        assert!(creator.set_piece_length(32 * 1024).is_ok());
+    }
+
+    #[test]
+    fn created_directory_torrent_round_trips() -> Result<(), String> {
+        let dir = test_dir("create_directory");
+        let err = |e: Error| e.to_string();
+        fs::create_dir_all(dir.join("data/data/sub")).map_err(err)?;
+        let first: Vec<u8> = (0..40000).map(|i| (i % 251) as u8).collect();
+        let second: Vec<u8> = (0..10000).map(|i| (i % 13) as u8).collect();
+        fs::write(dir.join("data/data/a.bin"), &first).map_err(err)?;
+        fs::write(dir.join("data/data/sub/b.bin"), &second).map_err(err)?;
+
+        let mut creator = TorrentCreator::new(
+            dir.join("data/data"),
+            "http://localhost/announce".to_string(),
+        );
+        creator.set_piece_length(16384)?;
+        creator.set_comment("datasets".to_string());
+        creator.set_private(true);
+        creator.write_to(dir.join("data.torrent"))?;
+
+        let mut torrent = Torrent::from(dir.join("data.torrent"))?;
+        assert_eq!(50000, torrent.get_total_size());
+        assert_eq!(4, torrent.total_pieces());
+
+        // Torrents are saved in "{path}/{name}", with their files under the directory of the same name.
+        torrent.save_in(dir.to_string_lossy().to_string());
+        let (log_tx, _log_rx) = mpsc::channel();
+        let (notif_tx, _notif_rx) = mpsc::channel();
+        assert_eq!(4, torrent.recheck(notif_tx, &LogHandle::new(log_tx))?);
+
+        fs::remove_dir_all(dir).map_err(err)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_are_not_followed() -> Result<(), String> {
+        use std::os::unix::fs::symlink;
+
+        let dir = test_dir("create_symlinks");
+        let err = |e: Error| e.to_string();
+        fs::create_dir_all(dir.join("data/sub")).map_err(err)?;
+        fs::write(dir.join("data/sub/a.txt"), b"inside").map_err(err)?;
+        fs::write(dir.join("outside.txt"), b"outside").map_err(err)?;
+        symlink(dir.join("data"), dir.join("data/sub/ancestor")).map_err(err)?;
+        symlink(dir.join("outside.txt"), dir.join("data/outside.txt")).map_err(err)?;
+
+        let mut files = Vec::new();
+        walk_directory(&dir.join("data"), &mut Vec::new(), &mut files)?;
+        assert_eq!(1, files.len());
+        assert_eq!(vec!["sub", "a.txt"], files[0].components);
+
+        fs::remove_dir_all(dir).map_err(err)
+    }
+
+    #[test]
+    fn created_single_file_torrent_round_trips() -> Result<(), String> {
+        let dir = test_dir("create_single");
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        fs::write(dir.join("file.txt"), b"hello world").map_err(|e| e.to_string())?;
+
+        let mut creator = TorrentCreator::new(
+            dir.join("file.txt"),
+            "http://localhost/announce".to_string(),
+        );
+        creator.add_tier(vec!["http://backup/announce".to_string()]);
+        let bytes = creator.create()?;
+
+        assert!(bytes.windows(13).any(|window| window == b"announce-list"));
+        fs::write(dir.join("file.torrent"), bytes).map_err(|e| e.to_string())?;
+        assert_eq!(
+            11,
+            Torrent::from(dir.join("file.torrent"))?.get_total_size()
+        );
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+}
@@ -1,9 +1,13 @@
 mod bdecoding;
+mod bencoding;
 pub mod client;
 pub mod config;
+pub mod create;
 pub mod logging;
-mod messages;
+pub mod messages;
+pub mod network;
 pub mod server;
+pub mod storage;
 pub mod torrent_client;
 pub mod ui_notification_structs;
 mod url_encoding;
@@ -1,8 +1,5 @@
 use crate::{client::download::peer_protocol::ProtocolError, utils};
-use std::{
-    io::{Error, Write},
-    net::TcpStream,
-};
+use std::io::{Error, Write};
 
 pub const BITFIELD_ID: u8 = 5;
 const BITS_IN_BYTE: usize = 8;
@@ -34,7 +31,7 @@ impl Bitfield {
     }
 
     pub fn from(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
-        Ok(Self::new(Vec::from(&bytes[1..])))
+        Ok(Self::new(bytes.get(1..).unwrap_or_default().to_vec()))
     }
 
     pub fn bits(&self) -> Vec<u8> {
@@ -65,7 +62,7 @@ impl Bitfield {
         1 << shift
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
         let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
         stream.write_all(&self.len.to_be_bytes()).map_err(err)?;
         stream.write_all(&self.id.to_be_bytes()).map_err(err)?;
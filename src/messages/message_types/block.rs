@@ -1,8 +1,6 @@
 use std::{
     array::TryFromSliceError,
-    fs::File,
-    io::{Error, Read, Seek, SeekFrom, Write},
-    net::TcpStream,
+    io::{Error, Write},
 };
 
 use crate::client::download::peer_protocol::ProtocolError;
@@ -33,13 +31,25 @@ impl Block {
         let err = |_e: TryFromSliceError| {
             ProtocolError::Peer("Conversion error for Block message".to_string())
         };
-        let index = u32::from_be_bytes(bytes[1..5].try_into().map_err(err)?);
-        let begin = u32::from_be_bytes(bytes[5..9].try_into().map_err(err)?);
-        let block = Vec::from(&bytes[9..]);
+        let index = u32::from_be_bytes(
+            bytes
+                .get(1..5)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
+        let begin = u32::from_be_bytes(
+            bytes
+                .get(5..9)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
+        let block = bytes.get(9..).unwrap_or_default().to_vec();
         Ok(Self::new(index, begin, block))
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
         let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
         stream.write_all(&self.len.to_be_bytes()).map_err(err)?;
         stream.write_all(&self.id.to_be_bytes()).map_err(err)?;
@@ -48,14 +58,6 @@ impl Block {
         stream.write_all(&self.block).map_err(err)
     }
 
-    pub fn load_from(&mut self, path: String) -> Result<(), String> {
-        let err = |e: Error| e.to_string();
-        let block_path = format!("{path}/.tmp/{}", self.index);
-        let mut file = File::open(block_path).map_err(err)?;
-        file.seek(SeekFrom::Start(self.begin as u64)).map_err(err)?;
-        file.read_exact(&mut self.block).map_err(err)
-    }
-
     pub fn index(&self) -> u32 {
         self.index
     }
@@ -72,6 +74,10 @@ impl Block {
         self.block.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.block.is_empty()
+    }
+
     pub fn next_begin(&self) -> usize {
         self.begin as usize + self.len()
     }
@@ -2,11 +2,10 @@ use crate::client::download::peer_protocol::ProtocolError;
 use std::{
     array::TryFromSliceError,
     io::{Error, Write},
-    net::TcpStream,
 };
 
 pub const CANCEL_ID: u8 = 8;
-const CANCEL_LEN: u32 = 13;
+pub const CANCEL_LEN: u32 = 13;
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct Cancel {
@@ -32,13 +31,31 @@ impl Cancel {
         let err = |_e: TryFromSliceError| {
             ProtocolError::Peer("Conversion error for Cancel message".to_string())
         };
-        let index = u32::from_be_bytes(bytes[1..5].try_into().map_err(err)?);
-        let begin = u32::from_be_bytes(bytes[5..9].try_into().map_err(err)?);
-        let length = u32::from_be_bytes(bytes[9..13].try_into().map_err(err)?);
+        let index = u32::from_be_bytes(
+            bytes
+                .get(1..5)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
+        let begin = u32::from_be_bytes(
+            bytes
+                .get(5..9)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
+        let length = u32::from_be_bytes(
+            bytes
+                .get(9..13)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
         Ok(Self::new(index, begin, length))
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
         let size = self.length as i32 - self.begin as i32;
         if size <= 16384 {
             let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
@@ -1,8 +1,5 @@
-use crate::client::download::peer_protocol::ProtocolError;
-use std::{
-    io::{Error, Write},
-    net::TcpStream,
-};
+use crate::{client::download::peer_protocol::ProtocolError, utils};
+use std::io::{Error, Write};
 
 pub const HANDSHAKE_PSTR: &str = "BitTorrent protocol";
 
@@ -26,7 +23,29 @@ impl Handshake {
         }
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    /// Parses a whole handshake, from its `pstrlen` byte to the end of the peer id.
+    pub fn from(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let err = || ProtocolError::Peer("Conversion error for Handshake message".to_string());
+        let pstrlen = *bytes.first().ok_or_else(err)? as usize;
+        let pstr = bytes.get(1..(1 + pstrlen)).ok_or_else(err)?;
+        let field = |begin: usize, len: usize| {
+            bytes.get((1 + pstrlen + begin)..(1 + pstrlen + begin + len))
+        };
+
+        let reserved = field(0, 8).ok_or_else(err)?.try_into().map_err(|_| err())?;
+        let info_hash = field(8, 20)
+            .ok_or_else(err)?
+            .try_into()
+            .map_err(|_| err())?;
+        let peer_id = field(28, 20)
+            .ok_or_else(err)?
+            .try_into()
+            .map_err(|_| err())?;
+        let pstr = utils::bytes_to_string(pstr).map_err(ProtocolError::Peer)?;
+        Ok(Self::new(&pstr, reserved, info_hash, peer_id))
+    }
+
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
         let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
         stream.write_all(&self.pstrlen.to_be_bytes()).map_err(err)?;
         stream.write_all(self.pstr.as_bytes()).map_err(err)?;
@@ -35,6 +54,10 @@ impl Handshake {
         stream.write_all(&self.peer_id).map_err(err)
     }
 
+    pub fn pstr(&self) -> &str {
+        &self.pstr
+    }
+
     pub fn info_hash(&self) -> [u8; 20] {
         self.info_hash
     }
@@ -61,6 +84,17 @@ mod tests {
         assert_eq!(empty_array, handshake.info_hash);
     }
 
+    #[test]
+    fn sent_handshakes_are_parsed_back() -> Result<(), ProtocolError> {
+        let handshake = Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], *b"-PK0001-144591253628");
+        let mut bytes = Vec::new();
+        handshake.send(&mut bytes)?;
+
+        assert_eq!(handshake, Handshake::from(&bytes)?);
+        assert!(Handshake::from(&bytes[..40]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn generate_correctly_handshake_size() {
         let empty_array = [0; 20];
@@ -0,0 +1,36 @@
+use super::hash_request::{HashRequest, HASH_REQUEST_LEN};
+use crate::client::download::peer_protocol::ProtocolError;
+use std::io::{Error, Write};
+
+pub const HASH_REJECT_ID: u8 = 23;
+
+/// Tells a peer that one of its hash requests cannot be answered (BEP 52).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct HashReject {
+    request: HashRequest,
+}
+
+impl HashReject {
+    pub fn new(request: HashRequest) -> Self {
+        Self { request }
+    }
+
+    pub fn from(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
+        if bytes.len() != HASH_REQUEST_LEN as usize {
+            return Err(ProtocolError::Peer(
+                "Invalid length for Hash Reject message".to_string(),
+            ));
+        }
+        Ok(Self::new(HashRequest::fields_from(&bytes)?))
+    }
+
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
+        let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
+        stream
+            .write_all(&HASH_REQUEST_LEN.to_be_bytes())
+            .map_err(err)?;
+        stream
+            .write_all(&self.request.fields_bytes(HASH_REJECT_ID))
+            .map_err(err)
+    }
+}
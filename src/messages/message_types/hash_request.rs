@@ -0,0 +1,128 @@
+use crate::client::{download::peer_protocol::ProtocolError, merkle::MerkleHash};
+use std::io::{Error, Write};
+
+pub const HASH_REQUEST_ID: u8 = 21;
+/// Bytes of the id, pieces root, base layer, index, length and proof layers fields.
+pub const HASH_REQUEST_LEN: u32 = 49;
+
+/// Asks for a range of the hashes of a layer of the merkle tree of a file (BEP 52).
+/// Hash reject messages carry the same fields, and hashes messages start with them.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct HashRequest {
+    pieces_root: MerkleHash,
+    base_layer: u32,
+    index: u32,
+    length: u32,
+    proof_layers: u32,
+}
+
+impl HashRequest {
+    pub fn new(
+        pieces_root: MerkleHash,
+        base_layer: u32,
+        index: u32,
+        length: u32,
+        proof_layers: u32,
+    ) -> Self {
+        Self {
+            pieces_root,
+            base_layer,
+            index,
+            length,
+            proof_layers,
+        }
+    }
+
+    pub fn from(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
+        if bytes.len() != HASH_REQUEST_LEN as usize {
+            return Err(ProtocolError::Peer(
+                "Invalid length for Hash Request message".to_string(),
+            ));
+        }
+        Self::fields_from(&bytes)
+    }
+
+    /// Reads the fields that follow the id of a hash request, hashes or hash reject message.
+    pub fn fields_from(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        let err = || ProtocolError::Peer("Conversion error for hash message fields".to_string());
+        let pieces_root = bytes
+            .get(1..33)
+            .and_then(|root| root.try_into().ok())
+            .ok_or_else(err)?;
+        let mut numbers = [0; 4];
+        for (i, number) in numbers.iter_mut().enumerate() {
+            let begin = 33 + i * 4;
+            let number_bytes = bytes.get(begin..(begin + 4)).ok_or_else(err)?;
+            *number = u32::from_be_bytes(number_bytes.try_into().map_err(|_| err())?);
+        }
+        Ok(Self::new(
+            pieces_root,
+            numbers[0],
+            numbers[1],
+            numbers[2],
+            numbers[3],
+        ))
+    }
+
+    /// Returns the id and fields of a message with these fields, ready to be prefixed with its length.
+    pub fn fields_bytes(&self, id: u8) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HASH_REQUEST_LEN as usize);
+        bytes.push(id);
+        bytes.extend_from_slice(&self.pieces_root);
+        for number in [self.base_layer, self.index, self.length, self.proof_layers] {
+            bytes.extend_from_slice(&number.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
+        let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
+        stream
+            .write_all(&HASH_REQUEST_LEN.to_be_bytes())
+            .map_err(err)?;
+        stream
+            .write_all(&self.fields_bytes(HASH_REQUEST_ID))
+            .map_err(err)
+    }
+
+    pub fn pieces_root(&self) -> MerkleHash {
+        self.pieces_root
+    }
+
+    pub fn base_layer(&self) -> u32 {
+        self.base_layer
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn proof_layers(&self) -> u32 {
+        self.proof_layers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_request_fields_round_trip() -> Result<(), ProtocolError> {
+        let request = HashRequest::new([9; 32], 1, 4, 2, 3);
+        let bytes = request.fields_bytes(HASH_REQUEST_ID);
+
+        assert_eq!(HASH_REQUEST_LEN as usize, bytes.len());
+        assert_eq!(request, HashRequest::from(bytes)?);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_hash_request_is_rejected() {
+        let bytes = HashRequest::new([9; 32], 1, 4, 2, 3).fields_bytes(HASH_REQUEST_ID);
+        assert!(HashRequest::from(bytes[..40].to_vec()).is_err());
+    }
+}
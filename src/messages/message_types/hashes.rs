@@ -0,0 +1,50 @@
+use super::hash_request::{HashRequest, HASH_REQUEST_LEN};
+use crate::client::{download::peer_protocol::ProtocolError, merkle::MerkleHash};
+use std::io::{Error, Write};
+
+pub const HASHES_ID: u8 = 22;
+
+/// Answers a hash request with the requested hashes followed by their uncle hashes (BEP 52).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Hashes {
+    request: HashRequest,
+    hashes: Vec<MerkleHash>,
+}
+
+impl Hashes {
+    pub fn new(request: HashRequest, hashes: Vec<MerkleHash>) -> Self {
+        Self { request, hashes }
+    }
+
+    pub fn from(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
+        let request = HashRequest::fields_from(&bytes)?;
+        let hashes_bytes = &bytes[HASH_REQUEST_LEN as usize..];
+        if hashes_bytes.len() % 32 != 0 {
+            return Err(ProtocolError::Peer(
+                "Hashes message does not hold whole hashes".to_string(),
+            ));
+        }
+
+        let hashes = hashes_bytes
+            .chunks_exact(32)
+            .map(|hash| {
+                hash.try_into().map_err(|_| {
+                    ProtocolError::Peer("Conversion error for Hashes message".to_string())
+                })
+            })
+            .collect::<Result<Vec<MerkleHash>, ProtocolError>>()?;
+        Ok(Self::new(request, hashes))
+    }
+
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
+        let err = |e: Error| ProtocolError::Peer(format!("Failed sending Hashes ({e})"));
+        let len = HASH_REQUEST_LEN + 32 * self.hashes.len() as u32;
+        let mut bytes = self.request.fields_bytes(HASHES_ID);
+        self.hashes
+            .iter()
+            .for_each(|hash| bytes.extend_from_slice(hash));
+
+        stream.write_all(&len.to_be_bytes()).map_err(err)?;
+        stream.write_all(&bytes).map_err(err)
+    }
+}
@@ -1,11 +1,8 @@
 use crate::client::download::peer_protocol::ProtocolError;
-use std::{
-    io::{Error, Write},
-    net::TcpStream,
-};
+use std::io::{Error, Write};
 
 pub const HAVE_ID: u8 = 4;
-const HAVE_LEN: u32 = 5;
+pub const HAVE_LEN: u32 = 5;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Have {
@@ -25,13 +22,13 @@ impl Have {
 
     pub fn from(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
         let piece_index =
-            u32::from_be_bytes(bytes[1..].try_into().map_err(|_| {
+            u32::from_be_bytes(bytes.get(1..).unwrap_or_default().try_into().map_err(|_| {
                 ProtocolError::Peer("Conversion error for Have message".to_string())
             })?);
         Ok(Self::new(piece_index))
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
         let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
         stream.write_all(&self.len.to_be_bytes()).map_err(err)?;
         stream.write_all(&self.id.to_be_bytes()).map_err(err)?;
@@ -2,6 +2,9 @@ pub mod bitfield;
 pub mod block;
 pub mod cancel;
 pub mod handshake;
+pub mod hash_reject;
+pub mod hash_request;
+pub mod hashes;
 pub mod have;
 pub mod interested;
 pub mod request;
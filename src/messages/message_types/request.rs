@@ -1,13 +1,15 @@
 use super::{block::Block, cancel::Cancel};
-use crate::client::download::peer_protocol::{ProtocolError, BLOCK_SIZE};
+use crate::{
+    client::download::peer_protocol::{ProtocolError, BLOCK_SIZE},
+    storage::disk_cache::DiskCache,
+};
 use std::{
     array::TryFromSliceError,
     io::{Error, Write},
-    net::TcpStream,
 };
 
 pub const REQUEST_ID: u8 = 6;
-const REQUEST_LEN: u32 = 13;
+pub const REQUEST_LEN: u32 = 13;
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct Request {
@@ -48,13 +50,31 @@ impl Request {
         let err = |_e: TryFromSliceError| {
             ProtocolError::Peer("Conversion error for Request message".to_string())
         };
-        let index = u32::from_be_bytes(bytes[1..5].try_into().map_err(err)?);
-        let begin = u32::from_be_bytes(bytes[5..9].try_into().map_err(err)?);
-        let length = u32::from_be_bytes(bytes[9..13].try_into().map_err(err)?);
+        let index = u32::from_be_bytes(
+            bytes
+                .get(1..5)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
+        let begin = u32::from_be_bytes(
+            bytes
+                .get(5..9)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
+        let length = u32::from_be_bytes(
+            bytes
+                .get(9..13)
+                .unwrap_or_default()
+                .try_into()
+                .map_err(err)?,
+        );
         Ok(Self::new(index, begin, length))
     }
 
-    pub fn send(&mut self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    pub fn send<W: Write>(&mut self, stream: &mut W) -> Result<(), ProtocolError> {
         if self.sent {
             return Ok(());
         }
@@ -78,10 +98,18 @@ impl Request {
             && block.len() == self.length as usize
     }
 
-    pub fn load_block_from(&self, path: String) -> Result<Block, String> {
-        let mut block = Block::new(self.index, self.begin, vec![0; self.length as usize]);
-        block.load_from(path)?;
-        Ok(block)
+    pub fn load_block_from(
+        &self,
+        download_path: &str,
+        disk_cache: &DiskCache,
+    ) -> Result<Block, String> {
+        let bytes = disk_cache.read_block(
+            download_path,
+            self.index as usize,
+            self.begin as usize,
+            self.length as usize,
+        )?;
+        Ok(Block::new(self.index, self.begin, bytes))
     }
 
     pub fn reset(&mut self) {
@@ -92,6 +120,19 @@ impl Request {
         self.index
     }
 
+    pub fn begin(&self) -> u32 {
+        self.begin
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns the offset of the block that follows the requested one.
+    pub fn next_begin(&self) -> usize {
+        (self.begin + self.length) as usize
+    }
+
     pub fn cancel(&self) -> Cancel {
         Cancel::new(self.index, self.begin, self.length)
     }
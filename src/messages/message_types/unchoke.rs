@@ -1,7 +1,4 @@
-use std::{
-    io::{Error, Write},
-    net::TcpStream,
-};
+use std::io::{Error, Write};
 
 use crate::client::download::peer_protocol::ProtocolError;
 
@@ -14,6 +11,12 @@ pub struct Unchoke {
     id: u8,
 }
 
+impl Default for Unchoke {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Unchoke {
     pub fn new() -> Self {
         Self {
@@ -22,7 +25,7 @@ impl Unchoke {
         }
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> Result<(), ProtocolError> {
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
         let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
         stream.write_all(&self.len.to_be_bytes()).map_err(err)?;
         stream.write_all(&self.id.to_be_bytes()).map_err(err)
@@ -1,17 +1,87 @@
-use crate::client::download::peer_protocol::ProtocolError;
+use crate::{
+    client::download::peer_protocol::{ProtocolError, BLOCK_SIZE, MAX_MESSAGE_LEN},
+    network::ban_list::Offense,
+};
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{Error, Write},
+};
 
 use super::message_types::{
     bitfield::{Bitfield, BITFIELD_ID},
     block::{Block, BLOCK_ID},
-    cancel::{Cancel, CANCEL_ID},
-    have::{Have, HAVE_ID},
-    interested::INTERESTED_ID,
-    request::{Request, REQUEST_ID},
-    unchoke::UNCHOKE_ID,
+    cancel::{Cancel, CANCEL_ID, CANCEL_LEN},
+    hash_reject::{HashReject, HASH_REJECT_ID},
+    hash_request::{HashRequest, HASH_REQUEST_ID, HASH_REQUEST_LEN},
+    hashes::{Hashes, HASHES_ID},
+    have::{Have, HAVE_ID, HAVE_LEN},
+    interested::{Interested, INTERESTED_ID},
+    request::{Request, REQUEST_ID, REQUEST_LEN},
+    unchoke::{Unchoke, UNCHOKE_ID},
 };
 
 const CHOKE_ID: u8 = 0;
 const NOT_INTERESTED_ID: u8 = 3;
+/// Extension protocol messages (BEP 10), which carry peer lists among other things.
+/// The handshake never advertises support for them, so any of them is unsolicited.
+const EXTENDED_ID: u8 = 20;
+/// Longest bitfield that a peer may send, which is enough for torrents of about a million pieces.
+pub const MAX_BITFIELD_LEN: usize = 1 << 17;
+/// Bytes of a block message besides its data.
+const BLOCK_HEADER_LEN: usize = 9;
+/// Most hashes that a single hashes message may have, counting its proof.
+const MAX_HASHES: usize = 1024;
+
+/// Why the bytes of a message are not a valid message. Every one of them is an offense of the peer that
+/// sent the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageError {
+    /// The length prefix of the message is longer than `MAX_MESSAGE_LEN`.
+    TooLong(usize),
+    /// The message is not as long as messages with its id are.
+    InvalidLength { id: u8, len: usize },
+    /// An extension protocol message, which the handshake never asks for.
+    UnsolicitedExtension,
+    /// The message has the right length, but its payload can't be parsed.
+    InvalidPayload { id: u8, reason: String },
+}
+
+impl MessageError {
+    pub fn offense(&self) -> Offense {
+        match self {
+            Self::TooLong(_) => Offense::OversizedMessage,
+            _ => Offense::MalformedMessage,
+        }
+    }
+}
+
+impl Display for MessageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong(len) => {
+                write!(f, "Message of {len} bytes is longer than {MAX_MESSAGE_LEN}")
+            }
+            Self::InvalidLength { id, len } => {
+                write!(
+                    f,
+                    "Message with id {id} has an invalid length of {len} bytes"
+                )
+            }
+            Self::UnsolicitedExtension => {
+                write!(f, "Received an unsolicited extension protocol message")
+            }
+            Self::InvalidPayload { id, reason } => {
+                write!(f, "Message with id {id} has an invalid payload: {reason}")
+            }
+        }
+    }
+}
+
+impl From<MessageError> for ProtocolError {
+    fn from(error: MessageError) -> Self {
+        ProtocolError::Offense(error.offense(), error.to_string())
+    }
+}
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum PeerMessage {
@@ -25,26 +95,171 @@ pub enum PeerMessage {
     Request(Request),
     Block(Block),
     Cancel(Cancel),
+    HashRequest(HashRequest),
+    Hashes(Hashes),
+    HashReject(HashReject),
+    /// A message with an id that the client does not know, with the id and the payload as they arrived.
+    /// Peers may send these for extensions that are newer than the client, so they are ignored.
+    Unknown(Vec<u8>),
 }
 
 impl PeerMessage {
-    pub fn from(bytes: Vec<u8>) -> Result<Self, ProtocolError> {
-        if bytes.is_empty() {
+    /// Parses a message without its length prefix, checking first that it is as long as messages with its
+    /// id are.
+    pub fn from(bytes: Vec<u8>) -> Result<Self, MessageError> {
+        let Some(&id) = bytes.first() else {
             return Ok(Self::KeepAlive);
+        };
+        let len = bytes.len();
+        let (min_len, max_len) = Self::len_range(id)?;
+        if len < min_len || len > max_len {
+            return Err(MessageError::InvalidLength { id, len });
         }
-        match bytes[0] {
+
+        let invalid = |e: ProtocolError| MessageError::InvalidPayload {
+            id,
+            reason: e.to_string(),
+        };
+        match id {
             CHOKE_ID => Ok(Self::Choke),
             UNCHOKE_ID => Ok(Self::Unchoke),
             INTERESTED_ID => Ok(Self::Interested),
             NOT_INTERESTED_ID => Ok(Self::NotInterested),
-            HAVE_ID => Ok(Self::Have(Have::from(bytes)?)),
-            BITFIELD_ID => Ok(Self::Bitfield(Bitfield::from(bytes)?)),
-            REQUEST_ID => Ok(Self::Request(Request::from(bytes)?)),
-            BLOCK_ID => Ok(Self::Block(Block::from(bytes)?)),
-            CANCEL_ID => Ok(Self::Cancel(Cancel::from(bytes)?)),
-            _ => Err(ProtocolError::Peer(
-                "Couldn't parse peer message".to_string(),
+            HAVE_ID => Have::from(bytes).map(Self::Have),
+            BITFIELD_ID => Bitfield::from(bytes).map(Self::Bitfield),
+            REQUEST_ID => Request::from(bytes).map(Self::Request),
+            BLOCK_ID => Block::from(bytes).map(Self::Block),
+            CANCEL_ID => Cancel::from(bytes).map(Self::Cancel),
+            HASH_REQUEST_ID => HashRequest::from(bytes).map(Self::HashRequest),
+            HASHES_ID => Hashes::from(bytes).map(Self::Hashes),
+            HASH_REJECT_ID => HashReject::from(bytes).map(Self::HashReject),
+            _ => return Ok(Self::Unknown(bytes)),
+        }
+        .map_err(invalid)
+    }
+
+    /// Returns the shortest and the longest that a message with `id` can be, counting the id itself.
+    fn len_range(id: u8) -> Result<(usize, usize), MessageError> {
+        let exactly = |len: u32| (len as usize, len as usize);
+        match id {
+            CHOKE_ID | UNCHOKE_ID | INTERESTED_ID | NOT_INTERESTED_ID => Ok((1, 1)),
+            HAVE_ID => Ok(exactly(HAVE_LEN)),
+            BITFIELD_ID => Ok((1, 1 + MAX_BITFIELD_LEN)),
+            REQUEST_ID => Ok(exactly(REQUEST_LEN)),
+            CANCEL_ID => Ok(exactly(CANCEL_LEN)),
+            // The client never requests blocks longer than `BLOCK_SIZE`.
+            BLOCK_ID => Ok((BLOCK_HEADER_LEN, BLOCK_HEADER_LEN + BLOCK_SIZE as usize)),
+            HASH_REQUEST_ID | HASH_REJECT_ID => Ok(exactly(HASH_REQUEST_LEN)),
+            HASHES_ID => Ok((
+                HASH_REQUEST_LEN as usize,
+                HASH_REQUEST_LEN as usize + 32 * MAX_HASHES,
             )),
+            EXTENDED_ID => Err(MessageError::UnsolicitedExtension),
+            _ => Ok((1, MAX_MESSAGE_LEN)),
+        }
+    }
+
+    /// Writes the message with its length prefix, as it travels through the wire.
+    pub fn send<W: Write>(&self, stream: &mut W) -> Result<(), ProtocolError> {
+        let err = |e: Error| ProtocolError::Peer(format!("Failed sending {self:?} ({e})"));
+        match self {
+            Self::KeepAlive => stream.write_all(&0_u32.to_be_bytes()).map_err(err),
+            Self::Choke => stream.write_all(&[0, 0, 0, 1, CHOKE_ID]).map_err(err),
+            Self::Unchoke => Unchoke::new().send(stream),
+            Self::Interested => Interested::new().send(stream),
+            Self::NotInterested => stream
+                .write_all(&[0, 0, 0, 1, NOT_INTERESTED_ID])
+                .map_err(err),
+            Self::Have(have) => have.send(stream),
+            Self::Bitfield(bitfield) => bitfield.send(stream),
+            Self::Request(request) => request.clone().send(stream),
+            Self::Block(block) => block.send(stream),
+            Self::Cancel(cancel) => cancel.send(stream),
+            Self::HashRequest(request) => request.send(stream),
+            Self::Hashes(hashes) => hashes.send(stream),
+            Self::HashReject(reject) => reject.send(stream),
+            Self::Unknown(bytes) => stream
+                .write_all(&[&(bytes.len() as u32).to_be_bytes(), bytes.as_slice()].concat())
+                .map_err(err),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sent_messages_are_parsed_back() -> Result<(), ProtocolError> {
+        let messages = vec![
+            PeerMessage::Choke,
+            PeerMessage::NotInterested,
+            PeerMessage::Have(Have::new(7)),
+            PeerMessage::Request(Request::new(1, 16384, 16384)),
+            PeerMessage::Block(Block::new(1, 0, vec![9; 10])),
+            PeerMessage::Unknown(vec![42, 1, 2]),
+        ];
+
+        for message in messages {
+            let mut bytes = Vec::new();
+            message.send(&mut bytes)?;
+            let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+            assert_eq!(bytes.len() - 4, len);
+            assert_eq!(message, PeerMessage::from(bytes[4..].to_vec())?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unsolicited_extended_messages_are_rejected() {
+        let pex_message = [vec![EXTENDED_ID, 1], b"d5:added6:abcdefe".to_vec()].concat();
+        assert!(PeerMessage::from(pex_message).is_err());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected_with_typed_errors() {
+        let invalid_length = |bytes: Vec<u8>| MessageError::InvalidLength {
+            id: bytes[0],
+            len: bytes.len(),
+        };
+        let malformed = vec![
+            vec![CHOKE_ID, 0],
+            vec![INTERESTED_ID, 1, 2, 3, 4],
+            vec![HAVE_ID],
+            vec![HAVE_ID, 0, 0, 0],
+            vec![HAVE_ID, 0, 0, 0, 1, 0],
+            [vec![REQUEST_ID], vec![0; 8]].concat(),
+            [vec![REQUEST_ID], vec![0; 13]].concat(),
+            [vec![CANCEL_ID], vec![0; 4]].concat(),
+            vec![BLOCK_ID, 0, 0, 0, 1],
+            [vec![BLOCK_ID], vec![0; 8 + BLOCK_SIZE as usize + 1]].concat(),
+            [vec![BITFIELD_ID], vec![0xff; MAX_BITFIELD_LEN + 1]].concat(),
+            [vec![HASH_REQUEST_ID], vec![0; 40]].concat(),
+            [vec![HASH_REJECT_ID], vec![0; 60]].concat(),
+            [vec![HASHES_ID], vec![0; 10]].concat(),
+        ];
+        for bytes in malformed {
+            assert_eq!(Err(invalid_length(bytes.clone())), PeerMessage::from(bytes));
+        }
+
+        assert_eq!(
+            Ok(PeerMessage::Unknown(vec![42, 1, 2])),
+            PeerMessage::from(vec![42, 1, 2])
+        );
+        assert!(matches!(
+            PeerMessage::from([vec![HASHES_ID], vec![0; 48 + 31]].concat()),
+            Err(MessageError::InvalidPayload { id: HASHES_ID, .. })
+        ));
+        assert!(matches!(
+            ProtocolError::from(MessageError::TooLong(MAX_MESSAGE_LEN + 1)),
+            ProtocolError::Offense(Offense::OversizedMessage, _)
+        ));
+        assert!(matches!(
+            ProtocolError::from(MessageError::InvalidLength {
+                id: HAVE_ID,
+                len: 1
+            }),
+            ProtocolError::Offense(Offense::MalformedMessage, _)
+        ));
+    }
+}
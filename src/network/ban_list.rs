@@ -0,0 +1,94 @@
+use crate::logging::log_handle::LogHandle;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Strikes after which a peer is banned.
+pub const BAN_STRIKES: usize = 3;
+
+/// Misbehaviour of a peer that counts against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offense {
+    /// The peer sent blocks of a piece that did not match its hash.
+    BadPiece,
+    /// The peer sent a message that could not be parsed.
+    MalformedMessage,
+    /// The peer requested a block outside of the pieces of the torrent, or a block that is too long.
+    OutOfRangeRequest,
+    /// The peer sent a message longer than any valid one.
+    OversizedMessage,
+}
+
+impl Offense {
+    /// Corrupt data may be an accident, so it takes a few times to ban a peer, while a peer that breaks
+    /// the protocol is banned right away.
+    fn strikes(&self) -> usize {
+        match self {
+            Self::BadPiece | Self::OutOfRangeRequest => 1,
+            Self::MalformedMessage | Self::OversizedMessage => BAN_STRIKES,
+        }
+    }
+}
+
+/// Strikes of the peers that misbehaved during this session of the client, by IP address. Peers with
+/// `BAN_STRIKES` or more are banned, so the client neither connects to them nor accepts them until it
+/// is restarted. Clones share the list.
+#[derive(Debug, Clone, Default)]
+pub struct BanList {
+    strikes: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl BanList {
+    /// Counts an offense against the peer at `ip`. Returns true if the peer was banned because of it.
+    pub fn strike(&self, ip: &str, offense: Offense) -> Result<bool, String> {
+        let mut strikes = self.strikes.lock().map_err(|e| e.to_string())?;
+        let peer_strikes = strikes.entry(ip.to_string()).or_default();
+        let was_banned = *peer_strikes >= BAN_STRIKES;
+        *peer_strikes += offense.strikes();
+        Ok(!was_banned && *peer_strikes >= BAN_STRIKES)
+    }
+
+    /// Counts an offense against every peer in `ips`, and logs the ones that get banned.
+    pub fn report(
+        &self,
+        ips: &[String],
+        offense: Offense,
+        log_handle: &LogHandle,
+    ) -> Result<(), String> {
+        for ip in ips {
+            if self.strike(ip, offense)? {
+                log_handle.log(&format!("Banned peer {ip} for {offense:?}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_banned(&self, ip: &str) -> Result<bool, String> {
+        let strikes = self.strikes.lock().map_err(|e| e.to_string())?;
+        Ok(strikes
+            .get(ip)
+            .is_some_and(|strikes| *strikes >= BAN_STRIKES))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peers_are_banned_after_repeated_corrupt_data_or_one_protocol_violation() -> Result<(), String>
+    {
+        let ban_list = BanList::default();
+        assert!(!ban_list.strike("10.0.0.1", Offense::BadPiece)?);
+        assert!(!ban_list.strike("10.0.0.1", Offense::OutOfRangeRequest)?);
+        assert!(!ban_list.is_banned("10.0.0.1")?);
+        assert!(ban_list.strike("10.0.0.1", Offense::BadPiece)?);
+        assert!(ban_list.clone().is_banned("10.0.0.1")?);
+
+        assert!(ban_list.strike("10.0.0.2", Offense::OversizedMessage)?);
+        assert!(!ban_list.strike("10.0.0.2", Offense::MalformedMessage)?);
+        assert!(!ban_list.is_banned("10.0.0.3")?);
+        Ok(())
+    }
+}
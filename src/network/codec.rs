@@ -0,0 +1,118 @@
+use crate::{
+    client::download::peer_protocol::{self, ProtocolError},
+    messages::{message_types::handshake::Handshake, peer_message::PeerMessage},
+};
+
+/// Bytes that a handshake has besides its protocol string.
+const HANDSHAKE_FIXED_LEN: usize = 49;
+const LEN_PREFIX: usize = 4;
+
+/// A complete unit of the peer wire protocol.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Frame {
+    Handshake(Handshake),
+    Message(PeerMessage),
+}
+
+/// Splits the bytes that arrive from a non-blocking socket into frames: the handshake first, and then
+/// length prefixed peer messages. Bytes of a frame that is not complete yet stay buffered until the rest
+/// of it arrives.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    /// Offset of the first byte that was not decoded yet.
+    start: usize,
+    handshake_received: bool,
+}
+
+impl FrameDecoder {
+    pub fn extend(&mut self, bytes: &[u8]) {
+        // Decoded bytes are only dropped once they are most of the buffer, so they are not moved on every read.
+        if self.start > 0 && self.start * 2 >= self.buffer.len() {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn handshake_received(&self) -> bool {
+        self.handshake_received
+    }
+
+    /// Returns the amount of bytes that were received and not decoded yet.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    /// Returns the next complete frame, or `None` if more bytes are needed.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        let pending = &self.buffer[self.start..];
+
+        if !self.handshake_received {
+            let Some(&pstrlen) = pending.first() else {
+                return Ok(None);
+            };
+            let len = HANDSHAKE_FIXED_LEN + pstrlen as usize;
+            if pending.len() < len {
+                return Ok(None);
+            }
+            let handshake = Handshake::from(&pending[..len])?;
+            self.start += len;
+            self.handshake_received = true;
+            return Ok(Some(Frame::Handshake(handshake)));
+        }
+
+        let Some(prefix) = pending.get(..LEN_PREFIX) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        peer_protocol::check_message_len(len)?;
+        let Some(message_bytes) = pending.get(LEN_PREFIX..(LEN_PREFIX + len)) else {
+            return Ok(None);
+        };
+
+        let message = PeerMessage::from(message_bytes.to_vec())?;
+        self.start += LEN_PREFIX + len;
+        Ok(Some(Frame::Message(message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::message_types::{
+        block::Block,
+        handshake::{Handshake, HANDSHAKE_PSTR},
+        have::Have,
+    };
+
+    #[test]
+    fn frames_split_across_reads_are_decoded_once_complete() -> Result<(), ProtocolError> {
+        let handshake = Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], [2; 20]);
+        let block = PeerMessage::Block(Block::new(0, 0, vec![7; 100]));
+        let mut bytes = Vec::new();
+        handshake.send(&mut bytes)?;
+        PeerMessage::Have(Have::new(3)).send(&mut bytes)?;
+        block.send(&mut bytes)?;
+
+        let mut decoder = FrameDecoder::default();
+        let mut frames = Vec::new();
+        for chunk in bytes.chunks(7) {
+            decoder.extend(chunk);
+            while let Some(frame) = decoder.next_frame()? {
+                frames.push(frame);
+            }
+        }
+
+        assert_eq!(
+            vec![
+                Frame::Handshake(handshake),
+                Frame::Message(PeerMessage::Have(Have::new(3))),
+                Frame::Message(block),
+            ],
+            frames
+        );
+        assert_eq!(0, decoder.buffered());
+        Ok(())
+    }
+}
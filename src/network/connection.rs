@@ -0,0 +1,277 @@
+use super::{
+    codec::{Frame, FrameDecoder},
+    event_loop::Timers,
+};
+use crate::{client::download::peer_protocol::ProtocolError, messages::peer_message::PeerMessage};
+use mio::net::TcpStream;
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Identifies a connection of an event loop.
+pub type ConnectionId = usize;
+
+const READ_CHUNK: usize = 64 * 1024;
+/// Nothing more is read from a peer while this many bytes that were sent to it wait to be written, so a
+/// peer that requests blocks without reading them cannot make the client buffer them without bound.
+pub const MAX_PENDING_BYTES: usize = 1024 * 1024;
+
+/// A non-blocking connection to a peer. Messages that handlers send are buffered, and the event loop writes
+/// them once the socket is ready, so handlers never block.
+#[derive(Debug)]
+pub struct Connection {
+    id: ConnectionId,
+    stream: TcpStream,
+    address: SocketAddr,
+    outbound: bool,
+    connected: bool,
+    decoder: FrameDecoder,
+    outgoing: Vec<u8>,
+    /// Amount of bytes of `outgoing` that were already written to the socket.
+    written: usize,
+    /// When the connection was established, or when it was opened while it is still in progress.
+    connected_at: Instant,
+    last_read: Instant,
+    last_write: Instant,
+    /// Nothing is read from the socket until then, so a peer cannot transfer faster than its rate limits.
+    reads_paused_until: Option<Instant>,
+    /// When the bytes waiting to be written went over `MAX_PENDING_BYTES`, if they still are.
+    backlogged_since: Option<Instant>,
+    /// True if frames were left unread because of the backlog, so they are read once it is written.
+    reads_blocked: bool,
+    closed: bool,
+}
+
+impl Connection {
+    pub(super) fn new(
+        id: ConnectionId,
+        stream: TcpStream,
+        address: SocketAddr,
+        outbound: bool,
+    ) -> Self {
+        Self {
+            id,
+            stream,
+            address,
+            outbound,
+            // Outgoing connections are in progress until the socket becomes writable.
+            connected: !outbound,
+            decoder: FrameDecoder::default(),
+            outgoing: Vec::new(),
+            written: 0,
+            connected_at: Instant::now(),
+            last_read: Instant::now(),
+            last_write: Instant::now(),
+            reads_paused_until: None,
+            backlogged_since: None,
+            reads_blocked: false,
+            closed: false,
+        }
+    }
+
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns true if the client opened the connection, and false if the peer did.
+    pub fn is_outbound(&self) -> bool {
+        self.outbound
+    }
+
+    /// Returns the amount of bytes that were sent by handlers and not written to the socket yet.
+    pub fn pending_bytes(&self) -> usize {
+        self.outgoing.len() - self.written
+    }
+
+    /// Returns true if the peer does not take what is sent to it, so nothing more is read from it.
+    pub fn is_backlogged(&self) -> bool {
+        self.pending_bytes() > MAX_PENDING_BYTES
+    }
+
+    /// Makes the event loop close the connection after writing what is already buffered.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Stops reading from the socket until `until`. Bytes that the peer sends meanwhile wait in the socket,
+    /// which makes it slow down.
+    pub fn pause_reads(&mut self, until: Instant) {
+        self.reads_paused_until = Some(until);
+    }
+
+    pub(super) fn reads_paused_until(&self) -> Option<Instant> {
+        self.reads_paused_until
+    }
+
+    /// Lets the connection read again once its pause is over. Returns true if the pause ended.
+    pub(super) fn resume_reads(&mut self, now: Instant) -> bool {
+        match self.reads_paused_until {
+            Some(until) if until <= now => {
+                self.reads_paused_until = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn stream(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Returns an error if the connection ran out of time to be established, to receive the handshake of the
+    /// peer, or to receive anything else, or if the peer did not read what was sent to it for as long.
+    pub(super) fn check_timers(&self, timers: &Timers, now: Instant) -> Result<(), String> {
+        if let Some(since) = self.backlogged_since {
+            if now.saturating_duration_since(since) >= timers.idle_timeout {
+                return Err(format!("{} does not read what is sent to it", self.address));
+            }
+        }
+        let (since, timeout) = if !self.connected {
+            (self.connected_at, timers.connect_timeout)
+        } else if !self.decoder.handshake_received() {
+            (self.connected_at, timers.handshake_timeout)
+        } else {
+            (self.last_read, timers.idle_timeout)
+        };
+        if now.saturating_duration_since(since) >= timeout {
+            return Err(format!("Connection to {} timed out", self.address));
+        }
+        Ok(())
+    }
+
+    /// Checks whether an outgoing connection was established, once its socket reports an event.
+    pub(super) fn check_connected(&mut self) -> Result<(), String> {
+        if self.connected {
+            return Ok(());
+        }
+        if let Some(e) = self.stream.take_error().map_err(|e| e.to_string())? {
+            return Err(format!("Error connecting to {}: {e}", self.address));
+        }
+        match self.stream.peer_addr() {
+            Ok(_) => {
+                self.connected = true;
+                self.connected_at = Instant::now();
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::NotConnected => Ok(()),
+            Err(e) => Err(format!("Error connecting to {}: {e}", self.address)),
+        }
+    }
+
+    /// Returns the next frame that the peer sent, reading every byte that is available once the buffered ones
+    /// do not complete any. Nothing is read while reads are paused or while the connection is backlogged,
+    /// which handlers make happen as they answer each frame.
+    pub(super) fn next_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        if self.reads_paused_until.is_some() {
+            return Ok(None);
+        }
+        if self.is_backlogged() {
+            self.reads_blocked = true;
+            return Ok(None);
+        }
+        if let Some(frame) = self.decoder.next_frame()? {
+            return Ok(Some(frame));
+        }
+        if self.closed {
+            return Ok(None);
+        }
+        self.read_available()?;
+        self.decoder.next_frame()
+    }
+
+    /// Returns true if reads stopped because of the backlog and it was written since, so the frames left
+    /// behind are read now. The socket does not report them again.
+    pub(super) fn resume_blocked_reads(&mut self) -> bool {
+        if self.reads_blocked && !self.is_backlogged() {
+            self.reads_blocked = false;
+            return true;
+        }
+        false
+    }
+
+    fn read_available(&mut self) -> Result<(), ProtocolError> {
+        let mut chunk = [0; READ_CHUNK];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(read) => {
+                    self.decoder.extend(&chunk[..read]);
+                    self.last_read = Instant::now();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(ProtocolError::Peer(format!(
+                        "Error reading from {}: {e}",
+                        self.address
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes as much of the buffered bytes as the socket accepts without blocking.
+    pub(super) fn write_pending(&mut self) -> Result<(), String> {
+        if !self.connected {
+            return Ok(());
+        }
+        while self.written < self.outgoing.len() {
+            match self.stream.write(&self.outgoing[self.written..]) {
+                Ok(0) => return Err(format!("Connection to {} was closed", self.address)),
+                Ok(written) => {
+                    self.written += written;
+                    self.last_write = Instant::now();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("Error writing to {}: {e}", self.address)),
+            }
+        }
+        if self.written == self.outgoing.len() {
+            self.outgoing.clear();
+            self.written = 0;
+        }
+        match (self.is_backlogged(), self.backlogged_since) {
+            (true, None) => self.backlogged_since = Some(Instant::now()),
+            (false, Some(_)) => self.backlogged_since = None,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Sends a keep-alive if nothing was sent to the peer for `interval`.
+    pub(super) fn keep_alive(&mut self, interval: Duration) -> Result<(), String> {
+        if self.connected && self.pending_bytes() == 0 && self.last_write.elapsed() >= interval {
+            PeerMessage::KeepAlive
+                .send(self)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Buffers the bytes of the messages that are sent through the connection.
+impl Write for Connection {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,567 @@
+use super::{
+    ban_list::Offense,
+    codec::Frame,
+    connection::{Connection, ConnectionId},
+};
+use crate::{
+    client::download::peer_protocol::ProtocolError,
+    messages::{message_types::handshake::Handshake, peer_message::PeerMessage},
+};
+use mio::{
+    net::{TcpListener, TcpStream},
+    Events, Interest, Poll, Token,
+};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+const LISTENER: Token = Token(usize::MAX);
+const EVENTS_CAPACITY: usize = 1024;
+/// How often the loop checks the timers of its connections and lets handlers send messages of their own.
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time limits of the connections to peers, both of event loops and of download workers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timers {
+    /// Connections that are not established within this long are given up.
+    pub connect_timeout: Duration,
+    /// Peers that do not send their handshake within this long of connecting are disconnected.
+    pub handshake_timeout: Duration,
+    /// Longest that a download worker waits for a message before it checks the other timers.
+    pub read_timeout: Duration,
+    /// A keep-alive is sent to peers that were not sent anything for this long.
+    pub keep_alive: Duration,
+    /// Peers that do not send anything, or that do not read what the client sends, for this long are
+    /// disconnected.
+    pub idle_timeout: Duration,
+    /// Peers that keep the client choked while it is interested, or that do not send any of the requested
+    /// blocks, for this long are snubbing it.
+    pub snub_timeout: Duration,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(5),
+            keep_alive: Duration::from_secs(120),
+            idle_timeout: Duration::from_secs(180),
+            snub_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Reacts to what happens on the connections of an event loop. Handlers answer by sending messages
+/// through the connection, which buffers them, and any error closes the connection.
+pub trait PeerHandler {
+    /// Called before a connection of a peer is accepted, which is dropped right away unless this returns true.
+    fn on_accept(&mut self, _address: SocketAddr) -> bool {
+        true
+    }
+
+    /// Called once the handshake of the peer arrives. Handlers of incoming connections answer it with their own.
+    fn on_handshake(
+        &mut self,
+        connection: &mut Connection,
+        handshake: Handshake,
+    ) -> Result<(), String>;
+
+    fn on_message(
+        &mut self,
+        connection: &mut Connection,
+        message: PeerMessage,
+    ) -> Result<(), String>;
+
+    /// Called once on every tick before the connections tick, so handlers can do work that is not about a
+    /// single connection.
+    fn on_tick_start(&mut self) {}
+
+    /// Called on every tick after `on_tick_start`. The loop opens a connection to each of the returned
+    /// addresses, which receives its handshake as soon as the connection is established.
+    fn peers_to_connect(&mut self) -> Vec<(SocketAddr, Handshake)> {
+        Vec::new()
+    }
+
+    /// Called when a connection that `peers_to_connect` asked for could not even be opened.
+    fn on_connect_error(&mut self, _address: SocketAddr, _error: &str) {}
+
+    /// Called on every tick, so handlers can send messages that do not answer another one.
+    fn on_tick(&mut self, _connection: &mut Connection) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called when the frames of the peer break the protocol, right before its connection is closed.
+    fn on_offense(&mut self, _connection: &Connection, _offense: Offense) {}
+
+    fn on_close(&mut self, _connection: &Connection) {}
+}
+
+/// Runs the connections of many peers on a single thread, waiting for the readiness of every socket at once
+/// instead of blocking on each of them.
+pub struct EventLoop<H: PeerHandler> {
+    poll: Poll,
+    events: Events,
+    listener: Option<TcpListener>,
+    connections: HashMap<ConnectionId, Connection>,
+    next_id: ConnectionId,
+    handler: H,
+    timers: Timers,
+    last_tick: Instant,
+}
+
+impl<H: PeerHandler> EventLoop<H> {
+    pub fn new(handler: H) -> Result<Self, String> {
+        Ok(Self {
+            poll: Poll::new().map_err(|e| e.to_string())?,
+            events: Events::with_capacity(EVENTS_CAPACITY),
+            listener: None,
+            connections: HashMap::new(),
+            next_id: 0,
+            handler,
+            timers: Timers::default(),
+            last_tick: Instant::now(),
+        })
+    }
+
+    pub fn set_timers(&mut self, timers: Timers) {
+        self.timers = timers;
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Accepts the connections of peers at `address`. Returns the address that was bound.
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> Result<SocketAddr, String> {
+        let address = address
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or("Missing address to listen at")?;
+        let mut listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+        self.poll
+            .registry()
+            .register(&mut listener, LISTENER, Interest::READABLE)
+            .map_err(|e| e.to_string())?;
+
+        let local_address = listener.local_addr().map_err(|e| e.to_string())?;
+        self.listener = Some(listener);
+        Ok(local_address)
+    }
+
+    /// Opens a connection to a peer, which receives `handshake` as soon as it is established.
+    pub fn connect(
+        &mut self,
+        address: SocketAddr,
+        handshake: Handshake,
+    ) -> Result<ConnectionId, String> {
+        let stream = TcpStream::connect(address).map_err(|e| e.to_string())?;
+        let id = self.register(stream, address, true)?;
+        if let Some(connection) = self.connections.get_mut(&id) {
+            handshake.send(connection).map_err(|e| e.to_string())?;
+        }
+        Ok(id)
+    }
+
+    /// Handles every connection forever.
+    pub fn run(&mut self) -> Result<(), String> {
+        loop {
+            self.turn(TICK_INTERVAL)?;
+        }
+    }
+
+    /// Waits up to `timeout` for sockets to be ready and handles them, along with the timers that expired.
+    pub fn turn(&mut self, timeout: Duration) -> Result<(), String> {
+        let until_tick = TICK_INTERVAL.saturating_sub(self.last_tick.elapsed());
+        let until_resume = self
+            .connections
+            .values()
+            .filter_map(Connection::reads_paused_until)
+            .min()
+            .map_or(until_tick, |until| {
+                until.saturating_duration_since(Instant::now())
+            });
+        match self.poll.poll(
+            &mut self.events,
+            Some(timeout.min(until_tick).min(until_resume)),
+        ) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let ready: Vec<(Token, bool)> = self
+            .events
+            .iter()
+            .map(|event| (event.token(), event.is_readable()))
+            .collect();
+        for (token, readable) in ready {
+            if token == LISTENER {
+                self.accept()?;
+            } else {
+                self.handle_ready(token.0, readable);
+            }
+        }
+
+        self.resume_reads();
+
+        if self.last_tick.elapsed() >= TICK_INTERVAL {
+            self.last_tick = Instant::now();
+            self.tick();
+        }
+        Ok(())
+    }
+
+    fn register(
+        &mut self,
+        mut stream: TcpStream,
+        address: SocketAddr,
+        outbound: bool,
+    ) -> Result<ConnectionId, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.poll
+            .registry()
+            .register(
+                &mut stream,
+                Token(id),
+                Interest::READABLE | Interest::WRITABLE,
+            )
+            .map_err(|e| e.to_string())?;
+        self.connections
+            .insert(id, Connection::new(id, stream, address, outbound));
+        Ok(id)
+    }
+
+    fn accept(&mut self) -> Result<(), String> {
+        loop {
+            let Some(listener) = &self.listener else {
+                return Ok(());
+            };
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    if self.handler.on_accept(address) {
+                        self.register(stream, address, false)?;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // Errors of a single connection, such as a peer that reset it before being accepted.
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+
+    fn handle_ready(&mut self, id: ConnectionId, readable: bool) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+        let result = Self::handle_connection(&mut self.handler, connection, readable);
+        if result.is_err() || connection.is_closed() {
+            self.close(id);
+        }
+    }
+
+    fn handle_connection(
+        handler: &mut H,
+        connection: &mut Connection,
+        readable: bool,
+    ) -> Result<(), String> {
+        connection.check_connected()?;
+        let mut readable = readable;
+        loop {
+            if readable {
+                Self::handle_frames(handler, connection)?;
+            }
+            connection.write_pending()?;
+            // Frames left behind while the peer did not take what was sent to it are read once it does.
+            readable = connection.resume_blocked_reads();
+            if !readable {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Hands every frame that arrived to the handler, until the answers fill the connection.
+    fn handle_frames(handler: &mut H, connection: &mut Connection) -> Result<(), String> {
+        loop {
+            let frame = match connection.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(ProtocolError::Offense(offense, e)) => {
+                    handler.on_offense(connection, offense);
+                    return Err(e);
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            match frame {
+                Frame::Handshake(handshake) => handler.on_handshake(connection, handshake)?,
+                Frame::Message(message) => handler.on_message(connection, message)?,
+            }
+        }
+    }
+
+    /// Reads what arrived at the connections whose pause is over, since their sockets do not report it again.
+    fn resume_reads(&mut self) {
+        let now = Instant::now();
+        let resumed: Vec<ConnectionId> = self
+            .connections
+            .iter_mut()
+            .filter_map(|(id, connection)| connection.resume_reads(now).then_some(*id))
+            .collect();
+        for id in resumed {
+            self.handle_ready(id, true);
+        }
+    }
+
+    /// Opens the connections that the handler asks for, closes the ones that timed out, and sends
+    /// keep-alives and the messages of the handler.
+    fn tick(&mut self) {
+        self.handler.on_tick_start();
+        for (address, handshake) in self.handler.peers_to_connect() {
+            if let Err(e) = self.connect(address, handshake) {
+                self.handler.on_connect_error(address, &e);
+            }
+        }
+        let now = Instant::now();
+        let ids: Vec<ConnectionId> = self.connections.keys().copied().collect();
+
+        for id in ids {
+            let Some(connection) = self.connections.get_mut(&id) else {
+                continue;
+            };
+            let result = connection
+                .check_timers(&self.timers, now)
+                .and_then(|()| self.handler.on_tick(connection))
+                .and_then(|()| connection.keep_alive(self.timers.keep_alive))
+                .and_then(|()| connection.write_pending());
+            if result.is_err() || connection.is_closed() {
+                self.close(id);
+            }
+        }
+    }
+
+    fn close(&mut self, id: ConnectionId) {
+        if let Some(mut connection) = self.connections.remove(&id) {
+            let _ = connection.write_pending();
+            let _ = self.poll.registry().deregister(connection.stream());
+            self.handler.on_close(&connection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messages::message_types::{handshake::HANDSHAKE_PSTR, have::Have},
+        network::connection::MAX_PENDING_BYTES,
+    };
+    use std::io::Write;
+
+    /// Answers handshakes and echoes every `Have` back, counting the ones it receives. It connects to the
+    /// peers of `to_connect` on its first tick.
+    #[derive(Default)]
+    struct Echo {
+        handshakes: usize,
+        haves: usize,
+        closed: usize,
+        to_connect: Vec<(SocketAddr, Handshake)>,
+    }
+
+    impl PeerHandler for Echo {
+        fn on_handshake(
+            &mut self,
+            connection: &mut Connection,
+            handshake: Handshake,
+        ) -> Result<(), String> {
+            self.handshakes += 1;
+            if !connection.is_outbound() {
+                handshake.send(connection).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        }
+
+        fn on_message(
+            &mut self,
+            connection: &mut Connection,
+            message: PeerMessage,
+        ) -> Result<(), String> {
+            if let PeerMessage::Have(have) = message {
+                self.haves += 1;
+                if !connection.is_outbound() {
+                    PeerMessage::Have(have)
+                        .send(connection)
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(())
+        }
+
+        fn peers_to_connect(&mut self) -> Vec<(SocketAddr, Handshake)> {
+            std::mem::take(&mut self.to_connect)
+        }
+
+        fn on_close(&mut self, _connection: &Connection) {
+            self.closed += 1;
+        }
+    }
+
+    fn turn_until<H: PeerHandler>(
+        loops: (&mut EventLoop<H>, &mut EventLoop<Echo>),
+        done: impl Fn(&EventLoop<H>) -> bool,
+    ) -> Result<(), String> {
+        let started = Instant::now();
+        while !done(loops.0) {
+            if started.elapsed() > Duration::from_secs(5) {
+                return Err("Event loops did not finish in time".to_string());
+            }
+            loops.0.turn(Duration::from_millis(5))?;
+            loops.1.turn(Duration::from_millis(5))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn messages_travel_both_ways_between_loops() -> Result<(), String> {
+        let mut server = EventLoop::new(Echo::default())?;
+        let address = server.listen("127.0.0.1:0")?;
+        let mut client = EventLoop::new(Echo::default())?;
+
+        let handshake = Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], [2; 20]);
+        for _ in 0..10 {
+            let id = client.connect(address, handshake.clone())?;
+            if let Some(connection) = client.connections.get_mut(&id) {
+                PeerMessage::Have(Have::new(id as u32))
+                    .send(connection)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        turn_until((&mut client, &mut server), |client| {
+            client.handler().handshakes == 10 && client.handler().haves == 10
+        })?;
+        assert_eq!(10, server.handler().haves);
+        Ok(())
+    }
+
+    #[test]
+    fn handlers_connect_to_peers_on_ticks() -> Result<(), String> {
+        let mut server = EventLoop::new(Echo::default())?;
+        let address = server.listen("127.0.0.1:0")?;
+        let mut client = EventLoop::new(Echo {
+            to_connect: vec![(
+                address,
+                Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], [2; 20]),
+            )],
+            ..Echo::default()
+        })?;
+
+        turn_until((&mut client, &mut server), |client| {
+            client.handler().handshakes == 1
+        })?;
+        assert_eq!(1, client.connections());
+        assert_eq!(1, server.handler().handshakes);
+        Ok(())
+    }
+
+    #[test]
+    fn idle_connections_time_out() -> Result<(), String> {
+        let mut server = EventLoop::new(Echo::default())?;
+        let address = server.listen("127.0.0.1:0")?;
+        server.set_timers(Timers {
+            idle_timeout: Duration::ZERO,
+            ..Timers::default()
+        });
+        let mut client = EventLoop::new(Echo::default())?;
+        client.connect(
+            address,
+            Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], [2; 20]),
+        )?;
+
+        turn_until((&mut server, &mut client), |server| {
+            server.handler().closed == 1
+        })?;
+        assert_eq!(0, server.connections());
+        Ok(())
+    }
+
+    #[test]
+    fn connections_without_a_handshake_time_out() -> Result<(), String> {
+        let mut server = EventLoop::new(Echo::default())?;
+        let address = server.listen("127.0.0.1:0")?;
+        server.set_timers(Timers {
+            handshake_timeout: Duration::ZERO,
+            ..Timers::default()
+        });
+        let _silent_peer = std::net::TcpStream::connect(address).map_err(|e| e.to_string())?;
+
+        turn_until(
+            (&mut server, &mut EventLoop::new(Echo::default())?),
+            |server| server.handler().closed == 1,
+        )?;
+        assert_eq!(0, server.connections());
+        Ok(())
+    }
+
+    /// Answers every message with `ANSWER_LEN` bytes, as a seed that serves the requests of a peer.
+    #[derive(Default)]
+    struct Flood {
+        max_pending: usize,
+    }
+
+    const ANSWER_LEN: usize = 64 * 1024;
+
+    impl PeerHandler for Flood {
+        fn on_handshake(&mut self, _: &mut Connection, _: Handshake) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn on_message(
+            &mut self,
+            connection: &mut Connection,
+            _: PeerMessage,
+        ) -> Result<(), String> {
+            connection
+                .write_all(&[0; ANSWER_LEN])
+                .map_err(|e| e.to_string())?;
+            self.max_pending = self.max_pending.max(connection.pending_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn peers_that_do_not_read_stop_being_read() -> Result<(), String> {
+        let mut server = EventLoop::new(Flood::default())?;
+        let address = server.listen("127.0.0.1:0")?;
+        let mut peer = std::net::TcpStream::connect(address).map_err(|e| e.to_string())?;
+        Handshake::new(HANDSHAKE_PSTR, [0; 8], [1; 20], [2; 20])
+            .send(&mut peer)
+            .map_err(|e| e.to_string())?;
+        for index in 0..2000 {
+            PeerMessage::Have(Have::new(index))
+                .send(&mut peer)
+                .map_err(|e| e.to_string())?;
+        }
+
+        for _ in 0..100 {
+            server.turn(Duration::from_millis(5))?;
+        }
+        let connection = server.connections.values().next().ok_or("Missing peer")?;
+        assert!(connection.is_backlogged());
+        assert!(server.handler().max_pending <= MAX_PENDING_BYTES + ANSWER_LEN);
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use crate::logging::log_handle::LogHandle;
+use std::{
+    fs,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+/// How often the filter checks whether its blocklist file changed.
+const FILTER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Ranges of eMule blocklists with this access level or a higher one are allowed, as in eMule itself.
+const EMULE_ALLOWED_LEVEL: u32 = 127;
+
+/// Sorted ranges of IPv4 addresses that do not overlap, so an address is looked up with a binary search.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpRanges {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl IpRanges {
+    /// Parses a blocklist in either the eMule `.dat` format, with lines like
+    /// `001.002.003.000 - 001.002.003.255 , 000 , Description`, or the PeerGuardian `.p2p` format, with
+    /// lines like `Description:1.2.3.0-1.2.3.255`. Empty lines and lines starting with `#` or `//` are
+    /// skipped.
+    pub fn from_blocklist(blocklist: &str) -> Result<Self, String> {
+        let mut ranges = Vec::new();
+        for (number, line) in blocklist.lines().enumerate() {
+            if let Some(range) = Self::parse_line(line)
+                .ok_or_else(|| format!("Invalid line {} of IP filter: {line}", number + 1))?
+            {
+                ranges.push(range);
+            }
+        }
+        Ok(Self::merged(ranges))
+    }
+
+    /// Returns `Some(None)` for lines that do not block anything, and `None` for lines that can't be parsed.
+    fn parse_line(line: &str) -> Option<Option<(u32, u32)>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            return Some(None);
+        }
+        // Descriptions of the P2P format may have colons of their own, but addresses do not.
+        if let Some(range) = line
+            .rsplit_once(':')
+            .and_then(|(_, range)| Self::parse_range(range))
+        {
+            return Some(Some(range));
+        }
+
+        let mut fields = line.split(',');
+        let range = fields.next().and_then(Self::parse_range)?;
+        match fields.next().map(|level| u32::from_str(level.trim())) {
+            Some(Ok(level)) if level >= EMULE_ALLOWED_LEVEL => Some(None),
+            Some(Err(_)) => None,
+            _ => Some(Some(range)),
+        }
+    }
+
+    fn parse_range(range: &str) -> Option<(u32, u32)> {
+        let (first, last) = range.split_once('-')?;
+        let (first, last) = (Self::parse_ipv4(first)?, Self::parse_ipv4(last)?);
+        (first <= last).then_some((first, last))
+    }
+
+    /// Parses an IPv4 address, allowing the leading zeros that eMule blocklists pad octets with.
+    fn parse_ipv4(address: &str) -> Option<u32> {
+        let octets: Vec<&str> = address.trim().split('.').collect();
+        let [a, b, c, d] = octets[..] else {
+            return None;
+        };
+        let mut ip = 0;
+        for octet in [a, b, c, d] {
+            ip = (ip << 8) | u32::from(u8::from_str(octet).ok()?);
+        }
+        Some(ip)
+    }
+
+    /// Sorts the ranges and joins the ones that overlap or are next to each other.
+    fn merged(mut ranges: Vec<(u32, u32)>) -> Self {
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some((_, end)) if first <= end.saturating_add(1) => *end = (*end).max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    /// Returns true if some range has the address. Blocklists only have IPv4 ranges, so IPv6 addresses
+    /// are only looked up when they map an IPv4 one.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V4(ip) => u32::from(ip),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => u32::from(ip),
+                None => return false,
+            },
+        };
+        let next = self.ranges.partition_point(|(first, _)| *first <= ip);
+        next > 0 && self.ranges[next - 1].1 >= ip
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// Address ranges that the client neither connects to nor accepts, loaded from the blocklist file of
+/// the config. Clones share the ranges, so a reload applies to every peer source at once.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    path: Option<String>,
+    ranges: Arc<Mutex<IpRanges>>,
+}
+
+impl IpFilter {
+    /// Creates a filter for the blocklist at `path`, which blocks nothing until it is loaded.
+    pub fn new(path: Option<String>) -> Self {
+        Self {
+            path,
+            ranges: Arc::default(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Reads the blocklist file again. The previous ranges are kept if it can't be read. Returns the amount
+    /// of ranges loaded.
+    pub fn load(&self) -> Result<usize, String> {
+        let Some(path) = &self.path else {
+            return Ok(0);
+        };
+        let blocklist = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let ranges = IpRanges::from_blocklist(&blocklist)?;
+        let len = ranges.len();
+        *self.ranges.lock().map_err(|e| e.to_string())? = ranges;
+        Ok(len)
+    }
+
+    /// Returns true if the peer at `ip` is blocked. Addresses that are not IPs, such as the hosts of web
+    /// seeds, are never blocked.
+    pub fn is_blocked(&self, ip: &str) -> Result<bool, String> {
+        let Ok(ip) = IpAddr::from_str(ip) else {
+            return Ok(false);
+        };
+        let ranges = self.ranges.lock().map_err(|e| e.to_string())?;
+        Ok(ranges.contains(ip))
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let path = self.path.as_ref()?;
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    /// Starts a thread that loads the blocklist again whenever its file changes.
+    pub fn spawn(self, log_handle: LogHandle) -> JoinHandle<Result<(), String>> {
+        thread::spawn(move || {
+            let mut loaded = self.modified();
+            loop {
+                thread::sleep(FILTER_CHECK_INTERVAL);
+                let modified = self.modified();
+                if modified == loaded {
+                    continue;
+                }
+                loaded = modified;
+                match self.load() {
+                    Ok(len) => log_handle.log(&format!("Reloaded IP filter: {len} ranges"))?,
+                    Err(e) => log_handle.log(&format!("Could not reload IP filter: {e}"))?,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn blocklists_in_both_formats_are_merged_into_ranges() -> Result<(), String> {
+        let blocklist = "# Comment\n\
+            001.002.003.000 - 001.002.003.255 , 000 , Some company\n\
+            001.002.004.000 - 001.002.004.010 , 100 , Next to the previous range\n\
+            005.000.000.000 - 005.255.255.255 , 200 , Allowed by its access level\n\
+            \n\
+            Some: network:10.0.0.0-10.0.0.255\n\
+            Inside another one:10.0.0.5-10.0.0.6\n";
+        let ranges = IpRanges::from_blocklist(blocklist)?;
+        assert_eq!(2, ranges.len());
+
+        let ip = |ip: &str| IpAddr::from_str(ip).map_err(|e| e.to_string());
+        assert!(ranges.contains(ip("1.2.3.0")?));
+        assert!(ranges.contains(ip("1.2.4.10")?));
+        assert!(!ranges.contains(ip("1.2.4.11")?));
+        assert!(!ranges.contains(ip("5.1.1.1")?));
+        assert!(ranges.contains(ip("10.0.0.255")?));
+        assert!(ranges.contains(ip("::ffff:10.0.0.1")?));
+        assert!(!ranges.contains(ip("0.0.0.0")?));
+
+        assert!(IpRanges::from_blocklist("1.2.3.4 - 1.2.3").is_err());
+        assert!(IpRanges::from_blocklist("Backwards:1.2.3.4-1.2.3.3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn filter_keeps_its_ranges_when_the_file_can_not_be_loaded() -> Result<(), String> {
+        let dir = test_dir("ip_filter");
+        let path = dir.join("blocklist.p2p");
+        fs::write(&path, "Blocked:192.168.1.0-192.168.1.255\n").map_err(|e| e.to_string())?;
+        let filter = IpFilter::new(Some(path.to_string_lossy().to_string()));
+        assert!(!filter.is_blocked("192.168.1.7")?);
+
+        assert_eq!(1, filter.load()?);
+        assert!(filter.clone().is_blocked("192.168.1.7")?);
+        assert!(!filter.is_blocked("192.168.2.7")?);
+        assert!(!filter.is_blocked("example.com")?);
+
+        fs::write(&path, "Not a range\n").map_err(|e| e.to_string())?;
+        assert!(filter.load().is_err());
+        assert!(filter.is_blocked("192.168.1.7")?);
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+}
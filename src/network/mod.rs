@@ -0,0 +1,6 @@
+pub mod ban_list;
+pub mod codec;
+pub mod connection;
+pub mod event_loop;
+pub mod ip_filter;
+pub mod rate_limit;
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Transfer rates are measured over windows of this length.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    window_bytes: usize,
+    measured_rate: usize,
+    total_bytes: usize,
+    last_transfer: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(now: Instant) -> Self {
+        Self {
+            tokens: 0.0,
+            last_refill: now,
+            window_start: now,
+            window_bytes: 0,
+            measured_rate: 0,
+            total_bytes: 0,
+            last_transfer: None,
+        }
+    }
+
+    fn measure(&mut self, bytes: usize, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed >= RATE_WINDOW {
+            self.measured_rate = (self.window_bytes as f64 / elapsed.as_secs_f64()) as usize;
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes;
+        if bytes > 0 {
+            self.total_bytes += bytes;
+            self.last_transfer = Some(now);
+        }
+    }
+}
+
+/// A token bucket that caps a transfer rate. The bucket holds up to a second worth of its limit, and a
+/// transfer that takes more tokens than there are leaves it in debt, which the caller waits out before
+/// transferring again. Clones share the bucket.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// Bytes per second, where zero means unlimited.
+    limit: Arc<AtomicUsize>,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: Arc::new(AtomicUsize::new(limit)),
+            bucket: Arc::new(Mutex::new(Bucket::new(Instant::now()))),
+        }
+    }
+
+    /// Returns a limiter with a bucket of its own that follows the limit of this one, even when it changes.
+    pub fn with_same_limit(&self) -> Self {
+        Self {
+            limit: self.limit.clone(),
+            bucket: Arc::new(Mutex::new(Bucket::new(Instant::now()))),
+        }
+    }
+
+    /// Sets the limit in bytes per second, where zero means unlimited.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Takes the tokens of `bytes` that were transferred, and returns how long to wait before the next transfer.
+    pub fn consume(&self, bytes: usize, now: Instant) -> Result<Duration, String> {
+        let limit = self.limit();
+        let mut bucket = self.bucket.lock().map_err(|e| e.to_string())?;
+        bucket.measure(bytes, now);
+
+        if limit == 0 {
+            bucket.tokens = 0.0;
+            bucket.last_refill = now;
+            return Ok(Duration::ZERO);
+        }
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * limit as f64).min(limit as f64);
+        bucket.last_refill = now;
+        bucket.tokens -= bytes as f64;
+
+        match bucket.tokens < 0.0 {
+            true => Ok(Duration::from_secs_f64(-bucket.tokens / limit as f64)),
+            false => Ok(Duration::ZERO),
+        }
+    }
+
+    /// Returns the bytes per second that were transferred during the last complete window.
+    pub fn current_rate(&self, now: Instant) -> Result<usize, String> {
+        let mut bucket = self.bucket.lock().map_err(|e| e.to_string())?;
+        bucket.measure(0, now);
+        Ok(bucket.measured_rate)
+    }
+
+    /// Returns the bytes that went through the limiter since it was created.
+    pub fn total_bytes(&self) -> Result<usize, String> {
+        Ok(self.bucket.lock().map_err(|e| e.to_string())?.total_bytes)
+    }
+
+    /// Returns when bytes last went through the limiter, if they ever did.
+    pub fn last_transfer(&self) -> Result<Option<Instant>, String> {
+        Ok(self.bucket.lock().map_err(|e| e.to_string())?.last_transfer)
+    }
+}
+
+/// The upload and download limiters of one level of the client: the whole client, a torrent or a peer.
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    upload: RateLimiter,
+    download: RateLimiter,
+}
+
+impl Bandwidth {
+    pub fn new(upload_limit: usize, download_limit: usize) -> Self {
+        Self {
+            upload: RateLimiter::new(upload_limit),
+            download: RateLimiter::new(download_limit),
+        }
+    }
+
+    /// Returns limiters with buckets of their own that follow the limits of these ones.
+    pub fn with_same_limits(&self) -> Self {
+        Self {
+            upload: self.upload.with_same_limit(),
+            download: self.download.with_same_limit(),
+        }
+    }
+
+    pub fn set_limits(&self, upload_limit: usize, download_limit: usize) {
+        self.upload.set_limit(upload_limit);
+        self.download.set_limit(download_limit);
+    }
+
+    pub fn upload(&self) -> &RateLimiter {
+        &self.upload
+    }
+
+    pub fn download(&self) -> &RateLimiter {
+        &self.download
+    }
+}
+
+/// Limits the transfers of a peer session by every level it belongs to at once.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    levels: Vec<Bandwidth>,
+}
+
+impl Throttle {
+    pub fn new(levels: Vec<Bandwidth>) -> Self {
+        Self { levels }
+    }
+
+    /// Takes the tokens of the bytes that were uploaded and downloaded from every level, and returns how long
+    /// the session waits before transferring more, which is the longest wait of any level.
+    pub fn transfer(
+        &self,
+        uploaded: usize,
+        downloaded: usize,
+        now: Instant,
+    ) -> Result<Duration, String> {
+        let mut wait = Duration::ZERO;
+        for level in &self.levels {
+            wait = wait
+                .max(level.upload.consume(uploaded, now)?)
+                .max(level.download.consume(downloaded, now)?);
+        }
+        Ok(wait)
+    }
+}
+
+/// Every rate limiter of the client: a global one, one for each torrent and one for each peer. Limits
+/// can change while the client runs, and the change applies right away to every running transfer.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    global: Bandwidth,
+    /// Limits that every torrent starts with.
+    torrent_limits: (usize, usize),
+    torrents: Arc<Mutex<HashMap<[u8; 20], Bandwidth>>>,
+    /// Each peer has buckets of its own, all of which follow the limits of this one.
+    peer: Bandwidth,
+}
+
+impl RateLimits {
+    /// Creates the limiters from the `(upload, download)` limits of each level, in bytes per second.
+    pub fn new(
+        global_limits: (usize, usize),
+        torrent_limits: (usize, usize),
+        peer_limits: (usize, usize),
+    ) -> Self {
+        Self {
+            global: Bandwidth::new(global_limits.0, global_limits.1),
+            torrent_limits,
+            torrents: Arc::new(Mutex::new(HashMap::new())),
+            peer: Bandwidth::new(peer_limits.0, peer_limits.1),
+        }
+    }
+
+    pub fn global(&self) -> &Bandwidth {
+        &self.global
+    }
+
+    /// Returns the limiters of a torrent, which are created the first time they are needed.
+    pub fn torrent(&self, info_hash: [u8; 20]) -> Result<Bandwidth, String> {
+        let mut torrents = self.torrents.lock().map_err(|e| e.to_string())?;
+        let (upload_limit, download_limit) = self.torrent_limits;
+        Ok(torrents
+            .entry(info_hash)
+            .or_insert_with(|| Bandwidth::new(upload_limit, download_limit))
+            .clone())
+    }
+
+    /// Returns new limiters for a peer connection.
+    pub fn peer(&self) -> Bandwidth {
+        self.peer.with_same_limits()
+    }
+
+    /// Returns the throttle of a session of a torrent, with `peer` being the limiters of the peer.
+    pub fn throttle(&self, info_hash: [u8; 20], peer: &Bandwidth) -> Result<Throttle, String> {
+        Ok(Throttle::new(vec![
+            self.global.clone(),
+            self.torrent(info_hash)?,
+            peer.clone(),
+        ]))
+    }
+
+    pub fn set_global_limits(&self, upload_limit: usize, download_limit: usize) {
+        self.global.set_limits(upload_limit, download_limit);
+    }
+
+    pub fn set_torrent_limits(
+        &self,
+        info_hash: [u8; 20],
+        upload_limit: usize,
+        download_limit: usize,
+    ) -> Result<(), String> {
+        self.torrent(info_hash)?
+            .set_limits(upload_limit, download_limit);
+        Ok(())
+    }
+
+    /// Sets the limits of every peer, including the ones that are already connected.
+    pub fn set_peer_limits(&self, upload_limit: usize, download_limit: usize) {
+        self.peer.set_limits(upload_limit, download_limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_hold_a_second_of_their_limit_and_make_callers_wait_out_their_debt(
+    ) -> Result<(), String> {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+
+        // The bucket starts empty, and fills up to its limit at most.
+        assert_eq!(Duration::ZERO, limiter.consume(0, start)?);
+        let later = start + Duration::from_secs(5);
+        assert_eq!(Duration::ZERO, limiter.consume(1000, later)?);
+        assert_eq!(Duration::from_millis(500), limiter.consume(500, later)?);
+        assert_eq!(1500, limiter.total_bytes()?);
+        assert_eq!(Some(later), limiter.last_transfer()?);
+
+        limiter.set_limit(0);
+        assert_eq!(Duration::ZERO, limiter.consume(1_000_000, later)?);
+        Ok(())
+    }
+
+    #[test]
+    fn sessions_wait_for_the_slowest_level() -> Result<(), String> {
+        let limits = RateLimits::new((0, 0), (0, 2000), (0, 0));
+        let peer = limits.peer();
+        let throttle = limits.throttle([1; 20], &peer)?;
+        let start = Instant::now();
+        throttle.transfer(0, 0, start)?;
+
+        let wait = throttle.transfer(0, 4000, start + Duration::from_secs(1))?;
+        assert_eq!(Duration::from_secs(1), wait);
+
+        // Peers follow limits that change after they connected.
+        limits.set_peer_limits(0, 1000);
+        let wait = throttle.transfer(0, 0, start + Duration::from_secs(2))?;
+        assert_eq!(Duration::ZERO, wait);
+        assert_eq!(1000, peer.download().limit());
+        Ok(())
+    }
+}
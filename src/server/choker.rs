@@ -0,0 +1,228 @@
+use crate::network::connection::ConnectionId;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// How often the peers that get the upload slots are chosen again.
+pub const RECHOKE_INTERVAL: Duration = Duration::from_secs(10);
+/// How often the optimistic unchoke moves to another peer.
+pub const OPTIMISTIC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What the choker knows about a peer when it chooses the peers to upload to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChokeCandidate {
+    pub id: ConnectionId,
+    pub interested: bool,
+    /// Whether the peer stopped sending the blocks that the client requested.
+    pub snubbed: bool,
+    /// Whether the client has the whole torrent of the peer, in which case peers are ranked by how fast
+    /// the client uploads to them instead of how fast they upload to the client.
+    pub seeding: bool,
+    /// Bytes that were downloaded from the peer since it connected.
+    pub downloaded: usize,
+    /// Bytes that were uploaded to the peer since it connected.
+    pub uploaded: usize,
+}
+
+/// Chooses which peers the client uploads to with the tit-for-tat algorithm: every `RECHOKE_INTERVAL`, the
+/// interested peers that uploaded the most to the client since the last time get the upload slots, except
+/// for one that is given to a choked peer for `OPTIMISTIC_INTERVAL`, so new peers get a chance to show
+/// their rate. Peers that snub the client only get that optimistic slot.
+#[derive(Debug)]
+pub struct Choker {
+    slots: usize,
+    unchoked: HashSet<ConnectionId>,
+    optimistic: Option<ConnectionId>,
+    last_rechoke: Option<Instant>,
+    last_optimistic: Option<Instant>,
+    /// Bytes that were downloaded from and uploaded to every peer at the last re-evaluation.
+    totals: HashMap<ConnectionId, (usize, usize)>,
+    /// Bytes per second that each peer was ranked by at the last re-evaluation.
+    rates: HashMap<ConnectionId, f64>,
+}
+
+impl Default for Choker {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl Choker {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots,
+            unchoked: HashSet::new(),
+            optimistic: None,
+            last_rechoke: None,
+            last_optimistic: None,
+            totals: HashMap::new(),
+            rates: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the upload slots should be chosen again.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.last_rechoke
+            .map_or(true, |last| now.duration_since(last) >= RECHOKE_INTERVAL)
+    }
+
+    /// Chooses the peers that get the upload slots among `candidates`, which are every peer with a session.
+    pub fn rechoke(&mut self, candidates: &[ChokeCandidate], now: Instant) {
+        let elapsed = self
+            .last_rechoke
+            .map_or(RECHOKE_INTERVAL, |last| now.duration_since(last))
+            .as_secs_f64()
+            .max(1.0);
+        self.last_rechoke = Some(now);
+
+        let mut totals = HashMap::new();
+        let mut rates = HashMap::new();
+        for candidate in candidates {
+            let (downloaded, uploaded) = self.totals.get(&candidate.id).copied().unwrap_or((0, 0));
+            let bytes = match candidate.seeding {
+                true => candidate.uploaded.saturating_sub(uploaded),
+                false => candidate.downloaded.saturating_sub(downloaded),
+            };
+            rates.insert(candidate.id, bytes as f64 / elapsed);
+            totals.insert(candidate.id, (candidate.downloaded, candidate.uploaded));
+        }
+        self.totals = totals;
+        self.rates = rates;
+
+        let mut ranked: Vec<&ChokeCandidate> = candidates
+            .iter()
+            .filter(|candidate| candidate.interested && !candidate.snubbed)
+            .collect();
+        ranked.sort_by_key(|candidate| candidate.id);
+        ranked.sort_by(|a, b| self.rate(b.id).total_cmp(&self.rate(a.id)));
+        self.unchoked = ranked
+            .iter()
+            .take(self.slots.saturating_sub(1))
+            .map(|candidate| candidate.id)
+            .collect();
+
+        self.choose_optimistic(candidates, now);
+        if let Some(id) = self.optimistic {
+            self.unchoked.insert(id);
+        }
+    }
+
+    /// Keeps the optimistic unchoke until `OPTIMISTIC_INTERVAL` passes, unless its peer stops being a
+    /// choked and interested one. The next one is the first choked and interested peer that connected
+    /// after the previous one, so every peer gets its turn.
+    fn choose_optimistic(&mut self, candidates: &[ChokeCandidate], now: Instant) {
+        if self.slots == 0 {
+            self.optimistic = None;
+            return;
+        }
+        let choked: Vec<ConnectionId> = {
+            let mut choked: Vec<ConnectionId> = candidates
+                .iter()
+                .filter(|candidate| candidate.interested && !self.unchoked.contains(&candidate.id))
+                .map(|candidate| candidate.id)
+                .collect();
+            choked.sort_unstable();
+            choked
+        };
+
+        let expired = self
+            .last_optimistic
+            .map_or(true, |last| now.duration_since(last) >= OPTIMISTIC_INTERVAL);
+        let valid = self.optimistic.is_some_and(|id| choked.contains(&id));
+        if valid && !expired {
+            return;
+        }
+
+        let next = match self.optimistic {
+            Some(previous) => choked.iter().find(|id| **id > previous).or(choked.first()),
+            None => choked.first(),
+        };
+        self.optimistic = next.copied();
+        self.last_optimistic = Some(now);
+    }
+
+    /// Forgets a peer whose connection was closed.
+    pub fn remove(&mut self, id: ConnectionId) {
+        self.unchoked.remove(&id);
+        self.totals.remove(&id);
+        self.rates.remove(&id);
+        if self.optimistic == Some(id) {
+            self.optimistic = None;
+        }
+    }
+
+    pub fn is_unchoked(&self, id: ConnectionId) -> bool {
+        self.unchoked.contains(&id)
+    }
+
+    pub fn is_optimistic(&self, id: ConnectionId) -> bool {
+        self.optimistic == Some(id)
+    }
+
+    /// Returns the bytes per second that the peer was ranked by at the last re-evaluation.
+    pub fn rate(&self, id: ConnectionId) -> f64 {
+        self.rates.get(&id).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: ConnectionId, downloaded: usize) -> ChokeCandidate {
+        ChokeCandidate {
+            id,
+            interested: true,
+            snubbed: false,
+            seeding: false,
+            downloaded,
+            uploaded: 0,
+        }
+    }
+
+    #[test]
+    fn fastest_peers_get_the_slots_and_one_is_left_for_an_optimistic_unchoke() {
+        let mut choker = Choker::new(3);
+        let mut candidates: Vec<ChokeCandidate> =
+            (0..6).map(|id| candidate(id, id * 1000)).collect();
+        candidates[5].snubbed = true;
+        candidates[4].interested = false;
+        let now = Instant::now();
+        choker.rechoke(&candidates, now);
+
+        assert!(choker.is_unchoked(3) && choker.is_unchoked(2));
+        assert!(choker.is_optimistic(0) && choker.is_unchoked(0));
+        assert!(!choker.is_unchoked(1) && !choker.is_unchoked(4) && !choker.is_unchoked(5));
+
+        // The optimistic unchoke stays until its interval passes, and then moves to the next choked peer.
+        let keep_rates = |candidates: &mut Vec<ChokeCandidate>| {
+            for candidate in candidates.iter_mut() {
+                candidate.downloaded += candidate.id * 1000;
+            }
+        };
+        keep_rates(&mut candidates);
+        choker.rechoke(&candidates, now + RECHOKE_INTERVAL);
+        assert!(choker.is_optimistic(0) && choker.is_unchoked(3));
+        keep_rates(&mut candidates);
+        choker.rechoke(&candidates, now + OPTIMISTIC_INTERVAL);
+        assert!(choker.is_optimistic(1) && choker.is_unchoked(3));
+    }
+
+    #[test]
+    fn seeding_ranks_peers_by_upload_rate() {
+        let mut choker = Choker::new(2);
+        let candidates: Vec<ChokeCandidate> = (0..3)
+            .map(|id| ChokeCandidate {
+                seeding: true,
+                downloaded: 0,
+                uploaded: [500, 9000, 100][id],
+                ..candidate(id, 0)
+            })
+            .collect();
+        choker.rechoke(&candidates, Instant::now());
+
+        assert!(choker.is_unchoked(1));
+        assert_eq!(900.0, choker.rate(1));
+    }
+}
@@ -1,2 +1,4 @@
+pub mod choker;
 pub mod server_side;
+pub mod session_handler;
 pub mod upload;
@@ -1,28 +1,55 @@
 use crate::{
-    client::{download::download_worker_state::DownloadWorkerState, piece::Piece},
+    client::{
+        client_side::{DownloadedTorrents, LoadedTorrents},
+        download::{
+            download_worker_state::DownloadWorkerState,
+            peer_manager::{ConnectedPeers, ConnectionLimit},
+        },
+        piece::Piece,
+        recheck_state::RecheckState,
+        torrent::Torrent,
+    },
     config::Config,
     logging::log_handle::LogHandle,
-    ui_notification_structs::{peer_state::PeerState, ui_notification::UiNotification},
+    network::{
+        ban_list::BanList,
+        event_loop::EventLoop,
+        ip_filter::IpFilter,
+        rate_limit::{Bandwidth, RateLimits},
+    },
+    storage::disk_cache::DiskCache,
+    ui_notification_structs::{
+        peer_state::PeerState, transfer_rates::TransferRates, ui_notification::UiNotification,
+    },
 };
 use gtk::glib::Sender as UiSender;
 use std::{
     collections::HashMap,
-    net::{TcpListener, TcpStream},
-    sync::{
-        mpsc::Receiver,
-        mpsc::{SendError, Sender},
-    },
+    sync::{mpsc::Receiver, mpsc::Sender},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use super::upload::{upload_info::UploadInfo, upload_pool::UploadPool};
+use super::{
+    session_handler::{SessionHandler, SESSION_STATE_IDS},
+    upload::{upload_info::UploadInfo, upload_pool::UploadPool},
+};
+
+/// How often the stats of the disk cache are written to the log.
+const CACHE_STATS_LOG_INTERVAL: Duration = Duration::from_secs(300);
 
 #[derive(Debug)]
 pub enum Notification {
     NewPiece(Piece, UploadInfo),
-    NewPeer(TcpStream),
+    /// A piece of the torrent that a session of the event loop finished downloading. It is stored, and the
+    /// files are built with the last one, here rather than on the event loop.
+    CompletePiece(Box<Torrent>, Piece),
+    /// A peer connection with the given id was closed.
     EndPeer(usize),
     UpdateUi(DownloadWorkerState),
+    Recheck(RecheckState),
+    /// Stops serving the torrent with the given info hash, because it was paused or removed.
+    RemoveTorrent([u8; 20]),
     EndServer,
 }
 
@@ -31,6 +58,14 @@ pub struct ServerSide {
     config: Config,
     log_handle: LogHandle,
     ui_sender: Option<UiSender<UiNotification>>,
+    disk_cache: DiskCache,
+    loaded_torrents: LoadedTorrents,
+    downloaded_torrents: DownloadedTorrents,
+    rate_limits: RateLimits,
+    ban_list: BanList,
+    ip_filter: IpFilter,
+    connected_peers: ConnectedPeers,
+    connection_limit: ConnectionLimit,
 }
 
 impl ServerSide {
@@ -40,6 +75,14 @@ impl ServerSide {
             config: config.clone(),
             log_handle,
             ui_sender: None,
+            disk_cache: DiskCache::new(config.disk_cache_size()),
+            loaded_torrents: LoadedTorrents::default(),
+            downloaded_torrents: DownloadedTorrents::default(),
+            rate_limits: RateLimits::default(),
+            ban_list: BanList::default(),
+            ip_filter: IpFilter::default(),
+            connected_peers: ConnectedPeers::default(),
+            connection_limit: ConnectionLimit::new(usize::MAX),
         }
     }
 
@@ -47,49 +90,131 @@ impl ServerSide {
         self.ui_sender = sender
     }
 
+    /// Shares the cache that the client side writes downloaded pieces to, so they can be served from it.
+    pub fn set_disk_cache(&mut self, disk_cache: DiskCache) {
+        self.disk_cache = disk_cache
+    }
+
+    /// Shares the torrents of the client side, so peers that connect to the client are downloaded from too.
+    pub fn set_client_torrents(
+        &mut self,
+        loaded_torrents: LoadedTorrents,
+        downloaded_torrents: DownloadedTorrents,
+    ) {
+        self.loaded_torrents = loaded_torrents;
+        self.downloaded_torrents = downloaded_torrents;
+    }
+
+    /// Shares the rate limits of the client side, so uploads and downloads are limited together.
+    pub fn set_rate_limits(&mut self, rate_limits: RateLimits) {
+        self.rate_limits = rate_limits
+    }
+
+    /// Shares the peers that the client side banned, so they are not accepted either.
+    pub fn set_ban_list(&mut self, ban_list: BanList) {
+        self.ban_list = ban_list
+    }
+
+    /// Shares the blocklist of the client side, so incoming connections from blocked addresses are dropped.
+    pub fn set_ip_filter(&mut self, ip_filter: IpFilter) {
+        self.ip_filter = ip_filter
+    }
+
+    /// Shares the peers that the client side is connected to, so peers that are already downloaded from are
+    /// not accepted again.
+    pub fn set_connected_peers(&mut self, connected_peers: ConnectedPeers) {
+        self.connected_peers = connected_peers
+    }
+
+    /// Shares the limit of open peer connections of the client side, so accepted connections count against
+    /// it too.
+    pub fn set_connection_limit(&mut self, connection_limit: ConnectionLimit) {
+        self.connection_limit = connection_limit
+    }
+
     pub fn init(
         &mut self,
         notif_tx: Sender<Notification>,
         notif_rx: Receiver<Notification>,
     ) -> Result<(), String> {
-        let _notification_thread = self.init_notifications(notif_tx.clone(), notif_rx)?;
-        let _connection_thread = self.init_connections(notif_tx)?;
+        let pool = UploadPool::new(self.id, self.disk_cache.clone());
+        let mut handler = pool.handler(&notif_tx, self.log_handle.clone());
+        handler.set_loaded_torrents(self.loaded_torrents.clone());
+        handler.set_upload_slots(self.config.upload_slots());
+        handler.set_rate_limits(self.rate_limits.clone());
+        handler.set_ban_list(self.ban_list.clone());
+        handler.set_ip_filter(self.ip_filter.clone());
+        handler.set_timers(self.config.timers());
+        handler.set_connected_peers(self.connected_peers.clone());
+        handler.set_connection_limit(self.connection_limit.clone());
+        let _notification_thread = self.init_notifications(pool, notif_tx, notif_rx)?;
+        let _connection_thread = self.init_connections(handler)?;
         Ok(())
     }
 
     fn init_notifications(
         &self,
+        mut pool: UploadPool,
         notif_tx: Sender<Notification>,
         notif_rx: Receiver<Notification>,
     ) -> Result<JoinHandle<Result<(), String>>, String> {
         let log_handle = self.log_handle.clone();
-        let mut pool = UploadPool::new(self.id);
+        let downloaded_torrents = self.downloaded_torrents.clone();
+        let disk_cache = self.disk_cache.clone();
+        let rate_limits = self.rate_limits.clone();
         let mut hash_states: HashMap<usize, DownloadWorkerState> =
             HashMap::<usize, DownloadWorkerState>::new();
         let ui_option = self.ui_sender.clone();
 
         let thread: JoinHandle<Result<(), String>> = thread::spawn(move || {
+            let mut stats_logged_at = Instant::now();
             for notification in notif_rx {
+                if stats_logged_at.elapsed() >= CACHE_STATS_LOG_INTERVAL {
+                    stats_logged_at = Instant::now();
+                    let stats = disk_cache.stats()?;
+                    log_handle.log(&format!(
+                        "Disk cache: {} hits, {} misses, {} bytes cached",
+                        stats.get_hits(),
+                        stats.get_misses(),
+                        stats.get_cached_bytes()
+                    ))?;
+                }
                 match notification {
                     Notification::NewPiece(piece, upload_info) => {
                         log_handle.log(&format!("Started serving piece {}", piece.index()))?;
                         pool.add_piece(piece, upload_info)?;
                     }
-                    Notification::NewPeer(stream) => {
-                        if let Err(e) = pool.add_worker(stream, &notif_tx) {
-                            log_handle.log(&format!("Worker creation error: {e}"))?;
+                    Notification::CompletePiece(torrent, piece) => {
+                        let index = piece.index();
+                        if let Err(e) = torrent.complete_piece(
+                            piece,
+                            &notif_tx,
+                            &log_handle,
+                            downloaded_torrents.clone(),
+                        ) {
+                            log_handle.log(&format!(
+                                "Failed to store piece {index} of {}: {e}",
+                                torrent.name()
+                            ))?;
                         }
                     }
                     Notification::EndPeer(id) => {
-                        pool.remove_worker(id)?;
-                        log_handle.log(&format!("Removing worker {id}"))?;
+                        log_handle.log(&format!("Closed peer connection {id}"))?;
+                        hash_states.remove(&(SESSION_STATE_IDS + id));
+                    }
+                    Notification::RemoveTorrent(info_hash) => {
+                        pool.remove_torrent(info_hash)?;
+                        log_handle.log(&format!("Stopped serving torrent {info_hash:?}"))?;
                     }
                     Notification::EndServer => break,
                     Notification::UpdateUi(sendable) => {
                         if ui_option.is_some() {
                             hash_states.insert(sendable.id, sendable);
-                            let ui_notif =
-                                ServerSide::generate_ui_notification(hash_states.clone());
+                            let mut ui_notif = ServerSide::generate_ui_notification(
+                                hash_states.clone(),
+                                &rate_limits,
+                            )?;
+                            ui_notif.set_cache_stats(disk_cache.stats()?);
                             let clone_ui_option = ui_option.clone();
                             let _ = match clone_ui_option {
                                 Some(sender) => sender.send(ui_notif),
@@ -97,6 +222,23 @@ impl ServerSide {
                             };
                         }
                     }
+                    Notification::Recheck(recheck_state) => {
+                        if let Some(sender) = ui_option.clone() {
+                            let mut ui_notif = ServerSide::generate_ui_notification(
+                                hash_states.clone(),
+                                &rate_limits,
+                            )?;
+                            ui_notif.add_torrent_state(recheck_state.generate_torrent_state());
+                            ui_notif.set_cache_stats(disk_cache.stats()?);
+                            let _ = sender.send(ui_notif);
+                        }
+                        if recheck_state.is_finished() {
+                            log_handle.log(&format!(
+                                "Finished rechecking {}",
+                                recheck_state.torrent_name()
+                            ))?;
+                        }
+                    }
                 }
             }
             Ok(())
@@ -104,19 +246,16 @@ impl ServerSide {
         Ok(thread)
     }
 
+    /// Runs the session of every incoming peer connection from a single event loop thread.
     fn init_connections(
         &self,
-        notif_tx: Sender<Notification>,
-    ) -> Result<JoinHandle<Result<(), SendError<Notification>>>, String> {
-        let socket = TcpListener::bind(self.config.server_address()).map_err(|e| e.to_string())?;
+        handler: SessionHandler,
+    ) -> Result<JoinHandle<Result<(), String>>, String> {
+        let mut event_loop = EventLoop::new(handler)?;
+        event_loop.set_timers(self.config.timers());
+        event_loop.listen(self.config.server_address())?;
 
-        let thread: JoinHandle<Result<(), SendError<Notification>>> = thread::spawn(move || {
-            for stream in socket.incoming().flatten() {
-                notif_tx.send(Notification::NewPeer(stream))?
-            }
-            Ok(())
-        });
-        Ok(thread)
+        Ok(thread::spawn(move || event_loop.run()))
     }
 
     pub fn set_peer_id(&mut self, peer_id: [u8; 20]) {
@@ -134,17 +273,33 @@ impl ServerSide {
     }
     pub fn generate_ui_notification(
         hash_to_send: HashMap<usize, DownloadWorkerState>,
-    ) -> UiNotification {
+        rate_limits: &RateLimits,
+    ) -> Result<UiNotification, String> {
+        let now = Instant::now();
         let mut ui_notif = UiNotification::new();
+        ui_notif.set_transfer_rates(ServerSide::get_transfer_rates(rate_limits.global(), now)?);
         for download_worker_state in hash_to_send.iter() {
             let different_peers = ServerSide::get_different_peers(hash_to_send.clone());
             let peer_states = ServerSide::get_peer_states_vec(hash_to_send.clone());
-            let torrent_state = download_worker_state
+            let mut torrent_state = download_worker_state
                 .1
                 .generate_torrent_state(different_peers, peer_states);
+            let bandwidth = rate_limits.torrent(download_worker_state.1.info_hash)?;
+            torrent_state.set_transfer_rates(ServerSide::get_transfer_rates(&bandwidth, now)?);
             ui_notif.add_torrent_state(torrent_state);
         }
-        ui_notif
+        Ok(ui_notif)
+    }
+    pub fn get_transfer_rates(
+        bandwidth: &Bandwidth,
+        now: Instant,
+    ) -> Result<TransferRates, String> {
+        Ok(TransferRates::new(
+            bandwidth.upload().current_rate(now)?,
+            bandwidth.download().current_rate(now)?,
+            bandwidth.upload().limit(),
+            bandwidth.download().limit(),
+        ))
     }
     pub fn get_peer_states_vec(
         hash_to_send: HashMap<usize, DownloadWorkerState>,
@@ -0,0 +1,690 @@
+use super::{
+    choker::{ChokeCandidate, Choker},
+    server_side::Notification,
+    upload::upload_info::UploadInfo,
+};
+use crate::{
+    client::{
+        client_side::LoadedTorrents,
+        download::{
+            peer::Peer,
+            peer_manager::{ConnectedPeers, ConnectionLimit, ConnectionPermit},
+            peer_protocol::{self, ProtocolError},
+        },
+        piece::Piece,
+        torrent::Torrent,
+    },
+    logging::log_handle::LogHandle,
+    messages::{
+        message_types::{handshake::Handshake, have::Have},
+        peer_message::PeerMessage,
+    },
+    network::{
+        ban_list::{BanList, Offense},
+        connection::{Connection, ConnectionId},
+        event_loop::{PeerHandler, Timers},
+        ip_filter::IpFilter,
+        rate_limit::{RateLimits, Throttle},
+    },
+    storage::disk_cache::DiskCache,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::Instant,
+};
+
+/// The sessions of the event loop are shown in the UI and ranked by the choker with ids from this one on,
+/// so they are told apart from download sessions.
+pub const SESSION_STATE_IDS: usize = usize::MAX / 2;
+
+/// State of a connection of the event loop, through which the client both uploads to the peer and
+/// downloads from it.
+struct Session {
+    info_hash: [u8; 20],
+    peer: Peer,
+    /// What the peer can download, as it was told with the bitfield and with `Have` messages.
+    upload: Option<UploadInfo>,
+    /// Piece that is being downloaded from the peer, if any.
+    piece: Option<Piece>,
+    throttle: Throttle,
+    /// Whether the client connected to the peer, which then goes back to the queue of its torrent once
+    /// the connection closes.
+    outbound: bool,
+}
+
+/// Runs the sessions of the peers of an event loop. Pieces of the upload pool are served to the peers that
+/// the choker unchokes, and the pieces that loaded torrents still miss are downloaded from every peer. The
+/// queued peers of the torrents that are downloading are connected to as well.
+pub struct SessionHandler {
+    server_id: [u8; 20],
+    torrents: Arc<Mutex<HashMap<[u8; 20], UploadInfo>>>,
+    disk_cache: DiskCache,
+    notif_tx: Sender<Notification>,
+    log_handle: LogHandle,
+    loaded_torrents: LoadedTorrents,
+    sessions: HashMap<ConnectionId, Session>,
+    choker: Choker,
+    rate_limits: RateLimits,
+    ban_list: BanList,
+    ip_filter: IpFilter,
+    timers: Timers,
+    connected_peers: ConnectedPeers,
+    connection_limit: ConnectionLimit,
+    /// Permits of the accepted connections, by the address of their peer.
+    permits: HashMap<SocketAddr, ConnectionPermit>,
+    /// Peers that are being connected to, with the torrent to download from them, by their address.
+    dialing: HashMap<SocketAddr, ([u8; 20], Peer)>,
+}
+
+impl SessionHandler {
+    pub fn new(
+        server_id: [u8; 20],
+        torrents: Arc<Mutex<HashMap<[u8; 20], UploadInfo>>>,
+        disk_cache: DiskCache,
+        notif_tx: Sender<Notification>,
+        log_handle: LogHandle,
+    ) -> Self {
+        Self {
+            server_id,
+            torrents,
+            disk_cache,
+            notif_tx,
+            log_handle,
+            loaded_torrents: LoadedTorrents::default(),
+            sessions: HashMap::new(),
+            choker: Choker::default(),
+            rate_limits: RateLimits::default(),
+            ban_list: BanList::default(),
+            ip_filter: IpFilter::default(),
+            timers: Timers::default(),
+            connected_peers: ConnectedPeers::default(),
+            connection_limit: ConnectionLimit::new(usize::MAX),
+            permits: HashMap::new(),
+            dialing: HashMap::new(),
+        }
+    }
+
+    /// Sets how many peers are uploaded to at once.
+    pub fn set_upload_slots(&mut self, slots: usize) {
+        self.choker = Choker::new(slots);
+    }
+
+    /// Shares the rate limits of the client side, so sessions are limited together with web seeds.
+    pub fn set_rate_limits(&mut self, rate_limits: RateLimits) {
+        self.rate_limits = rate_limits;
+    }
+
+    /// Shares the peers that the client side banned, so they are neither accepted nor downloaded from.
+    pub fn set_ban_list(&mut self, ban_list: BanList) {
+        self.ban_list = ban_list;
+    }
+
+    /// Shares the blocklist of the client side, so blocked peers are not accepted either.
+    pub fn set_ip_filter(&mut self, ip_filter: IpFilter) {
+        self.ip_filter = ip_filter;
+    }
+
+    /// Shares the peers that the client is connected to, so no peer is connected to twice.
+    pub fn set_connected_peers(&mut self, connected_peers: ConnectedPeers) {
+        self.connected_peers = connected_peers;
+    }
+
+    /// Shares the connection limit of the client side, which both accepted and opened connections count against.
+    pub fn set_connection_limit(&mut self, connection_limit: ConnectionLimit) {
+        self.connection_limit = connection_limit;
+    }
+
+    /// Sets the time limits of the sessions, which tell when a peer snubs the client.
+    pub fn set_timers(&mut self, timers: Timers) {
+        self.timers = timers;
+    }
+
+    /// Shares the torrents of the client side, so their missing pieces are downloaded from the sessions too.
+    pub fn set_loaded_torrents(&mut self, loaded_torrents: LoadedTorrents) {
+        self.loaded_torrents = loaded_torrents;
+    }
+
+    fn upload_info(&self, info_hash: [u8; 20]) -> Result<Option<UploadInfo>, String> {
+        let torrents = self.torrents.lock().map_err(|e| e.to_string())?;
+        Ok(torrents.get(&info_hash).cloned())
+    }
+
+    /// Chooses the peers that get the upload slots among the sessions of the event loop, and shows the
+    /// result in the UI. Peers are choked and unchoked as their connections tick.
+    fn rechoke(&mut self, now: Instant) -> Result<(), String> {
+        let loaded_torrents = self.loaded_torrents.lock().map_err(|e| e.to_string())?;
+        let mut candidates = Vec::new();
+        for (id, session) in &self.sessions {
+            // Sessions of torrents that have nothing to upload yet do not get upload slots.
+            if session
+                .upload
+                .as_ref()
+                .and_then(UploadInfo::bitfield)
+                .is_none()
+            {
+                continue;
+            }
+            let seeding = match loaded_torrents.get(&session.info_hash) {
+                Some(torrent) => {
+                    !torrent.handle().is_active() || torrent.all_pieces_downloaded()?
+                }
+                None => true,
+            };
+            candidates.push(ChokeCandidate {
+                id: SESSION_STATE_IDS + id,
+                interested: session.peer.is_interested(),
+                snubbed: session.peer.is_snubbed(now),
+                seeding,
+                downloaded: session.peer.downloaded_bytes(),
+                uploaded: session.peer.uploaded_bytes(),
+            });
+        }
+        self.choker.rechoke(&candidates, now);
+
+        for (id, session) in &self.sessions {
+            let Some(torrent) = loaded_torrents.get(&session.info_hash) else {
+                continue;
+            };
+            let id = SESSION_STATE_IDS + id;
+            let mut state = torrent.worker_state(id, &session.peer, None)?;
+            state.set_is_choked(!self.choker.is_unchoked(id));
+            state.set_choker_flags(self.choker.is_optimistic(id), session.peer.is_snubbed(now));
+            self.notif_tx
+                .send(Notification::UpdateUi(state))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Hands the piece of the session over to be stored once it is complete, so the disk is not written to
+    /// from the event loop, and starts downloading the next queued piece that the peer has.
+    fn advance_download(
+        &self,
+        connection: &mut Connection,
+        session: &mut Session,
+        torrent: &Torrent,
+    ) -> Result<(), String> {
+        if session.piece.as_ref().is_some_and(Piece::is_full) {
+            if let Some(piece) = session.piece.take() {
+                self.notif_tx
+                    .send(Notification::CompletePiece(
+                        Box::new(torrent.clone()),
+                        piece,
+                    ))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        if session.piece.is_none() && torrent.handle().is_active() {
+            session.piece = torrent.take_piece_for(&session.peer)?;
+            if let Some(piece) = &session.piece {
+                session
+                    .peer
+                    .start_piece(connection, piece)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_message(
+        &self,
+        connection: &mut Connection,
+        session: &mut Session,
+        message: PeerMessage,
+    ) -> Result<(), String> {
+        let loaded_torrents = self.loaded_torrents.lock().map_err(|e| e.to_string())?;
+        let torrent = loaded_torrents.get(&session.info_hash);
+        let downloaded = torrent.map(Torrent::downloaded).unwrap_or_default();
+
+        let (downloaded_before, uploaded_before) = (
+            session.peer.downloaded_bytes(),
+            session.peer.uploaded_bytes(),
+        );
+        let result = session.peer.handle_message(
+            connection,
+            message,
+            session.piece.as_mut(),
+            session.upload.as_ref(),
+            &self.disk_cache,
+            downloaded,
+        );
+        self.throttle(connection, session, downloaded_before, uploaded_before)?;
+        if let Err(ProtocolError::Offense(offense, e)) = &result {
+            return self.punish(session, torrent, *offense, e);
+        }
+        let Some(torrent) = torrent else {
+            return result.map_err(|e| e.to_string());
+        };
+        match result {
+            Ok(()) => {}
+            Err(ProtocolError::Piece(_)) => {
+                if let Some(piece) = session.piece.take() {
+                    torrent.discard_piece(piece)?;
+                }
+            }
+            Err(ProtocolError::Peer(e)) => return Err(e),
+            Err(ProtocolError::Offense(..)) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+        self.advance_download(connection, session, torrent)
+    }
+
+    /// Counts an offense against the peers to blame for it. Corrupt pieces are blamed on every peer that
+    /// sent blocks of them, and the session goes on unless the peer of the session is banned. Any other
+    /// offense closes the connection.
+    fn punish(
+        &self,
+        session: &mut Session,
+        torrent: Option<&Torrent>,
+        offense: Offense,
+        error: &str,
+    ) -> Result<(), String> {
+        let ip = session.peer.ip();
+        let offenders = match (offense, session.piece.take()) {
+            (Offense::BadPiece, Some(mut piece)) => {
+                let contributors = piece.reset();
+                if let Some(torrent) = torrent {
+                    torrent.discard_piece(piece)?;
+                }
+                contributors
+            }
+            (_, piece) => {
+                session.piece = piece;
+                vec![ip.clone()]
+            }
+        };
+        self.ban_list
+            .report(&offenders, offense, &self.log_handle)?;
+
+        if offense != Offense::BadPiece || self.ban_list.is_banned(&ip)? {
+            return Err(format!("Peer {ip} misbehaved: {error}"));
+        }
+        Ok(())
+    }
+
+    /// Takes the bytes that the last message transferred from the rate limiters of the session, and stops
+    /// reading from the peer until the limiters allow more.
+    fn throttle(
+        &self,
+        connection: &mut Connection,
+        session: &Session,
+        downloaded_before: usize,
+        uploaded_before: usize,
+    ) -> Result<(), String> {
+        let now = Instant::now();
+        let wait = session.throttle.transfer(
+            session
+                .peer
+                .uploaded_bytes()
+                .saturating_sub(uploaded_before),
+            session
+                .peer
+                .downloaded_bytes()
+                .saturating_sub(downloaded_before),
+            now,
+        )?;
+        if !wait.is_zero() {
+            connection.pause_reads(now + wait);
+        }
+        Ok(())
+    }
+
+    /// Applies the choice of the choker, tells the peer about the pieces that were downloaded since the
+    /// last tick, and gives pieces back to the queue of torrents that were stopped. Sessions of torrents
+    /// that are neither served nor downloaded anymore are closed.
+    fn tick(&self, connection: &mut Connection, session: &mut Session) -> Result<(), String> {
+        let upload = self.upload_info(session.info_hash)?;
+        let loaded_torrents = self.loaded_torrents.lock().map_err(|e| e.to_string())?;
+        let torrent = loaded_torrents.get(&session.info_hash);
+        let downloading = torrent.is_some_and(|torrent| torrent.handle().is_active());
+        if upload.is_none() && !downloading {
+            return Err(format!(
+                "Torrent {:?} is not served by this peer anymore",
+                session.info_hash
+            ));
+        }
+
+        session
+            .peer
+            .set_choked(
+                connection,
+                !self.choker.is_unchoked(SESSION_STATE_IDS + connection.id()),
+            )
+            .map_err(|e| e.to_string())?;
+
+        if let Some(bitfield) = upload.as_ref().and_then(UploadInfo::bitfield) {
+            let announced = session.upload.as_ref().and_then(UploadInfo::bitfield);
+            for piece_index in 0..bitfield.total_pieces() {
+                let was_announced = announced
+                    .as_ref()
+                    .is_some_and(|announced| announced.contains(piece_index));
+                if bitfield.contains(piece_index) && !was_announced {
+                    Have::new(piece_index as u32)
+                        .send(connection)
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        session.upload = upload;
+
+        let Some(torrent) = torrent else {
+            return Ok(());
+        };
+        if !downloading {
+            if let Some(piece) = session.piece.take() {
+                torrent.discard_piece(piece)?;
+            }
+        }
+        self.advance_download(connection, session, torrent)
+    }
+
+    /// Counts the connections that were opened, or are being opened, to download a torrent.
+    fn outbound_connections(&self, info_hash: [u8; 20]) -> usize {
+        let sessions = self
+            .sessions
+            .values()
+            .filter(|session| session.outbound && session.info_hash == info_hash)
+            .count();
+        let dialing = self
+            .dialing
+            .values()
+            .filter(|(dialed_hash, _)| *dialed_hash == info_hash)
+            .count();
+        sessions + dialing
+    }
+
+    /// Takes queued peers of the torrents that are downloading until each one has as many connections as
+    /// the peer manager allows, or the connection limit is reached. Returns the addresses to connect to
+    /// with the handshake to send them.
+    fn dial(&mut self) -> Result<Vec<(SocketAddr, Handshake)>, String> {
+        let loaded_torrents = self.loaded_torrents.clone();
+        let loaded_torrents = loaded_torrents.lock().map_err(|e| e.to_string())?;
+        let mut to_connect = Vec::new();
+
+        for (info_hash, torrent) in loaded_torrents.iter() {
+            if !torrent.handle().is_active() || torrent.all_pieces_downloaded()? {
+                continue;
+            }
+            while self.outbound_connections(*info_hash) < torrent.target_connections() {
+                let Some(mut peer) = torrent.take_peer()? else {
+                    break;
+                };
+                // Peers whose address can not be parsed are never connected to.
+                let Some(address) = peer.socket_address() else {
+                    continue;
+                };
+                if self.dialing.contains_key(&address) {
+                    torrent.discard_peer(peer)?;
+                    break;
+                }
+                let Some(permit) = self.connection_limit.try_acquire()? else {
+                    torrent.discard_peer(peer)?;
+                    return Ok(to_connect);
+                };
+                peer.set_permit(permit);
+                let swarm_hash = peer.swarm_hash().unwrap_or(*info_hash);
+                to_connect.push((
+                    address,
+                    peer_protocol::handshake(self.server_id, swarm_hash),
+                ));
+                self.dialing.insert(address, (*info_hash, peer));
+            }
+        }
+        Ok(to_connect)
+    }
+}
+
+impl PeerHandler for SessionHandler {
+    /// Refuses banned and blocked peers, and every peer once the connection limit is reached.
+    fn on_accept(&mut self, address: SocketAddr) -> bool {
+        let ip = address.ip().to_string();
+        // A ban list that can not be read does not keep peers out, but a blocklist that can't be read does.
+        if self.ban_list.is_banned(&ip).unwrap_or(false)
+            || self.ip_filter.is_blocked(&ip).unwrap_or(true)
+        {
+            return false;
+        }
+        match self.connection_limit.try_acquire() {
+            Ok(Some(permit)) => {
+                self.permits.insert(address, permit);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Answers the handshakes of accepted connections, and checks that the peers that the client connected
+    /// to are the ones it meant to.
+    fn on_handshake(
+        &mut self,
+        connection: &mut Connection,
+        handshake: Handshake,
+    ) -> Result<(), String> {
+        let dialed = match connection.is_outbound() {
+            true => Some(
+                self.dialing
+                    .remove(&connection.address())
+                    .ok_or("Connected to a peer that was not dialed")?,
+            ),
+            false => None,
+        };
+        let info_hash = dialed
+            .as_ref()
+            .map_or(handshake.info_hash(), |(info_hash, _)| *info_hash);
+        let upload = self.upload_info(info_hash)?;
+        let loaded_torrents = self.loaded_torrents.lock().map_err(|e| e.to_string())?;
+        let total_pieces = match (&upload, loaded_torrents.get(&info_hash)) {
+            (_, Some(torrent)) => torrent.total_pieces(),
+            (Some(upload), None) => upload.total_pieces(),
+            (None, None) => {
+                return Err(format!("Torrent {info_hash:?} is not served by this peer"));
+            }
+        };
+        drop(loaded_torrents);
+
+        let mut peer = match dialed {
+            Some((_, mut peer)) => {
+                let swarm_hash = peer.swarm_hash().unwrap_or(info_hash);
+                peer_protocol::validate_handshake(
+                    &handshake,
+                    self.server_id,
+                    swarm_hash,
+                    peer.id(),
+                )
+                .map_err(|e| e.to_string())?;
+                peer.set_handshake(handshake.peer_id(), total_pieces);
+                peer
+            }
+            None => {
+                // Outgoing connections already sent their handshake when they were opened. Answering before
+                // the handshake is validated lets the client tell that it connected to itself on the other
+                // end too.
+                peer_protocol::send_handshake(connection, self.server_id, info_hash)
+                    .map_err(|e| e.to_string())?;
+                peer_protocol::validate_handshake(&handshake, self.server_id, info_hash, None)
+                    .map_err(|e| e.to_string())?;
+                Peer::from_accepted(handshake.peer_id(), connection.address(), total_pieces)
+            }
+        };
+        let registration = self
+            .connected_peers
+            .register(info_hash, handshake.peer_id())
+            .map_err(|e| e.to_string())?;
+        if let Some(bitfield) = upload.as_ref().and_then(UploadInfo::bitfield) {
+            bitfield.send(connection).map_err(|e| e.to_string())?;
+        }
+
+        peer.set_bandwidth(self.rate_limits.peer());
+        peer.set_timers(self.timers);
+        peer.set_registration(registration);
+        let throttle = self.rate_limits.throttle(info_hash, peer.bandwidth())?;
+        self.sessions.insert(
+            connection.id(),
+            Session {
+                info_hash,
+                peer,
+                upload,
+                piece: None,
+                throttle,
+                outbound: connection.is_outbound(),
+            },
+        );
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        connection: &mut Connection,
+        message: PeerMessage,
+    ) -> Result<(), String> {
+        let mut session = self
+            .sessions
+            .remove(&connection.id())
+            .ok_or("Peer sent a message before its handshake")?;
+        let result = self.handle_message(connection, &mut session, message);
+        self.sessions.insert(connection.id(), session);
+        result
+    }
+
+    /// Chooses the peers that get the upload slots when it is time to, so choked peers are unchoked even if
+    /// they send nothing.
+    fn on_tick_start(&mut self) {
+        let now = Instant::now();
+        if !self.choker.is_due(now) {
+            return;
+        }
+        if let Err(e) = self.rechoke(now) {
+            let _ = self
+                .log_handle
+                .log(&format!("Could not choose the peers to upload to: {e}"));
+        }
+    }
+
+    fn peers_to_connect(&mut self) -> Vec<(SocketAddr, Handshake)> {
+        self.dial().unwrap_or_else(|e| {
+            let _ = self
+                .log_handle
+                .log(&format!("Could not connect to the queued peers: {e}"));
+            Vec::new()
+        })
+    }
+
+    /// Drops the peer, along with the permit that was taken for its connection.
+    fn on_connect_error(&mut self, address: SocketAddr, _error: &str) {
+        self.dialing.remove(&address);
+    }
+
+    fn on_tick(&mut self, connection: &mut Connection) -> Result<(), String> {
+        let Some(mut session) = self.sessions.remove(&connection.id()) else {
+            return Ok(());
+        };
+        let result = self.tick(connection, &mut session);
+        self.sessions.insert(connection.id(), session);
+        result
+    }
+
+    fn on_offense(&mut self, connection: &Connection, offense: Offense) {
+        let ip = connection.address().ip().to_string();
+        let _ = self.ban_list.report(&[ip], offense, &self.log_handle);
+    }
+
+    /// Gives the piece that was being downloaded back to the queue of its torrent, and the permit of the
+    /// connection back to the connection limit. Peers that the client connected to go back to the queue
+    /// of their torrent, while those that never answered the handshake are dropped.
+    fn on_close(&mut self, connection: &Connection) {
+        self.permits.remove(&connection.address());
+        self.dialing.remove(&connection.address());
+        self.choker.remove(SESSION_STATE_IDS + connection.id());
+        if let Some(session) = self.sessions.remove(&connection.id()) {
+            if let Ok(loaded_torrents) = self.loaded_torrents.lock() {
+                if let Some(torrent) = loaded_torrents.get(&session.info_hash) {
+                    if let Some(piece) = session.piece {
+                        let _ = torrent.discard_piece(piece);
+                    }
+                    if session.outbound {
+                        let mut peer = session.peer;
+                        peer.disconnect();
+                        let _ = torrent.discard_peer(peer);
+                    }
+                }
+            }
+        }
+        let _ = self.notif_tx.send(Notification::EndPeer(connection.id()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::peer_source::PeerSource;
+    use std::sync::mpsc;
+
+    #[test]
+    fn accepted_connections_count_against_the_connection_limit() -> Result<(), String> {
+        let (notif_tx, _notif_rx) = mpsc::channel();
+        let (log_tx, _log_rx) = mpsc::channel();
+        let mut handler = SessionHandler::new(
+            [0; 20],
+            Arc::default(),
+            DiskCache::new(0),
+            notif_tx,
+            LogHandle::new(log_tx),
+        );
+        let limit = ConnectionLimit::new(1);
+        handler.set_connection_limit(limit.clone());
+        let address = |port| SocketAddr::from(([127, 0, 0, 1], port));
+
+        assert!(handler.on_accept(address(6881)));
+        assert!(!handler.on_accept(address(6882)));
+        assert_eq!(1, limit.open_connections()?);
+
+        limit.set_max(2);
+        assert!(handler.on_accept(address(6882)));
+        assert_eq!(2, limit.open_connections()?);
+        Ok(())
+    }
+
+    #[test]
+    fn queued_peers_of_active_torrents_are_connected_to_within_the_limits() -> Result<(), String> {
+        let (notif_tx, _notif_rx) = mpsc::channel();
+        let (log_tx, _log_rx) = mpsc::channel();
+        let mut handler = SessionHandler::new(
+            [0; 20],
+            Arc::default(),
+            DiskCache::new(0),
+            notif_tx,
+            LogHandle::new(log_tx),
+        );
+        let limit = ConnectionLimit::new(5);
+        handler.set_connection_limit(limit.clone());
+
+        let torrent = Torrent::from("tests/sample.torrent")?;
+        let info_hash = torrent.info_hash();
+        for port in [6881, 6882] {
+            let peer = Peer::new(None, "127.0.0.1".to_string(), port);
+            torrent.add_peer(peer, PeerSource::Tracker)?;
+        }
+        let loaded_torrents = LoadedTorrents::default();
+        loaded_torrents
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(info_hash, torrent);
+        handler.set_loaded_torrents(loaded_torrents);
+
+        // Each torrent downloads from one peer at once by default.
+        let to_connect = handler.peers_to_connect();
+        assert_eq!(1, to_connect.len());
+        assert_eq!(1, limit.open_connections()?);
+        assert!(handler.peers_to_connect().is_empty());
+
+        handler.on_connect_error(to_connect[0].0, "refused");
+        assert_eq!(0, limit.open_connections()?);
+        let next = handler.peers_to_connect();
+        assert_eq!(1, next.len());
+        assert_ne!(to_connect[0].0, next[0].0);
+        Ok(())
+    }
+}
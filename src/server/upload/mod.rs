@@ -1,3 +1,2 @@
 pub mod upload_info;
 pub mod upload_pool;
-mod upload_worker;
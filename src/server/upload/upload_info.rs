@@ -1,4 +1,5 @@
-use crate::messages::message_types::bitfield::Bitfield;
+use crate::{client::torrent_v2::V2Info, messages::message_types::bitfield::Bitfield};
+use std::sync::Arc;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub struct UploadInfo {
@@ -6,6 +7,7 @@ pub struct UploadInfo {
     download_path: String,
     bitfield: Option<Bitfield>,
     total_pieces: usize,
+    v2: Option<Arc<V2Info>>,
 }
 
 impl UploadInfo {
@@ -15,6 +17,7 @@ impl UploadInfo {
             download_path,
             bitfield: None,
             total_pieces,
+            v2: None,
         }
     }
 
@@ -22,34 +25,42 @@ impl UploadInfo {
         self.info_hash
     }
 
-    pub fn download_path(&self) -> String {
-        self.download_path.clone()
+    pub fn set_v2(&mut self, v2: Arc<V2Info>) {
+        self.v2 = Some(v2);
     }
 
-    pub fn add_piece_to_bitfield(&mut self, piece_index: usize) -> Result<(), String> {
-        let mut bitfield = match self.bitfield.clone() {
-            Some(msg) => msg,
-            None => {
-                let mut bitfield_vec = Vec::<u8>::new();
-                let mut byte_amount = self.total_pieces / 8;
-
-                if self.total_pieces % 8 > 0 {
-                    byte_amount += 1;
-                }
-
-                for _i in 0..byte_amount {
-                    bitfield_vec.push(0);
-                }
+    pub fn v2(&self) -> Option<Arc<V2Info>> {
+        self.v2.clone()
+    }
 
-                Bitfield::new(bitfield_vec.clone())
+    /// Returns the info hashes that peers can use to ask for this torrent, which are two for hybrid torrents.
+    pub fn swarm_hashes(&self) -> Vec<[u8; 20]> {
+        let mut hashes = vec![self.info_hash];
+        if let Some(v2) = &self.v2 {
+            if v2.truncated_info_hash() != self.info_hash {
+                hashes.push(v2.truncated_info_hash());
             }
-        };
+        }
+        hashes
+    }
 
-        bitfield.add_piece(piece_index as usize);
-        self.bitfield = Some(bitfield);
+    pub fn download_path(&self) -> String {
+        self.download_path.clone()
+    }
+
+    pub fn add_piece_to_bitfield(&mut self, piece_index: usize) -> Result<(), String> {
+        let total_pieces = self.total_pieces;
+        let bitfield = self
+            .bitfield
+            .get_or_insert_with(|| Bitfield::new(vec![0; total_pieces.div_ceil(8)]));
+        bitfield.add_piece(piece_index);
         Ok(())
     }
 
+    pub fn total_pieces(&self) -> usize {
+        self.total_pieces
+    }
+
     pub fn bitfield(&self) -> Option<Bitfield> {
         self.bitfield.clone()
     }
@@ -1,74 +1,72 @@
-use super::{upload_info::UploadInfo, upload_worker::UploadWorker};
-use crate::{client::piece::Piece, server::server_side::Notification};
+use super::upload_info::UploadInfo;
+use crate::{
+    client::piece::Piece,
+    logging::log_handle::LogHandle,
+    server::{server_side::Notification, session_handler::SessionHandler},
+    storage::disk_cache::DiskCache,
+};
 use std::{
     collections::HashMap,
-    net::TcpStream,
     sync::{mpsc::Sender, Arc, Mutex},
 };
 
+/// Keeps track of the pieces that every torrent can serve. The connections of the peers that download them
+/// run on the event loop of a `SessionHandler`.
 pub struct UploadPool {
     server_id: [u8; 20],
     torrents: Arc<Mutex<HashMap<[u8; 20], UploadInfo>>>,
-    workers: HashMap<usize, UploadWorker>,
+    disk_cache: DiskCache,
 }
 
 impl Default for UploadPool {
     fn default() -> Self {
-        Self::new([0; 20])
+        Self::new([0; 20], DiskCache::default())
     }
 }
 
 impl UploadPool {
-    pub fn new(server_id: [u8; 20]) -> Self {
+    pub fn new(server_id: [u8; 20], disk_cache: DiskCache) -> Self {
         Self {
             server_id,
             torrents: Arc::new(Mutex::new(HashMap::<[u8; 20], UploadInfo>::new())),
-            workers: HashMap::<usize, UploadWorker>::new(),
+            disk_cache,
         }
     }
 
-    pub fn add_worker(
-        &mut self,
-        stream: TcpStream,
+    /// Returns a handler that serves the torrents of the pool to the peers of an event loop.
+    pub fn handler(
+        &self,
         notif_tx: &Sender<Notification>,
-    ) -> Result<(), String> {
-        let worker = UploadWorker::new(
-            self.workers.len(),
-            stream,
+        log_handle: LogHandle,
+    ) -> SessionHandler {
+        SessionHandler::new(
             self.server_id,
             self.torrents.clone(),
+            self.disk_cache.clone(),
             notif_tx.clone(),
-        )?;
-
-        self.workers.insert(worker.id(), worker);
-        Ok(())
-    }
-
-    pub fn remove_worker(&mut self, id: usize) -> Result<(), String> {
-        self.workers
-            .remove(&id)
-            .ok_or_else(|| format!("Error removing upload worker {id}"))?
-            .join()
+            log_handle,
+        )
     }
 
     pub fn add_piece(&mut self, piece: Piece, upload_info: UploadInfo) -> Result<(), String> {
         let mut torrents = self.torrents.lock().map_err(|e| e.to_string())?;
 
-        let mut updated_info = match torrents.remove(&upload_info.info_hash()) {
-            Some(info) => info,
-            None => upload_info,
-        };
+        for swarm_hash in upload_info.swarm_hashes() {
+            let mut updated_info = match torrents.remove(&swarm_hash) {
+                Some(info) => info,
+                None => upload_info.clone(),
+            };
 
-        updated_info.add_piece_to_bitfield(piece.index())?;
-        torrents.insert(updated_info.info_hash(), updated_info);
+            updated_info.add_piece_to_bitfield(piece.index())?;
+            torrents.insert(swarm_hash, updated_info);
+        }
         Ok(())
     }
-}
 
-impl Drop for UploadPool {
-    fn drop(&mut self) {
-        for (_, mut worker) in self.workers.drain() {
-            let _ = worker.join();
-        }
+    /// Stops serving a torrent in every swarm it belongs to. Connections that upload it close on their next tick.
+    pub fn remove_torrent(&mut self, info_hash: [u8; 20]) -> Result<(), String> {
+        let mut torrents = self.torrents.lock().map_err(|e| e.to_string())?;
+        torrents.retain(|_, upload_info| upload_info.info_hash() != info_hash);
+        Ok(())
     }
 }
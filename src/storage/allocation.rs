@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Error,
+    path::Path,
+    str::FromStr,
+};
+
+/// Ways in which the files of a torrent can be laid out on disk before downloading them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationMode {
+    /// Files are created with their final length, without reserving their disk blocks.
+    Sparse,
+    /// Every disk block that the files need is reserved before downloading.
+    Full,
+    /// Files are only created once the download finishes.
+    None,
+}
+
+impl FromStr for AllocationMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.trim() {
+            "sparse" => Ok(Self::Sparse),
+            "full" => Ok(Self::Full),
+            "none" => Ok(Self::None),
+            _ => Err(format!("Invalid allocation mode: {mode}")),
+        }
+    }
+}
+
+/// Lays out a file of `length` bytes at `path` as the given `mode` describes, creating its parent directories.
+/// Existing files are never shrunk, so data already present on disk is kept.
+pub fn allocate(path: &Path, length: u64, mode: AllocationMode) -> Result<(), String> {
+    let err = |e: Error| format!("Error allocating {}: {e}", path.display());
+
+    if mode == AllocationMode::None {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(err)?;
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .map_err(err)?;
+
+    if mode == AllocationMode::Full {
+        preallocate(&file, length).map_err(err)?;
+    }
+    if file.metadata().map_err(err)?.len() < length {
+        file.set_len(length).map_err(err)?;
+    }
+    Ok(())
+}
+
+/// Returns the amount of bytes of `path` that are still missing to reach `length`.
+pub fn missing_bytes(path: &Path, length: u64) -> u64 {
+    let current_length = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    length.saturating_sub(current_length)
+}
+
+/// Checks that the filesystem where `dir` lives has room for `required` more bytes.
+pub fn check_free_space(dir: &Path, required: u64) -> Result<(), String> {
+    let available = available_space(dir)?;
+    if available < required {
+        return Err(format!(
+            "Not enough free space in {}: {required} bytes needed, {available} bytes available",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, length: u64) -> Result<(), Error> {
+    use std::os::unix::io::AsRawFd;
+
+    if length == 0 {
+        return Ok(());
+    }
+    // SAFETY: the descriptor belongs to `file`, which stays open for the whole call.
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, length as libc::off_t) };
+    if result != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn preallocate(file: &File, length: u64) -> Result<(), Error> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let mut file = file;
+    let current_length = file.metadata()?.len();
+    let zeros = vec![0; 1 << 16];
+    file.seek(SeekFrom::Start(current_length))?;
+
+    let mut written = current_length;
+    while written < length {
+        let chunk = zeros.len().min((length - written) as usize);
+        file.write_all(&zeros[..chunk])?;
+        written += chunk as u64;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // statvfs field types differ between platforms
+fn available_space(dir: &Path) -> Result<u64, String> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(dir.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `c_path` is a valid NUL terminated string and `stats` is only read if the call succeeds.
+    let result = unsafe { libc::statvfs(c_path.as_ptr(), stats.as_mut_ptr()) };
+    if result != 0 {
+        let e = Error::last_os_error();
+        return Err(format!(
+            "Error reading free space of {}: {e}",
+            dir.display()
+        ));
+    }
+    let stats = unsafe { stats.assume_init() };
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_dir: &Path) -> Result<u64, String> {
+    Ok(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_dir;
+
+    #[test]
+    fn parse_allocation_modes() -> Result<(), String> {
+        assert_eq!(AllocationMode::Sparse, AllocationMode::from_str("sparse")?);
+        assert_eq!(AllocationMode::Full, AllocationMode::from_str("full")?);
+        assert_eq!(AllocationMode::None, AllocationMode::from_str("none")?);
+        assert!(AllocationMode::from_str("compact").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn allocated_files_have_their_final_length() -> Result<(), String> {
+        let dir = test_dir("allocation");
+
+        for (name, mode) in [
+            ("sparse", AllocationMode::Sparse),
+            ("full", AllocationMode::Full),
+        ] {
+            let path = dir.join(name).join("file.bin");
+            allocate(&path, 100_000, mode)?;
+            assert_eq!(
+                100_000,
+                fs::metadata(&path).map_err(|e| e.to_string())?.len()
+            );
+            assert_eq!(0, missing_bytes(&path, 100_000));
+        }
+
+        let path = dir.join("none").join("file.bin");
+        allocate(&path, 100_000, AllocationMode::None)?;
+        assert!(!path.exists());
+        assert_eq!(100_000, missing_bytes(&path, 100_000));
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn allocation_keeps_existing_data() -> Result<(), String> {
+        let dir = test_dir("allocation_existing");
+        let path = dir.join("file.bin");
+        fs::write(&path, b"existing data").map_err(|e| e.to_string())?;
+
+        allocate(&path, 4, AllocationMode::Full)?;
+        assert_eq!(
+            b"existing data".to_vec(),
+            fs::read(&path).map_err(|e| e.to_string())?
+        );
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn free_space_check_fails_for_impossible_sizes() -> Result<(), String> {
+        let dir = std::env::temp_dir();
+        check_free_space(&dir, 0)?;
+        assert!(check_free_space(&dir, u64::MAX).is_err());
+        Ok(())
+    }
+}
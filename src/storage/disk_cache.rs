@@ -0,0 +1,423 @@
+use super::piece_files::PieceInFiles;
+use crate::{ui_notification_structs::cache_stats::CacheStats, utils};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Error,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// Identifies a piece by the download directory of its torrent and its index.
+type PieceKey = (String, usize);
+
+#[derive(Debug)]
+struct CachedPiece {
+    bytes: Arc<Vec<u8>>,
+    last_use: u64,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    budget: usize,
+    read_cache: HashMap<PieceKey, CachedPiece>,
+    cached_bytes: usize,
+    pending_writes: HashMap<PieceKey, Arc<Vec<u8>>>,
+    pending_bytes: usize,
+    /// Pieces that are read from the files of their torrent, since they have no copy in `.tmp`.
+    in_files: HashMap<PieceKey, PieceInFiles>,
+    flushing: bool,
+    uses: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// Bounded in-memory cache placed between the torrents and their piece files.
+/// Downloaded pieces are buffered and written to disk together once they fill half of the memory budget,
+/// and the pieces that are read to serve uploads are kept around, evicting the least recently used ones.
+/// Clones share the same cache. A budget of zero bytes disables caching.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl DiskCache {
+    pub fn new(budget: usize) -> Self {
+        let state = CacheState {
+            budget,
+            read_cache: HashMap::new(),
+            cached_bytes: 0,
+            pending_writes: HashMap::new(),
+            pending_bytes: 0,
+            in_files: HashMap::new(),
+            flushing: false,
+            uses: 0,
+            hits: 0,
+            misses: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, CacheState>, String> {
+        self.state.lock().map_err(|e| e.to_string())
+    }
+
+    /// Stores the bytes of a downloaded piece, which are written to disk on the next flush.
+    pub fn write_piece(
+        &self,
+        download_path: &str,
+        piece_index: usize,
+        bytes: Vec<u8>,
+    ) -> Result<(), String> {
+        let mut state = self.lock()?;
+        if bytes.len() > state.budget / 2 {
+            drop(state);
+            return write_piece_file(download_path, piece_index, &bytes);
+        }
+
+        let key = (download_path.to_string(), piece_index);
+        state.pending_bytes += bytes.len();
+        if let Some(replaced) = state.pending_writes.insert(key, Arc::new(bytes)) {
+            state.pending_bytes -= replaced.len();
+        }
+        state.make_room(0);
+
+        let needs_flush = state.pending_bytes > state.budget / 2 && !state.flushing;
+        drop(state);
+        if needs_flush {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Makes a piece be read from the files of its torrent, where a recheck found it.
+    pub fn keep_in_files(
+        &self,
+        download_path: &str,
+        piece_index: usize,
+        piece: PieceInFiles,
+    ) -> Result<(), String> {
+        let key = (download_path.to_string(), piece_index);
+        self.lock()?.in_files.insert(key, piece);
+        Ok(())
+    }
+
+    /// Returns true if the piece is read from the files of its torrent, so the files are not built from it.
+    pub fn is_in_files(&self, download_path: &str, piece_index: usize) -> Result<bool, String> {
+        let key = (download_path.to_string(), piece_index);
+        Ok(self.lock()?.in_files.contains_key(&key))
+    }
+
+    /// Returns `length` bytes of a piece starting at `begin`, reading the whole piece from disk
+    /// only if it is not already cached.
+    pub fn read_block(
+        &self,
+        download_path: &str,
+        piece_index: usize,
+        begin: usize,
+        length: usize,
+    ) -> Result<Vec<u8>, String> {
+        let piece = self.read_piece(download_path, piece_index)?;
+        piece
+            .get(begin..(begin + length))
+            .map(|block| block.to_vec())
+            .ok_or_else(|| {
+                format!("Block {begin}+{length} is out of the bounds of piece {piece_index}")
+            })
+    }
+
+    fn read_piece(&self, download_path: &str, piece_index: usize) -> Result<Arc<Vec<u8>>, String> {
+        let key = (download_path.to_string(), piece_index);
+        let mut state = self.lock()?;
+
+        if let Some(bytes) = state.get(&key) {
+            state.hits += 1;
+            return Ok(bytes);
+        }
+        state.misses += 1;
+        let in_files = state.in_files.get(&key).cloned();
+        drop(state);
+
+        let bytes = Arc::new(match in_files {
+            Some(piece) => piece.read()?,
+            None => utils::read_piece_file(format!("{download_path}/.tmp"), piece_index)?,
+        });
+        self.lock()?.insert(key, bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Returns true if the piece is either waiting to be written or already on disk.
+    pub fn has_piece(&self, download_path: &str, piece_index: usize) -> Result<bool, String> {
+        let key = (download_path.to_string(), piece_index);
+        let state = self.lock()?;
+        if state.pending_writes.contains_key(&key) || state.in_files.contains_key(&key) {
+            return Ok(true);
+        }
+        drop(state);
+        Ok(Path::new(&piece_file_path(download_path, piece_index)).is_file())
+    }
+
+    /// Writes every buffered piece to disk. The files are written without holding the lock, so uploads
+    /// keep reading from the cache meanwhile, and the pieces are served from memory until they are written.
+    pub fn flush(&self) -> Result<(), String> {
+        let pending = self.lock()?.start_flush();
+        let mut written = Vec::with_capacity(pending.len());
+        let mut result = Ok(());
+        for ((download_path, piece_index), bytes) in pending {
+            if let Err(e) = write_piece_file(&download_path, piece_index, &bytes) {
+                result = Err(e);
+                break;
+            }
+            written.push(((download_path, piece_index), bytes));
+        }
+        self.lock()?.finish_flush(written);
+        result
+    }
+
+    /// Drops every piece of a torrent from the cache without writing it, for torrents whose data is deleted.
+    pub fn forget(&self, download_path: &str) -> Result<(), String> {
+        let mut state = self.lock()?;
+        let pending: usize = state
+            .pending_writes
+            .iter()
+            .filter(|((path, _), _)| path == download_path)
+            .map(|(_, bytes)| bytes.len())
+            .sum();
+        let cached: usize = state
+            .read_cache
+            .iter()
+            .filter(|((path, _), _)| path == download_path)
+            .map(|(_, cached)| cached.bytes.len())
+            .sum();
+
+        state
+            .pending_writes
+            .retain(|(path, _), _| path != download_path);
+        state
+            .read_cache
+            .retain(|(path, _), _| path != download_path);
+        state.in_files.retain(|(path, _), _| path != download_path);
+        state.pending_bytes -= pending;
+        state.cached_bytes -= cached;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<CacheStats, String> {
+        let state = self.lock()?;
+        Ok(CacheStats::new(
+            state.hits,
+            state.misses,
+            state.cached_bytes,
+            state.pending_bytes,
+            state.budget,
+        ))
+    }
+}
+
+impl Default for DiskCache {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl CacheState {
+    fn get(&mut self, key: &PieceKey) -> Option<Arc<Vec<u8>>> {
+        if let Some(bytes) = self.pending_writes.get(key) {
+            return Some(bytes.clone());
+        }
+        self.uses += 1;
+        let uses = self.uses;
+        self.read_cache.get_mut(key).map(|cached| {
+            cached.last_use = uses;
+            cached.bytes.clone()
+        })
+    }
+
+    fn insert(&mut self, key: PieceKey, bytes: Arc<Vec<u8>>) {
+        if bytes.len() > self.budget.saturating_sub(self.pending_bytes) {
+            return;
+        }
+        self.make_room(bytes.len());
+        self.uses += 1;
+        self.cached_bytes += bytes.len();
+
+        let cached = CachedPiece {
+            bytes,
+            last_use: self.uses,
+        };
+        if let Some(replaced) = self.read_cache.insert(key, cached) {
+            self.cached_bytes -= replaced.bytes.len();
+        }
+    }
+
+    /// Evicts the least recently used pieces until `needed` more bytes fit in the budget.
+    fn make_room(&mut self, needed: usize) {
+        while self.cached_bytes + self.pending_bytes + needed > self.budget {
+            let oldest = self
+                .read_cache
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_use)
+                .map(|(key, _)| key.clone());
+
+            match oldest.and_then(|key| self.read_cache.remove(&key)) {
+                Some(evicted) => self.cached_bytes -= evicted.bytes.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the buffered pieces in order, so pieces of the same torrent reach the disk together. They
+    /// stay buffered until `finish_flush`.
+    fn start_flush(&mut self) -> Vec<(PieceKey, Arc<Vec<u8>>)> {
+        self.flushing = true;
+        let mut pending: Vec<(PieceKey, Arc<Vec<u8>>)> = self
+            .pending_writes
+            .iter()
+            .map(|(key, bytes)| (key.clone(), bytes.clone()))
+            .collect();
+        pending.sort_by(|(a, _), (b, _)| a.cmp(b));
+        pending
+    }
+
+    /// Stops buffering the written pieces, unless they were replaced or forgotten during the flush, and
+    /// keeps them as recently used since peers usually ask for the newest pieces.
+    fn finish_flush(&mut self, written: Vec<(PieceKey, Arc<Vec<u8>>)>) {
+        self.flushing = false;
+        for (key, bytes) in written {
+            if !self
+                .pending_writes
+                .get(&key)
+                .is_some_and(|pending| Arc::ptr_eq(pending, &bytes))
+            {
+                continue;
+            }
+            self.pending_writes.remove(&key);
+            self.pending_bytes -= bytes.len();
+            self.insert(key, bytes);
+        }
+    }
+}
+
+impl Drop for CacheState {
+    fn drop(&mut self) {
+        for ((download_path, piece_index), bytes) in self.pending_writes.drain() {
+            let _ = write_piece_file(&download_path, piece_index, &bytes);
+        }
+    }
+}
+
+fn piece_file_path(download_path: &str, piece_index: usize) -> String {
+    format!("{download_path}/.tmp/{piece_index}")
+}
+
+fn write_piece_file(download_path: &str, piece_index: usize, bytes: &[u8]) -> Result<(), String> {
+    let err = |e: Error| e.to_string();
+    let path = piece_file_path(download_path, piece_index);
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent).map_err(err)?;
+    }
+    fs::write(path, bytes).map_err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> String {
+        utils::test_dir(&format!("cache_{name}"))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn buffered_writes_are_served_before_reaching_disk() -> Result<(), String> {
+        let dir = test_dir("buffered");
+        let cache = DiskCache::new(1000);
+
+        cache.write_piece(&dir, 0, vec![1; 100])?;
+        assert!(cache.has_piece(&dir, 0)?);
+        assert!(!Path::new(&piece_file_path(&dir, 0)).exists());
+        assert_eq!(vec![1; 10], cache.read_block(&dir, 0, 90, 10)?);
+
+        cache.flush()?;
+        assert_eq!(
+            vec![1; 100],
+            utils::read_piece_file(format!("{dir}/.tmp"), 0)?
+        );
+        assert_eq!(
+            (1, 0),
+            (cache.stats()?.get_hits(), cache.stats()?.get_misses())
+        );
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn writes_are_flushed_together_once_half_the_budget_is_used() -> Result<(), String> {
+        let dir = test_dir("coalesced");
+        let cache = DiskCache::new(1000);
+
+        for index in 0..5 {
+            cache.write_piece(&dir, index, vec![index as u8; 100])?;
+        }
+        assert!(!Path::new(&piece_file_path(&dir, 0)).exists());
+
+        cache.write_piece(&dir, 5, vec![5; 100])?;
+        for index in 0..6 {
+            assert!(Path::new(&piece_file_path(&dir, index)).is_file());
+        }
+        assert_eq!(0, cache.stats()?.get_pending_write_bytes());
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn forgotten_pieces_are_never_written() -> Result<(), String> {
+        let dir = test_dir("forgotten");
+        let cache = DiskCache::new(1000);
+
+        cache.write_piece(&dir, 0, vec![1; 100])?;
+        cache.write_piece("other", 0, vec![2; 100])?;
+        cache.forget(&dir)?;
+        assert!(!cache.has_piece(&dir, 0)?);
+        assert!(cache.has_piece("other", 0)?);
+        assert_eq!(100, cache.stats()?.get_pending_write_bytes());
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn least_recently_used_pieces_are_evicted() -> Result<(), String> {
+        let dir = test_dir("eviction");
+        for index in 0..3 {
+            write_piece_file(&dir, index, &[index as u8; 400])?;
+        }
+        let cache = DiskCache::new(1000);
+
+        cache.read_block(&dir, 0, 0, 1)?;
+        cache.read_block(&dir, 1, 0, 1)?;
+        cache.read_block(&dir, 0, 0, 1)?;
+        cache.read_block(&dir, 2, 0, 1)?;
+        assert_eq!(800, cache.stats()?.get_cached_bytes());
+
+        cache.read_block(&dir, 0, 0, 1)?;
+        cache.read_block(&dir, 1, 0, 1)?;
+        let stats = cache.stats()?;
+        assert_eq!((2, 4), (stats.get_hits(), stats.get_misses()));
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn out_of_bounds_blocks_are_rejected() -> Result<(), String> {
+        let dir = test_dir("bounds");
+        let cache = DiskCache::new(0);
+
+        cache.write_piece(&dir, 0, vec![0; 10])?;
+        assert!(Path::new(&piece_file_path(&dir, 0)).is_file());
+        assert!(cache.read_block(&dir, 0, 5, 10).is_err());
+
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,103 @@
+use sha1::{Digest, Sha1};
+use std::{
+    fs::{self, File},
+    io::{Error, Read},
+    path::{Path, PathBuf},
+};
+
+/// Adds the executable permission to a file for everyone who can read it.
+#[cfg(unix)]
+pub fn make_executable(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let err = |e: Error| format!("Error making {} executable: {e}", path.display());
+    let mut permissions = fs::metadata(path).map_err(err)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(mode | ((mode & 0o444) >> 2));
+    fs::set_permissions(path, permissions).map_err(err)
+}
+
+#[cfg(not(unix))]
+pub fn make_executable(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Creates a symbolic link at `link` that points to `target`, where both paths are relative to `root`.
+/// The link is written relative to its own directory, so it cannot point outside of `root` as long as
+/// neither path has parent directory references.
+pub fn create_symlink(root: &Path, link: &str, target: &str) -> Result<(), String> {
+    let link_path = root.join(link);
+    let err = |e: Error| format!("Error creating link {}: {e}", link_path.display());
+
+    let mut relative_target = PathBuf::new();
+    for _ in 1..Path::new(link).components().count() {
+        relative_target.push("..");
+    }
+    relative_target.push(target);
+
+    if let Some(parent) = link_path.parent() {
+        fs::create_dir_all(parent).map_err(err)?;
+    }
+    if fs::symlink_metadata(&link_path).is_ok() {
+        fs::remove_file(&link_path).map_err(err)?;
+    }
+    symlink(&relative_target, &link_path).map_err(err)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> Result<(), Error> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &Path, _link: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Returns the SHA-1 hash of a whole file, reading it in chunks.
+pub fn file_sha1(path: &Path) -> Result<[u8; 20], String> {
+    let err = |e: Error| format!("Error hashing {}: {e}", path.display());
+    let mut file = File::open(path).map_err(err)?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; 1 << 16];
+
+    loop {
+        let read = file.read(&mut buffer).map_err(err)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn links_point_inside_their_root() -> Result<(), String> {
+        let dir = utils::test_dir("file_attributes");
+        fs::create_dir_all(dir.join("bla/sub")).map_err(|e| e.to_string())?;
+        fs::write(dir.join("bla/target.sh"), b"#!/bin/sh\n").map_err(|e| e.to_string())?;
+
+        make_executable(&dir.join("bla/target.sh"))?;
+        create_symlink(&dir, "bla/sub/link.sh", "bla/target.sh")?;
+
+        let link = fs::read_link(dir.join("bla/sub/link.sh")).map_err(|e| e.to_string())?;
+        assert_eq!(Path::new("../../bla/target.sh"), link);
+        assert_eq!(
+            utils::sha1(b"#!/bin/sh\n")?,
+            file_sha1(&dir.join("bla/sub/link.sh"))?
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = fs::metadata(dir.join("bla/target.sh")).map_err(|e| e.to_string())?;
+            assert_ne!(0, metadata.permissions().mode() & 0o111);
+        }
+        fs::remove_dir_all(dir).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,4 @@
+pub mod allocation;
+pub mod disk_cache;
+pub mod file_attributes;
+pub mod piece_files;
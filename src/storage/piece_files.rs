@@ -0,0 +1,39 @@
+use std::{
+    fs::File,
+    io::{Error, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+/// Part of a piece that is stored in one of the files of its torrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRange {
+    pub path: PathBuf,
+    /// Offset of the range from the beginning of the file.
+    pub file_begin: u64,
+    /// Offset of the range from the beginning of the piece.
+    pub piece_begin: usize,
+    pub length: usize,
+}
+
+/// A piece that is read from the files of its torrent instead of from a copy in `.tmp`, as the pieces that a
+/// recheck verifies are. Bytes that no range covers belong to padding files, so they are zeros.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PieceInFiles {
+    pub size: usize,
+    pub ranges: Vec<FileRange>,
+}
+
+impl PieceInFiles {
+    pub fn read(&self) -> Result<Vec<u8>, String> {
+        let err = |e: Error| e.to_string();
+        let mut bytes = vec![0; self.size];
+
+        for range in &self.ranges {
+            let mut file = File::open(&range.path).map_err(err)?;
+            file.seek(SeekFrom::Start(range.file_begin)).map_err(err)?;
+            file.read_exact(&mut bytes[range.piece_begin..(range.piece_begin + range.length)])
+                .map_err(err)?;
+        }
+        Ok(bytes)
+    }
+}
@@ -10,6 +10,14 @@ pub fn run() -> Result<(), String> {
 
     let mut client = ClientSide::new(&config, logger.handle());
     let mut server = ServerSide::new(client.get_id(), &config, logger.handle());
+    server.set_disk_cache(client.disk_cache());
+    let (loaded_torrents, downloaded_torrents) = client.client_torrents();
+    server.set_client_torrents(loaded_torrents, downloaded_torrents);
+    server.set_rate_limits(client.rate_limits());
+    server.set_ban_list(client.ban_list());
+    server.set_ip_filter(client.ip_filter());
+    server.set_connected_peers(client.connected_peers());
+    server.set_connection_limit(client.connection_limit());
 
     let log_peer_id = format!(
         "Client Peer ID: {}",
@@ -0,0 +1,58 @@
+impl CacheStats {
+    pub fn new(
+        hits: u64,
+        misses: u64,
+        cached_bytes: usize,
+        pending_write_bytes: usize,
+        budget: usize,
+    ) -> CacheStats {
+        CacheStats {
+            hits,
+            misses,
+            cached_bytes,
+            pending_write_bytes,
+            budget,
+        }
+    }
+
+    pub fn get_hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn get_misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn get_cached_bytes(&self) -> usize {
+        self.cached_bytes
+    }
+
+    pub fn get_pending_write_bytes(&self) -> usize {
+        self.pending_write_bytes
+    }
+
+    pub fn get_budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn get_hit_ratio(&self) -> f64 {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            return 0.0;
+        }
+        (self.hits as f64) / (reads as f64)
+    }
+}
+impl Default for CacheStats {
+    fn default() -> Self {
+        Self::new(0, 0, 0, 0, 0)
+    }
+}
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    hits: u64,
+    misses: u64,
+    cached_bytes: usize,
+    pending_write_bytes: usize,
+    budget: usize,
+}
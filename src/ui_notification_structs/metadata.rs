@@ -8,6 +8,12 @@ impl Metadata {
             downloaded: 0,
             conections: 0,
             name: "".to_string(),
+            checked: 0,
+            comment: "".to_string(),
+            created_by: "".to_string(),
+            creation_date: None,
+            source: "".to_string(),
+            is_private: false,
         }
     }
 
@@ -66,6 +72,54 @@ impl Metadata {
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
+
+    pub fn set_checked(&mut self, _checked: u32) {
+        self.checked = _checked;
+    }
+
+    pub fn get_checked(&self) -> u32 {
+        self.checked
+    }
+
+    pub fn set_comment(&mut self, comment: String) {
+        self.comment = comment;
+    }
+
+    pub fn get_comment(&self) -> String {
+        self.comment.clone()
+    }
+
+    pub fn set_created_by(&mut self, created_by: String) {
+        self.created_by = created_by;
+    }
+
+    pub fn get_created_by(&self) -> String {
+        self.created_by.clone()
+    }
+
+    pub fn set_creation_date(&mut self, creation_date: Option<i64>) {
+        self.creation_date = creation_date;
+    }
+
+    pub fn get_creation_date(&self) -> Option<i64> {
+        self.creation_date
+    }
+
+    pub fn set_source(&mut self, source: String) {
+        self.source = source;
+    }
+
+    pub fn get_source(&self) -> String {
+        self.source.clone()
+    }
+
+    pub fn set_is_private(&mut self, is_private: bool) {
+        self.is_private = is_private;
+    }
+
+    pub fn get_is_private(&self) -> bool {
+        self.is_private
+    }
 }
 impl Default for Metadata {
     fn default() -> Self {
@@ -81,4 +135,10 @@ pub struct Metadata {
     downloaded: u32,
     conections: u32,
     name: String,
+    checked: u32,
+    comment: String,
+    created_by: String,
+    creation_date: Option<i64>,
+    source: String,
+    is_private: bool,
 }
@@ -1,4 +1,6 @@
+pub mod cache_stats;
 pub mod metadata;
 pub mod peer_state;
 pub mod torrent_state;
+pub mod transfer_rates;
 pub mod ui_notification;
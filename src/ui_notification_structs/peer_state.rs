@@ -10,6 +10,8 @@ impl PeerState {
             p_is_interested: true,
             c_is_chocked: false,
             c_is_interested: true,
+            is_optimistic: false,
+            is_snubbed: false,
             last_download_instant,
         }
     }
@@ -70,6 +72,24 @@ impl PeerState {
         self.c_is_interested
     }
 
+    /// Sets whether the peer holds the optimistic unchoke of the choker.
+    pub fn set_is_optimistic(&mut self, is_optimistic: bool) {
+        self.is_optimistic = is_optimistic;
+    }
+
+    pub fn get_is_optimistic(&self) -> bool {
+        self.is_optimistic
+    }
+
+    /// Sets whether the peer stopped sending the blocks that the client requested.
+    pub fn set_is_snubbed(&mut self, is_snubbed: bool) {
+        self.is_snubbed = is_snubbed;
+    }
+
+    pub fn get_is_snubbed(&self) -> bool {
+        self.is_snubbed
+    }
+
     pub fn set_last_download_instant(&mut self, _last_download_instant: Option<Instant>) {
         self.last_download_instant = _last_download_instant;
     }
@@ -102,5 +122,7 @@ pub struct PeerState {
     p_is_interested: bool,
     c_is_chocked: bool,
     c_is_interested: bool,
+    is_optimistic: bool,
+    is_snubbed: bool,
     last_download_instant: Option<Instant>,
 }
@@ -1,5 +1,6 @@
 use super::metadata::Metadata;
 use super::peer_state::PeerState;
+use super::transfer_rates::TransferRates;
 
 impl Default for TorrentState {
     fn default() -> Self {
@@ -12,9 +13,19 @@ impl TorrentState {
             peers: Vec::new(),
             metadata: Metadata::new(),
             total_peers,
+            transfer_rates: TransferRates::default(),
         }
     }
 
+    /// Sets the rates of the torrent, between all of its peers.
+    pub fn set_transfer_rates(&mut self, transfer_rates: TransferRates) {
+        self.transfer_rates = transfer_rates;
+    }
+
+    pub fn get_transfer_rates(&self) -> TransferRates {
+        self.transfer_rates.clone()
+    }
+
     pub fn add_peer_state(&mut self, state: PeerState) {
         self.peers.push(state);
     }
@@ -75,6 +86,54 @@ impl TorrentState {
         self.metadata.get_name()
     }
 
+    pub fn set_metadata_checked(&mut self, _checked: u32) {
+        self.metadata.set_checked(_checked);
+    }
+
+    pub fn get_metadata_checked(&self) -> u32 {
+        self.metadata.get_checked()
+    }
+
+    pub fn set_metadata_comment(&mut self, comment: String) {
+        self.metadata.set_comment(comment);
+    }
+
+    pub fn get_metadata_comment(&self) -> String {
+        self.metadata.get_comment()
+    }
+
+    pub fn set_metadata_created_by(&mut self, created_by: String) {
+        self.metadata.set_created_by(created_by);
+    }
+
+    pub fn get_metadata_created_by(&self) -> String {
+        self.metadata.get_created_by()
+    }
+
+    pub fn set_metadata_creation_date(&mut self, creation_date: Option<i64>) {
+        self.metadata.set_creation_date(creation_date);
+    }
+
+    pub fn get_metadata_creation_date(&self) -> Option<i64> {
+        self.metadata.get_creation_date()
+    }
+
+    pub fn set_metadata_source(&mut self, source: String) {
+        self.metadata.set_source(source);
+    }
+
+    pub fn get_metadata_source(&self) -> String {
+        self.metadata.get_source()
+    }
+
+    pub fn set_metadata_is_private(&mut self, is_private: bool) {
+        self.metadata.set_is_private(is_private);
+    }
+
+    pub fn get_metadata_is_private(&self) -> bool {
+        self.metadata.get_is_private()
+    }
+
     pub fn get_metadata(&self) -> Metadata {
         self.metadata.clone()
     }
@@ -90,6 +149,15 @@ impl TorrentState {
     pub fn get_completion_precentage(&self) -> f64 {
         ((self.get_metadata_downloaded() as f64) / (self.get_metadata_n_pieces() as f64)) * 100.0
     }
+
+    pub fn is_checking(&self) -> bool {
+        self.get_metadata_checked() > 0
+            && self.get_metadata_checked() < self.get_metadata_n_pieces()
+    }
+
+    pub fn get_checking_percentage(&self) -> f64 {
+        ((self.get_metadata_checked() as f64) / (self.get_metadata_n_pieces() as f64)) * 100.0
+    }
 }
 
 #[derive(Clone)]
@@ -97,4 +165,5 @@ pub struct TorrentState {
     peers: Vec<PeerState>,
     metadata: Metadata,
     total_peers: usize,
+    transfer_rates: TransferRates,
 }
@@ -0,0 +1,47 @@
+impl TransferRates {
+    pub fn new(
+        upload_rate: usize,
+        download_rate: usize,
+        upload_limit: usize,
+        download_limit: usize,
+    ) -> TransferRates {
+        TransferRates {
+            upload_rate,
+            download_rate,
+            upload_limit,
+            download_limit,
+        }
+    }
+
+    /// Bytes per second that were uploaded during the last second.
+    pub fn get_upload_rate(&self) -> usize {
+        self.upload_rate
+    }
+
+    /// Bytes per second that were downloaded during the last second.
+    pub fn get_download_rate(&self) -> usize {
+        self.download_rate
+    }
+
+    /// Upload limit in bytes per second, where zero means unlimited.
+    pub fn get_upload_limit(&self) -> usize {
+        self.upload_limit
+    }
+
+    /// Download limit in bytes per second, where zero means unlimited.
+    pub fn get_download_limit(&self) -> usize {
+        self.download_limit
+    }
+}
+impl Default for TransferRates {
+    fn default() -> Self {
+        Self::new(0, 0, 0, 0)
+    }
+}
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransferRates {
+    upload_rate: usize,
+    download_rate: usize,
+    upload_limit: usize,
+    download_limit: usize,
+}
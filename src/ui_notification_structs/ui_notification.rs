@@ -1,9 +1,11 @@
-use super::torrent_state::TorrentState;
+use super::{cache_stats::CacheStats, torrent_state::TorrentState, transfer_rates::TransferRates};
 
 impl UiNotification {
     pub fn new() -> UiNotification {
         UiNotification {
             torrents: Vec::new(),
+            cache_stats: CacheStats::default(),
+            transfer_rates: TransferRates::default(),
         }
     }
 
@@ -24,6 +26,23 @@ impl UiNotification {
     pub fn get_torrent_states(&self) -> Vec<TorrentState> {
         self.torrents.clone()
     }
+
+    pub fn set_cache_stats(&mut self, cache_stats: CacheStats) {
+        self.cache_stats = cache_stats;
+    }
+
+    pub fn get_cache_stats(&self) -> CacheStats {
+        self.cache_stats.clone()
+    }
+
+    /// Sets the rates of the whole client.
+    pub fn set_transfer_rates(&mut self, transfer_rates: TransferRates) {
+        self.transfer_rates = transfer_rates;
+    }
+
+    pub fn get_transfer_rates(&self) -> TransferRates {
+        self.transfer_rates.clone()
+    }
 }
 impl Default for UiNotification {
     fn default() -> Self {
@@ -32,4 +51,6 @@ impl Default for UiNotification {
 }
 pub struct UiNotification {
     torrents: Vec<TorrentState>,
+    cache_stats: CacheStats,
+    transfer_rates: TransferRates,
 }
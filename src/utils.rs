@@ -5,6 +5,7 @@ use std::{
 };
 
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
 /// Returns a 20-byte array containing the result of applying SHA1 hash algorithm to the given collection of bytes.
 pub fn sha1(bytes: impl AsRef<[u8]>) -> Result<[u8; 20], String> {
@@ -15,11 +16,21 @@ pub fn sha1(bytes: impl AsRef<[u8]>) -> Result<[u8; 20], String> {
         .map_err(|e: Infallible| e.to_string())
 }
 
+/// Returns a 32-byte array containing the result of applying SHA-256 hash algorithm to the given collection of bytes.
+pub fn sha256(bytes: impl AsRef<[u8]>) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
 /// Transforms a collection of bytes into a valid UTF-8 string.
 pub fn bytes_to_string(bytes: &[u8]) -> Result<String, String> {
     String::from_utf8(bytes.to_owned()).map_err(|e| e.to_string())
 }
 
+/// Writes bytes as lowercase hexadecimal digits, as info hashes are usually shown.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 pub fn round_up(base: usize, multiple: usize) -> usize {
     ((base + (multiple - 1)) / multiple) * multiple
 }
@@ -44,3 +55,13 @@ pub fn read_piece_file(download_path: String, piece_index: usize) -> Result<Vec<
 pub fn append_to_file(file: &mut File, bytes_to_append: Vec<u8>) -> Result<(), String> {
     file.write_all(&bytes_to_append).map_err(|e| e.to_string())
 }
+
+/// Returns an empty directory for the test called `name`. The process id is part of its name, so test runs
+/// that share the temp directory do not collide, and leftovers of a failed run are removed first.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("kernel_panics_{}_{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::create_dir_all(&dir);
+    dir
+}